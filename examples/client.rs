@@ -26,12 +26,12 @@ fn main() {
     // any messages is to register them where they are defined!
     shared::client_register_network_messages(&mut app);
 
-    app.add_startup_system(setup_ui.system());
+    app.add_startup_system(setup_ui);
 
-    app.add_system(handle_connect_button.system());
-    app.add_system(handle_message_button.system());
-    app.add_system(handle_incoming_messages.system());
-    app.add_system(handle_network_events.system());
+    app.add_system(handle_connect_button);
+    app.add_system(handle_message_button);
+    app.add_system(handle_incoming_messages);
+    app.add_system(handle_network_events);
     app.insert_resource(NetworkSettings::new((
         IpAddr::from_str("127.0.0.1").unwrap(),
         8080,
@@ -39,7 +39,7 @@ fn main() {
 
     app.init_resource::<GlobalChatSettings>();
 
-    app.add_system_to_stage(CoreStage::PostUpdate, handle_chat_area.system());
+    app.add_system_to_stage(CoreStage::PostUpdate, handle_chat_area);
 
     app.run();
 }
@@ -79,10 +79,26 @@ fn handle_network_events(
                 text.sections[0].value = String::from("Disconnect");
             }
 
-            ClientNetworkEvent::Disconnected => {
-                messages.add(SystemMessage::new("Disconnected from server!".to_string()));
+            ClientNetworkEvent::Disconnected(reason) => {
+                messages.add(SystemMessage::new(format!(
+                    "Disconnected from server: {:?}",
+                    reason
+                )));
                 text.sections[0].value = String::from("Connect to server");
             }
+            ClientNetworkEvent::Reconnecting { attempt } => {
+                messages.add(SystemMessage::new(format!(
+                    "Lost the server, reconnecting (attempt {})",
+                    attempt
+                )));
+            }
+            ClientNetworkEvent::ReconnectFailed => {
+                messages.add(SystemMessage::new(
+                    "Could not reconnect to the server".to_string(),
+                ));
+                text.sections[0].value = String::from("Connect to server");
+            }
+            ClientNetworkEvent::Transfer(_) => (),
             ClientNetworkEvent::Error(err) => {
                 messages.add(UserMessage::new(String::from("SYSTEM"), err.to_string()));
             }
@@ -278,7 +294,7 @@ fn handle_chat_area(
         .messages
         .iter()
         .flat_map(|msg| {
-            std::array::IntoIter::new([
+            [
                 TextSection {
                     value: format!("{}: ", msg.get_author()),
                     style: chat_settings.author_style.clone(),
@@ -287,7 +303,7 @@ fn handle_chat_area(
                     value: format!("{}\n", msg.get_text()),
                     style: chat_settings.chat_style.clone(),
                 },
-            ])
+            ]
         })
         .collect::<Vec<_>>();
 
@@ -16,7 +16,7 @@ fn main() {
         1.0 / 60.0,
     )));
     app.add_plugins(MinimalPlugins);
-    app.add_plugin(bevy::log::LogPlugin::default());
+    app.add_plugin(bevy::log::LogPlugin);
     app.insert_resource(bevy::tasks::TaskPoolBuilder::new().num_threads(2).build());
 
     // Before we can register the potential message types, we
@@ -72,17 +72,29 @@ fn handle_connection_events(
     mut commands: Commands,
     net: Res<NetworkServer<TcpServerProvider>>,
     mut network_events: EventReader<ServerNetworkEvent>,
+    players: Query<(Entity, &Player)>,
 ) {
     for event in network_events.iter() {
-        if let ServerNetworkEvent::Connected(conn_id) = event {
-            commands.spawn_bundle((Player(*conn_id),));
-
-            // Broadcasting sends the message to all connected players! (Including the just connected one in this case)
-            net.broadcast(shared::NewChatMessage {
-                name: String::from("SERVER"),
-                message: format!("New user connected; {}", conn_id),
-            });
-            info!("New player connected: {}", conn_id);
+        match event {
+            ServerNetworkEvent::Connected(conn_id) => {
+                commands.spawn_bundle((Player(*conn_id),));
+
+                // Broadcasting sends the message to all connected players! (Including the just connected one in this case)
+                net.broadcast(shared::NewChatMessage {
+                    name: String::from("SERVER"),
+                    message: format!("New user connected; {}", conn_id),
+                });
+                info!("New player connected: {}", conn_id);
+            }
+            ServerNetworkEvent::Disconnected(conn_id, reason) => {
+                for (entity, player) in players.iter() {
+                    if player.0 == *conn_id {
+                        commands.entity(entity).despawn();
+                    }
+                }
+                info!("Player disconnected: {} ({:?})", conn_id, reason);
+            }
+            _ => (),
         }
     }
 }
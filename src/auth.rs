@@ -0,0 +1,162 @@
+use std::{fmt::Debug, future::Future, sync::Arc, time::Duration};
+
+use async_io::Timer;
+use bytes::Bytes;
+use futures_lite::{
+    future::{self, Boxed},
+    FutureExt,
+};
+
+use crate::{
+    error::AuthenticationError, network_packet::PacketKind, Channel, ConnectionInfo, NetworkPacket,
+};
+
+type Validator = dyn Fn(Credentials, ConnectionInfo) -> Boxed<Result<String, String>> + Send + Sync;
+
+/// Insert this as a resource on the server to only let clients in once they proved who they are.
+///
+/// Clients present their [`Credentials`] right after connecting, and the validator decides
+/// whether they may stay. Until then neither the server nor the client emits `Connected`, and
+/// the server drops whatever else the client sends. The validator may take its time, like when it looks a token up
+/// in a database, other connections aren't held up by it.
+///
+/// It returns the identity of the client, like its user id, which is kept for as long as the
+/// client is connected, see [`NetworkServer::identity`](crate::NetworkServer::identity).
+/// Rejected clients are dropped instead with [`DisconnectReason::Rejected`](crate::DisconnectReason::Rejected),
+/// and both sides emit a [`NetworkError::Unauthorized`](crate::error::NetworkError::Unauthorized) for them.
+/// Clients that send a transfer or a compressed message before they are let in are dropped as well.
+///
+/// ## Example
+/// ```rust,no_run
+/// use bevy::prelude::*;
+/// use bevy_eventwork::Authentication;
+///
+/// /// Looks the user up, like in a database
+/// async fn find_user_by_token(token: &str) -> Option<u64> {
+///     (token == "secret").then_some(1)
+/// }
+///
+/// let mut app = App::new();
+/// app.insert_resource(Authentication::new(|credentials, _info| async move {
+///     let token = credentials.as_str().ok_or("The token is not valid UTF-8")?;
+///     match find_user_by_token(token).await {
+///         Some(user_id) => Ok(user_id.to_string()),
+///         None => Err("Unknown token".to_string()),
+///     }
+/// }));
+/// ```
+///
+/// ## Note
+/// Credentials are sent as they are, use a provider that encrypts the connection to keep them secret
+#[derive(Clone)]
+pub struct Authentication {
+    validator: Arc<Validator>,
+
+    /// How long the validator may take, clients it hasn't decided on by then are rejected
+    ///
+    /// ## Default
+    /// The default is set to 10 seconds
+    pub timeout: Duration,
+
+    /// How long a client may take to present its credentials, counted from when it connected.
+    /// Clients that haven't by then are rejected, so they can't keep a connection open without ever logging in.
+    ///
+    /// ## Default
+    /// The default is set to 10 seconds
+    pub admission_timeout: Duration,
+}
+
+impl Authentication {
+    /// Create a new instance of [`Authentication`]
+    ///
+    /// The validator returns the identity of the client, or why it was rejected.
+    pub fn new<F, Fut>(validator: F) -> Self
+    where
+        F: Fn(Credentials, ConnectionInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        Self {
+            validator: Arc::new(move |credentials, info| validator(credentials, info).boxed()),
+            timeout: Duration::from_secs(10),
+            admission_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Check the credentials a client presented, returning its identity
+    pub(crate) async fn validate(
+        &self,
+        credentials: Option<Credentials>,
+        info: ConnectionInfo,
+    ) -> Result<String, AuthenticationError> {
+        let credentials = credentials.ok_or(AuthenticationError::MissingCredentials)?;
+
+        let validated = future::or(
+            async { Some((self.validator)(credentials, info).await) },
+            async {
+                Timer::after(self.timeout).await;
+                None
+            },
+        )
+        .await;
+
+        match validated {
+            Some(result) => result.map_err(AuthenticationError::Rejected),
+            None => Err(AuthenticationError::TimedOut),
+        }
+    }
+}
+
+impl Debug for Authentication {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authentication")
+            .field("timeout", &self.timeout)
+            .field("admission_timeout", &self.admission_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Insert this as a resource on the client, to present it to servers that require [`Authentication`]
+///
+/// What they hold is up to you, like a token or a user name and password.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials(Bytes);
+
+impl Credentials {
+    /// Create a new instance of [`Credentials`]
+    pub fn new(credentials: impl Into<Bytes>) -> Self {
+        Self(credentials.into())
+    }
+
+    /// The credentials as they were sent
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The credentials as text, `None` if they aren't valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    /// The packet presenting the credentials to the server
+    pub(crate) fn announcement(&self) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Auth,
+            data: self.0.clone(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+}
+
+impl From<Bytes> for Credentials {
+    fn from(credentials: Bytes) -> Self {
+        Self(credentials)
+    }
+}
+
+// Keeps them out of logs
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Credentials [{} bytes]", self.0.len())
+    }
+}
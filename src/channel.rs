@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+#[cfg(any(feature = "udp", feature = "quic"))]
+use std::collections::HashMap;
+
+use crate::error::NetworkError;
+
+/// How the messages on a [`Channel`] get to the peer
+///
+/// Providers on top of a single stream, like tcp, deliver every message
+/// as [`Delivery::ReliableOrdered`], no matter what the channel asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Delivery {
+    /// Every message arrives, in the order it was sent
+    ReliableOrdered,
+    /// Every message arrives, but a lost one doesn't hold up the ones after it
+    ReliableUnordered,
+    /// Messages may get lost, or arrive out of order
+    Unreliable,
+    /// Messages may get lost, and are dropped when a newer one on the same channel arrived first
+    UnreliableSequenced,
+}
+
+impl Delivery {
+    /// How the delivery is put on the wire, when a peer announces its channels
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Delivery::ReliableOrdered => 0,
+            Delivery::ReliableUnordered => 1,
+            Delivery::Unreliable => 2,
+            Delivery::UnreliableSequenced => 3,
+        }
+    }
+
+    /// Read a delivery announced by a peer, see [`Delivery::to_byte`]
+    pub(crate) fn from_byte(byte: u8) -> Result<Self, NetworkError> {
+        match byte {
+            0 => Ok(Delivery::ReliableOrdered),
+            1 => Ok(Delivery::ReliableUnordered),
+            2 => Ok(Delivery::Unreliable),
+            3 => Ok(Delivery::UnreliableSequenced),
+            _ => Err(NetworkError::MalformedPacket("unknown delivery")),
+        }
+    }
+}
+
+/// A named lane that messages are sent on, see [`MessageOptions::channel`](crate::MessageOptions::channel)
+///
+/// ## Example
+/// ```rust
+/// use bevy_eventwork::{Channel, Delivery};
+///
+/// const POSITIONS: Channel = Channel::new("positions", Delivery::UnreliableSequenced);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Channel {
+    /// Only owned for channels a peer announced
+    name: Cow<'static, str>,
+    delivery: Delivery,
+}
+
+impl Channel {
+    /// The channel messages are sent on unless they ask for another one
+    pub const DEFAULT: Channel = Channel::new("default", Delivery::ReliableOrdered);
+
+    /// Create a new [`Channel`]
+    ///
+    /// Sequencing only looks at the name, so every channel should have its own.
+    pub const fn new(name: &'static str, delivery: Delivery) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            delivery,
+        }
+    }
+
+    /// A channel the peer announced, see [`MessageRegistry`](crate::registry::MessageRegistry)
+    pub(crate) fn announced(name: String, delivery: Delivery) -> Self {
+        Self {
+            name: Cow::Owned(name),
+            delivery,
+        }
+    }
+
+    /// The name of the channel
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// How messages on the channel are delivered
+    pub fn delivery(&self) -> Delivery {
+        self.delivery
+    }
+
+    /// A short id for the channel, to put on the wire instead of its name
+    pub fn id(&self) -> u32 {
+        // 32 bit FNV-1a, it has to be the same on both ends
+        self.name.bytes().fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Channel::DEFAULT
+    }
+}
+
+/// The latest sequence number per channel, for [`Delivery::UnreliableSequenced`]
+///
+/// The sending side numbers its packets with it, the receiving side drops stale ones.
+#[cfg(any(feature = "udp", feature = "quic"))]
+#[derive(Debug, Default)]
+pub(crate) struct Sequences {
+    latest: HashMap<u32, u32>,
+}
+
+#[cfg(any(feature = "udp", feature = "quic"))]
+impl Sequences {
+    /// Number the next packet sent on the channel with the given id
+    pub(crate) fn next(&mut self, channel: u32) -> u32 {
+        let latest = self.latest.entry(channel).or_insert(0);
+        *latest = latest.wrapping_add(1);
+        *latest
+    }
+
+    /// Whether a packet is newer than every one received on its channel before
+    pub(crate) fn accept(&mut self, channel: u32, seq: u32) -> bool {
+        match self.latest.get_mut(&channel) {
+            Some(latest) if (seq.wrapping_sub(*latest) as i32) <= 0 => false,
+            Some(latest) => {
+                *latest = seq;
+                true
+            }
+            None => {
+                self.latest.insert(channel, seq);
+                true
+            }
+        }
+    }
+}
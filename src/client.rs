@@ -1,17 +1,41 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use async_channel::{unbounded, Receiver, Sender};
+use async_channel::{bounded, unbounded, Receiver, Sender, TryRecvError};
+use async_io::Timer;
 use bevy::prelude::*;
-use dashmap::DashMap;
+use bytes::Bytes;
+use dashmap::{DashMap, DashSet};
+use futures_lite::future;
 
 use async_trait::async_trait;
 
+#[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+use crate::compression::{self, Compression};
 use crate::{
-    error::NetworkError,
-    network_message::{ClientMessage, ServerMessage},
+    auth::Credentials,
+    codec::{Codec, JsonCodec},
+    error::{AuthenticationError, HandshakeError, NetworkError},
+    handshake::Handshake,
+    heartbeat::{self, Heartbeat},
+    network_message::{ClientMessage, MessageOptions, RequestMessage, ServerMessage},
+    network_packet::PacketKind,
+    queue::{Outgoing, Priority, QueueError, SendQueue},
+    reconnect::{self, Reconnect},
+    registry::{MessageRegistry, PeerRegistry},
+    request::{self, PendingResponse},
     runtime::JoinHandle,
-    AsyncChannel, ClientNetworkEvent, Connection, ConnectionId, NetworkData, NetworkPacket,
-    Runtime,
+    send_until_closed, session,
+    transfer::{self, Reassembly, Transfer, TransferId, Transfers},
+    AsyncChannel, ClientNetworkEvent, Connection, ConnectionId, ConnectionInfo, DisconnectReason,
+    NetworkData, NetworkPacket, Runtime, CLOSE_TIMEOUT,
 };
 
 /// A trait used by [`NetworkClient`] to drive a client, this is responsible
@@ -39,11 +63,13 @@ pub trait NetworkClientProvider: 'static + Send + Sync {
     );
 
     /// Recieves messages from the server.
+    ///
+    /// Returns why it stopped receiving.
     async fn recv_loop(
         read_half: Self::ReadHalf,
         messages: Sender<NetworkPacket>,
         settings: Self::NetworkSettings,
-    );
+    ) -> DisconnectReason;
 
     /// Writes messages to the server.
     async fn send_loop(
@@ -55,20 +81,52 @@ pub trait NetworkClientProvider: 'static + Send + Sync {
     /// Split the socket into a read and write half, so that the two actions
     /// can be handled concurrently.
     fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf);
+
+    /// The address on the other side of the socket
+    ///
+    /// Returns `None` by default, for providers that don't connect over IP.
+    fn peer_addr(_socket: &Self::Socket) -> Option<SocketAddr> {
+        None
+    }
+
+    /// The address on our side of the socket
+    ///
+    /// Returns `None` by default, for providers that don't connect over IP.
+    fn local_addr(_socket: &Self::Socket) -> Option<SocketAddr> {
+        None
+    }
 }
 
 /// An instance of a [`NetworkClient`] is used to connect to a remote server
 /// using [`NetworkClient::connect`]
-pub struct NetworkClient<NCP: NetworkClientProvider> {
+///
+/// Messages are encoded with the [`Codec`] `C`, which has to match the one the server uses.
+pub struct NetworkClient<NCP: NetworkClientProvider, C: Codec = JsonCodec> {
     server_connection: Option<Connection>,
-    recv_message_map: Arc<DashMap<&'static str, Vec<String>>>,
+    pending_connection: Option<(Connection, Receiver<Result<(), Refusal>>)>,
+    disconnected: Option<AsyncChannel<DisconnectReason>>,
+    recv_message_map: Arc<DashMap<&'static str, Vec<Bytes>>>,
     network_events: AsyncChannel<ClientNetworkEvent>,
     connection_events: AsyncChannel<NCP::Socket>,
     connection_task: Option<Box<dyn JoinHandle>>,
+    connect_settings: Option<NCP::NetworkSettings>,
+    reconnect_attempt: Option<u32>,
+    reconnect_errors: AsyncChannel<ClientNetworkEvent>,
+    session_token: Option<Bytes>,
+    session_tokens: AsyncChannel<Bytes>,
+    message_options: DashMap<&'static str, MessageOptions>,
+    /// The requests we may send, checked by the [`Handshake`]
+    peer_messages: DashSet<&'static str>,
+    responses: AsyncChannel<Bytes>,
+    pending_requests: DashMap<u32, (Sender<Bytes>, Instant)>,
+    request_timeout: Duration,
+    next_request_id: AtomicU32,
+    next_transfer_id: AtomicU32,
     provider: PhantomData<NCP>,
+    codec: PhantomData<C>,
 }
 
-impl<NCP: NetworkClientProvider> std::fmt::Debug for NetworkClient<NCP> {
+impl<NCP: NetworkClientProvider, C: Codec> std::fmt::Debug for NetworkClient<NCP, C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(_conn) = self.server_connection.as_ref() {
             write!(f, "NetworkClient [Connected to server]")?;
@@ -80,15 +138,30 @@ impl<NCP: NetworkClientProvider> std::fmt::Debug for NetworkClient<NCP> {
     }
 }
 
-impl<NCP: NetworkClientProvider> NetworkClient<NCP> {
+impl<NCP: NetworkClientProvider, C: Codec> NetworkClient<NCP, C> {
     pub(crate) fn new(_provider: NCP) -> Self {
         Self {
             server_connection: None,
+            pending_connection: None,
+            disconnected: None,
             recv_message_map: Arc::new(DashMap::new()),
             network_events: AsyncChannel::new(),
             connection_events: AsyncChannel::new(),
             connection_task: None,
+            connect_settings: None,
+            reconnect_attempt: None,
+            reconnect_errors: AsyncChannel::new(),
+            session_token: None,
+            session_tokens: AsyncChannel::new(),
+            message_options: DashMap::new(),
+            peer_messages: DashSet::new(),
+            responses: AsyncChannel::new(),
+            pending_requests: DashMap::new(),
+            request_timeout: Duration::from_secs(10),
+            next_request_id: AtomicU32::new(0),
+            next_transfer_id: AtomicU32::new(0),
             provider: PhantomData,
+            codec: PhantomData,
         }
     }
 
@@ -96,10 +169,11 @@ impl<NCP: NetworkClientProvider> NetworkClient<NCP> {
     ///
     /// ## Note
     /// This will disconnect you first from any existing server connections
-    pub fn connect<'a, RT: Runtime>(&mut self, runtime: &RT, connect_info: &NCP::NetworkSettings) {
+    pub fn connect<RT: Runtime>(&mut self, runtime: &RT, connect_info: &NCP::NetworkSettings) {
         debug!("Starting connection");
 
         self.disconnect();
+        self.connect_settings = Some(connect_info.clone());
 
         let network_error_sender = self.network_events.sender.clone();
         let connection_event_sender = self.connection_events.sender.clone();
@@ -111,50 +185,221 @@ impl<NCP: NetworkClientProvider> NetworkClient<NCP> {
         ))));
     }
 
-    /// a server
+    /// Disconnect from a server
+    ///
+    /// The server is told the client left, see [`DisconnectReason::Left`]
+    ///
+    /// This also stops connecting, or [`Reconnect`]ing, to a server.
     ///
     /// This operation is idempotent and simply does nothing when you are
     /// not connected to anything
     pub fn disconnect(&mut self) {
+        if let Some(mut task) = self.connection_task.take() {
+            task.abort();
+        }
+        self.reconnect_attempt = None;
+        while self.reconnect_errors.receiver.try_recv().is_ok() {}
+
+        // Never counted as connected, so there is nothing to report
+        if let Some((conn, _admission)) = self.pending_connection.take() {
+            conn.close_with(&DisconnectReason::Left);
+        }
+        self.disconnected = None;
+        self.pending_requests.clear();
+        while self.responses.receiver.try_recv().is_ok() {}
+
         if let Some(conn) = self.server_connection.take() {
-            conn.stop();
+            conn.close_with(&DisconnectReason::Left);
+
+            // The server forgets the session of clients that left
+            self.session_token = None;
+            while self.session_tokens.receiver.try_recv().is_ok() {}
 
             let _ = self
                 .network_events
                 .sender
-                .send(ClientNetworkEvent::Disconnected);
+                .try_send(ClientNetworkEvent::Disconnected(DisconnectReason::Left));
         }
     }
 
     /// Send a message to the connected server, returns `Err(NetworkError::NotConnected)` if
     /// the connection hasn't been established yet
     pub fn send_message<T: ServerMessage>(&self, message: T) -> Result<(), NetworkError> {
+        self.send_message_with_priority(message, Priority::Normal)
+    }
+
+    /// Send a message to the connected server, ahead of or behind the others waiting for it
+    pub fn send_message_with_priority<T: ServerMessage>(
+        &self,
+        message: T,
+        priority: Priority,
+    ) -> Result<(), NetworkError> {
         debug!("Sending message to server");
+        let data = C::encode(&message)?.into();
+        self.send_data(T::NAME, priority, data)
+    }
+
+    /// Send a request to the connected server, see [`NetworkClient::request_with_timeout`]
+    ///
+    /// The response has to arrive within the timeout set with [`NetworkClient::set_request_timeout`].
+    pub fn request<T: RequestMessage>(
+        &self,
+        message: T,
+    ) -> Result<PendingResponse<T::Response>, NetworkError> {
+        self.request_with_timeout(message, self.request_timeout)
+    }
+
+    /// Set how long [`NetworkClient::request`] waits for responses
+    ///
+    /// ## Default
+    /// The default is set to 10 seconds
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    /// Send a request to the connected server, and get a handle to its response
+    ///
+    /// The server answers it with [`NetworkRequest::respond`](crate::NetworkRequest::respond).
+    /// If that doesn't happen within `timeout` the response fails with
+    /// [`NetworkError::RequestTimedOut`]. Returns `Err(NetworkError::NotConnected)` if
+    /// the connection hasn't been established yet.
+    ///
+    /// ## Note
+    /// The response type has to be registered with
+    /// [`AppNetworkClientMessage::listen_for_client_response`]
+    pub fn request_with_timeout<T: RequestMessage>(
+        &self,
+        message: T,
+        timeout: Duration,
+    ) -> Result<PendingResponse<T::Response>, NetworkError> {
+        debug!("Sending request to server");
+        let data = C::encode(&message)?;
+
+        let id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let deadline = Instant::now() + timeout;
+        let (response_sender, response) = bounded(1);
+        self.pending_requests
+            .insert(id, (response_sender, deadline));
+
+        if let Err(err) = self.send_data(T::NAME, Priority::Normal, request::tag(id, &data)) {
+            self.pending_requests.remove(&id);
+            return Err(err);
+        }
+
+        Ok(PendingResponse::new(
+            response,
+            deadline,
+            C::decode::<T::Response>,
+        ))
+    }
+
+    /// Send a message to the connected server in chunks, for payloads too large to be sent at once
+    ///
+    /// The chunks only go out when nothing else is waiting, so other messages aren't held up
+    /// behind them. Both sides report the progress with [`ClientNetworkEvent::Transfer`] and
+    /// [`ServerNetworkEvent::Transfer`](crate::ServerNetworkEvent::Transfer), the server receives
+    /// the message as usual once it is complete. See [`Transfers`] for the settings.
+    ///
+    /// ## Note
+    /// Transfers are always delivered reliably and in order, whatever channel the message is on
+    pub fn send_transfer<T: ServerMessage>(&self, message: T) -> Result<TransferId, NetworkError> {
+        debug!("Sending transfer to server");
+        let data = C::encode(&message)?.into();
+
         let server_connection = match self.server_connection.as_ref() {
             Some(server) => server,
             None => return Err(NetworkError::NotConnected),
         };
 
-        let packet = NetworkPacket {
-            kind: String::from(T::NAME),
-            data: serde_json::to_string(&message).unwrap(),
+        let packet = server_connection.packet(T::NAME, data);
+        let id = self.next_transfer_id.fetch_add(1, Ordering::Relaxed);
+        let transfer = Transfer::new(id, ConnectionId::server(), packet);
+        let id = transfer.id;
+
+        match server_connection.transfers.try_send(transfer) {
+            Ok(()) => Ok(id),
+            Err(_) => {
+                error!("Server disconnected: the connection is closed");
+                Err(NetworkError::NotConnected)
+            }
+        }
+    }
+
+    fn send_data(
+        &self,
+        name: &'static str,
+        priority: Priority,
+        data: Bytes,
+    ) -> Result<(), NetworkError> {
+        let server_connection = match self.server_connection.as_ref() {
+            Some(server) => server,
+            None => return Err(NetworkError::NotConnected),
         };
 
-        match server_connection.send_message.try_send(packet) {
-            Ok(_) => (),
-            Err(err) => {
-                error!("Server disconnected: {}", err);
-                return Err(NetworkError::NotConnected);
+        let packet = server_connection.packet(name, data);
+
+        match server_connection.outgoing.send(priority, packet) {
+            Ok(()) => Ok(()),
+            Err(QueueError::Closed) => {
+                error!("Server disconnected: the connection is closed");
+                Err(NetworkError::NotConnected)
+            }
+            Err(QueueError::Full) => Err(NetworkError::QueueFull(ConnectionId::server())),
+            Err(QueueError::Disconnect) => {
+                warn!("Disconnecting, the send queue is full");
+                if let Some(disconnected) = self.disconnected.as_ref() {
+                    let _ = disconnected.sender.try_send(DisconnectReason::QueueFull);
+                }
+                Err(NetworkError::QueueFull(ConnectionId::server()))
             }
         }
+    }
 
-        Ok(())
+    /// Start the next attempt to get back to the server, or give up once there are no attempts left
+    fn reconnect<RT: Runtime>(
+        &mut self,
+        runtime: &RT,
+        reconnect: Option<&Reconnect>,
+        events: &mut EventWriter<ClientNetworkEvent>,
+    ) {
+        let next = reconnect.and_then(|reconnect| {
+            let attempt = reconnect.next_attempt(self.reconnect_attempt)?;
+            Some((reconnect, attempt))
+        });
+        let (reconnect, attempt, settings) = match (next, self.connect_settings.clone()) {
+            (Some((reconnect, attempt)), Some(settings)) => (reconnect, attempt, settings),
+            _ => {
+                self.reconnect_attempt = None;
+                events.send(ClientNetworkEvent::ReconnectFailed);
+                return;
+            }
+        };
+
+        let delay = reconnect.delay(attempt);
+        debug!("Reconnecting in {:?}", delay);
+
+        let connection_event_sender = self.connection_events.sender.clone();
+        let reconnect_error_sender = self.reconnect_errors.sender.clone();
+        self.connection_task = Some(Box::new(runtime.spawn(async move {
+            Timer::after(delay).await;
+            NCP::connect_task(settings, connection_event_sender, reconnect_error_sender).await;
+        })));
+        self.reconnect_attempt = Some(attempt);
+        events.send(ClientNetworkEvent::Reconnecting { attempt });
+    }
+
+    /// Where the server is and since when the client is connected to it, `None` if it isn't
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.server_connection
+            .as_ref()
+            .map(|connection| connection.info)
     }
 
     /// Returns true if the client has an established connection
     ///
     /// # Note
-    /// This may return true even if the connection has already been broken on the server side.
+    /// This may return true even if the connection has already been broken on the server side,
+    /// insert a [`Heartbeat`] resource to notice that in time.
     pub fn is_connected(&self) -> bool {
         self.server_connection.is_some()
     }
@@ -171,31 +416,162 @@ pub trait AppNetworkClientMessage {
     /// - Internal bookkeeping
     fn listen_for_client_message<T: ClientMessage, NCP: NetworkClientProvider>(
         &mut self,
+    ) -> &mut Self {
+        self.listen_for_client_message_with_codec::<T, NCP, JsonCodec>()
+    }
+
+    /// Register a client message type for a client that uses the [`Codec`] `C`
+    ///
+    /// ## Details
+    /// See [`AppNetworkClientMessage::listen_for_client_message`]
+    fn listen_for_client_message_with_codec<
+        T: ClientMessage,
+        NCP: NetworkClientProvider,
+        C: Codec,
+    >(
+        &mut self,
+    ) -> &mut Self {
+        self.listen_for_client_message_with_options::<T, NCP, C>(MessageOptions::default())
+    }
+
+    /// Register a client message type, and choose how the server sends it
+    ///
+    /// ## Details
+    /// See [`AppNetworkClientMessage::listen_for_client_message`] and [`MessageOptions`]
+    fn listen_for_client_message_with_options<
+        T: ClientMessage,
+        NCP: NetworkClientProvider,
+        C: Codec,
+    >(
+        &mut self,
+        options: MessageOptions,
+    ) -> &mut Self;
+
+    /// Register the response type of a request, to send it with [`NetworkClient::request`]
+    ///
+    /// ## Details
+    /// Responses are handed to the [`PendingResponse`] of their request by its id, no event type is
+    /// added. Several requests may share a response type, and it may be a client message as well.
+    fn listen_for_client_response<T: RequestMessage, NCP: NetworkClientProvider>(
+        &mut self,
+    ) -> &mut Self {
+        self.listen_for_client_response_with_codec::<T, NCP, JsonCodec>()
+    }
+
+    /// Register the response type of a request for a client that uses the [`Codec`] `C`
+    ///
+    /// ## Details
+    /// See [`AppNetworkClientMessage::listen_for_client_response`]
+    fn listen_for_client_response_with_codec<
+        T: RequestMessage,
+        NCP: NetworkClientProvider,
+        C: Codec,
+    >(
+        &mut self,
+    ) -> &mut Self {
+        self.listen_for_client_response_with_options::<T, NCP, C>(MessageOptions::default())
+    }
+
+    /// Register the response type of a request, and choose how the server sends it
+    ///
+    /// ## Details
+    /// See [`AppNetworkClientMessage::listen_for_client_response`] and [`MessageOptions`].
+    /// A response type shared by several requests, or listened for as a client message
+    /// as well, has to be registered with the same options every time.
+    fn listen_for_client_response_with_options<
+        T: RequestMessage,
+        NCP: NetworkClientProvider,
+        C: Codec,
+    >(
+        &mut self,
+        options: MessageOptions,
     ) -> &mut Self;
 }
 
 impl AppNetworkClientMessage for App {
-    fn listen_for_client_message<T: ClientMessage, NCP: NetworkClientProvider>(
+    fn listen_for_client_message_with_options<
+        T: ClientMessage,
+        NCP: NetworkClientProvider,
+        C: Codec,
+    >(
         &mut self,
+        options: MessageOptions,
     ) -> &mut Self {
-        let client = self.world.get_resource::<NetworkClient<NCP>>().expect("Could not find `NetworkClient`. Be sure to include the `ClientPlugin` before listening for client messages.");
+        let client = self.world.get_resource::<NetworkClient<NCP, C>>().expect("Could not find `NetworkClient`. Be sure to include the `ClientPlugin` before listening for client messages.");
 
         debug!("Registered a new ClientMessage: {}", T::NAME);
+        register_name(client, T::NAME);
+        register_options(client, T::NAME, options);
+
+        self.add_event::<NetworkData<T>>();
+        self.add_system_to_stage(CoreStage::PreUpdate, register_client_message::<T, NCP, C>)
+    }
+
+    fn listen_for_client_response_with_options<
+        T: RequestMessage,
+        NCP: NetworkClientProvider,
+        C: Codec,
+    >(
+        &mut self,
+        options: MessageOptions,
+    ) -> &mut Self {
+        let client = self.world.get_resource::<NetworkClient<NCP, C>>().expect("Could not find `NetworkClient`. Be sure to include the `ClientPlugin` before listening for client responses.");
 
         assert!(
-            !client.recv_message_map.contains_key(T::NAME),
-            "Duplicate registration of ClientMessage: {}",
-            T::NAME
+            NetworkPacket::is_valid_name(T::Response::NAME),
+            "ClientMessage name is too long: {}",
+            T::Response::NAME
         );
-        client.recv_message_map.insert(T::NAME, Vec::new());
+        // Responses are routed by their request id, the name only tells the server how to send them
+        debug!("Registered a new response: {}", T::Response::NAME);
+        register_options(client, T::Response::NAME, options);
+        client.peer_messages.insert(T::NAME);
 
-        self.add_event::<NetworkData<T>>();
-        self.add_system_to_stage(CoreStage::PreUpdate, register_client_message::<T, NCP>)
+        self
     }
 }
 
-fn register_client_message<T, NCP: NetworkClientProvider>(
-    net_res: ResMut<NetworkClient<NCP>>,
+fn register_name<NCP: NetworkClientProvider, C: Codec>(
+    client: &NetworkClient<NCP, C>,
+    name: &'static str,
+) {
+    assert!(
+        NetworkPacket::is_valid_name(name),
+        "ClientMessage name is too long: {}",
+        name
+    );
+    assert!(
+        !client.recv_message_map.contains_key(name),
+        "Duplicate registration of ClientMessage: {}",
+        name
+    );
+    client.recv_message_map.insert(name, Vec::new());
+}
+
+/// Remember how the server should send the message called `name`, to announce it
+fn register_options<NCP: NetworkClientProvider, C: Codec>(
+    client: &NetworkClient<NCP, C>,
+    name: &'static str,
+    options: MessageOptions,
+) {
+    assert!(
+        NetworkPacket::is_valid_name(options.channel.name()),
+        "Channel name is too long: {}",
+        options.channel.name()
+    );
+    let registered = client
+        .message_options
+        .entry(name)
+        .or_insert(options.clone());
+    assert!(
+        *registered == options,
+        "ClientMessage registered with different options: {}",
+        name
+    );
+}
+
+fn register_client_message<T, NCP: NetworkClientProvider, C: Codec>(
+    net_res: ResMut<NetworkClient<NCP, C>>,
     mut events: EventWriter<NetworkData<T>>,
 ) where
     T: ClientMessage,
@@ -208,18 +584,129 @@ fn register_client_message<T, NCP: NetworkClientProvider>(
     events.send_batch(
         messages
             .drain(..)
-            .filter_map(|msg| serde_json::from_str(&msg).ok())
+            .filter_map(|msg| match C::decode(&msg) {
+                Ok(msg) => Some(msg),
+                Err(err) => {
+                    warn!("Could not decode {} from the server: {}", T::NAME, err);
+                    None
+                }
+            })
             .map(|msg| NetworkData::<T>::new(ConnectionId::server(), msg)),
     );
 }
 
 /// Pushes messages into the network event queue.
-pub fn handle_connection_event<NCP: NetworkClientProvider, RT: Runtime>(
-    mut net_res: ResMut<NetworkClient<NCP>>,
+#[allow(clippy::too_many_arguments)]
+pub fn handle_connection_event<NCP: NetworkClientProvider, RT: Runtime, C: Codec>(
+    mut net_res: ResMut<NetworkClient<NCP, C>>,
     mut events: EventWriter<ClientNetworkEvent>,
     runtime: Res<RT>,
     network_settings: Res<NCP::NetworkSettings>,
+    handshake: Option<Res<Handshake>>,
+    heartbeat: Option<Res<Heartbeat>>,
+    reconnect: Option<Res<Reconnect>>,
+    send_queue: Option<Res<SendQueue>>,
+    transfers: Option<Res<Transfers>>,
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))] compression: Option<
+        Res<Compression>,
+    >,
+    credentials: Option<Res<Credentials>>,
 ) {
+    while let Ok(token) = net_res.session_tokens.receiver.try_recv() {
+        net_res.session_token = Some(token);
+    }
+
+    while let Ok(response) = net_res.responses.receiver.try_recv() {
+        match request::untag(response) {
+            Ok((id, response)) => {
+                // Responses to requests that timed out, or were answered already, are dropped
+                if let Some((_, (sender, _))) = net_res.pending_requests.remove(&id) {
+                    let _ = sender.try_send(response);
+                }
+            }
+            Err(err) => warn!("Could not read a response from the server: {}", err),
+        }
+    }
+
+    // Nobody waits for the responses of requests that timed out anymore
+    let now = Instant::now();
+    net_res
+        .pending_requests
+        .retain(|_, (_, deadline)| *deadline > now);
+
+    let admission = net_res
+        .pending_connection
+        .as_ref()
+        .and_then(|(_, admission)| match admission.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Closed) => Some(Err(Refusal::Handshake(HandshakeError::Closed))),
+        });
+    if let Some(result) = admission {
+        if let Some((connection, _)) = net_res.pending_connection.take() {
+            match result {
+                Ok(()) => {
+                    net_res.reconnect_attempt = None;
+                    net_res.server_connection = Some(connection);
+                    events.send(ClientNetworkEvent::Connected);
+                }
+                Err(Refusal::Handshake(err)) => {
+                    connection.close_with(&DisconnectReason::Left);
+                    events.send(ClientNetworkEvent::Error(NetworkError::Handshake(
+                        ConnectionId::server(),
+                        err,
+                    )));
+                }
+                Err(Refusal::Dropped(DisconnectReason::Rejected(why))) => {
+                    net_res.disconnected = None;
+                    connection.close();
+                    events.send(ClientNetworkEvent::Error(NetworkError::Unauthorized(
+                        ConnectionId::server(),
+                        AuthenticationError::Rejected(why),
+                    )));
+                }
+                Err(Refusal::Dropped(reason)) => {
+                    net_res.disconnected = None;
+                    connection.close();
+                    let retried = reconnect.is_some() && reconnect::is_retried(&reason);
+                    events.send(ClientNetworkEvent::Disconnected(reason));
+
+                    if retried {
+                        net_res.reconnect(&*runtime, reconnect.as_deref(), &mut events);
+                    }
+                }
+            }
+        }
+    }
+
+    let disconnect_reason = net_res
+        .disconnected
+        .as_ref()
+        .and_then(|disconnected| disconnected.receiver.try_recv().ok());
+    if let Some(reason) = disconnect_reason {
+        net_res.disconnected = None;
+        if let Some(connection) = net_res.server_connection.take() {
+            // Everything else has been announced to the server already
+            if reason == DisconnectReason::QueueFull {
+                connection.close_with(&reason);
+            } else {
+                connection.close();
+            }
+            let retried = reconnect.is_some() && reconnect::is_retried(&reason);
+            events.send(ClientNetworkEvent::Disconnected(reason));
+
+            if retried {
+                net_res.reconnect(&*runtime, reconnect.as_deref(), &mut events);
+            }
+        }
+    }
+
+    // Failed attempts are reported like any other connection error
+    while let Ok(event) = net_res.reconnect_errors.receiver.try_recv() {
+        events.send(event);
+        net_res.reconnect(&*runtime, reconnect.as_deref(), &mut events);
+    }
+
     let connection = match net_res.connection_events.receiver.try_recv() {
         Ok(event) => event,
         Err(_err) => {
@@ -227,36 +714,208 @@ pub fn handle_connection_event<NCP: NetworkClientProvider, RT: Runtime>(
         }
     };
 
+    let info = ConnectionInfo {
+        peer_addr: NCP::peer_addr(&connection),
+        local_addr: NCP::local_addr(&connection),
+        connected_at: SystemTime::now(),
+    };
     let (read_half, write_half) = NCP::split(connection);
     let recv_message_map = net_res.recv_message_map.clone();
-    let (outgoing_tx, outgoing_rx) = unbounded();
+    let (outgoing, queued) = Outgoing::new(send_queue.as_deref());
+    let (transfer_tx, transfer_rx) = unbounded();
+    let (closed_tx, closed_rx) = bounded(1);
+    let (chunk_tx, chunk_rx) = bounded(1);
+    let (outgoing_rx, prioritized) = queued.relay(chunk_rx);
     let (incoming_tx, incoming_rx) = unbounded();
-    let network_event_sender = net_res.network_events.sender.clone();
+    let (outgoing_rx, heartbeats) = heartbeat::keep_alive(outgoing_rx, heartbeat.as_deref());
+    let map_heartbeat = heartbeat.as_deref().cloned();
+    let disconnected = AsyncChannel::new();
+    let disconnected_tx = disconnected.sender.clone();
+    let session_tokens = net_res.session_tokens.sender.clone();
+    let responses = net_res.responses.sender.clone();
+    let (recv_reason_tx, recv_reason_rx) = bounded(1);
+    let map_outgoing_tx = outgoing.sender(Priority::Low);
+    let transfers = transfers.as_deref().cloned().unwrap_or_default();
+    let transfer_progress = net_res.network_events.sender.clone();
+    let chunks = transfer::chunk_loop(
+        transfer_rx,
+        chunk_tx,
+        transfers.chunk_size,
+        move |_, progress| {
+            let _ = transfer_progress.try_send(ClientNetworkEvent::Transfer(progress));
+        },
+    );
+    let map_transfer_progress = net_res.network_events.sender.clone();
+    let mut reassembly = Reassembly::new(transfers.max_length);
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+    let map_compression = compression.as_deref().cloned();
+
+    let registry = MessageRegistry::new(
+        net_res
+            .message_options
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone())),
+    );
+    let peer_registry = PeerRegistry::default();
+    let map_peer_registry = peer_registry.clone();
+
+    let mut pending_handshake = handshake.as_ref().map(|handshake| {
+        handshake.expect(net_res.peer_messages.iter().map(|name| *name).collect())
+    });
+    let (admission_tx, admission_rx) = unbounded();
+    let mut pending_admission = true;
+
+    // Goes out before anything else, so the server can check us and switch to ids right away
+    if let Some(token) = net_res.session_token.clone() {
+        if outgoing
+            .send_control(session::resume_request(token))
+            .is_err()
+        {
+            error!("Could not ask the server to resume the session");
+        }
+    }
+    if let Some(handshake) = handshake.as_ref() {
+        if outgoing.send_control(handshake.announcement()).is_err() {
+            error!("Could not send the handshake to the server");
+        }
+    }
+    if let Some(credentials) = credentials.as_ref() {
+        if outgoing.send_control(credentials.announcement()).is_err() {
+            error!("Could not present the credentials to the server");
+        }
+    }
+    if outgoing.send_control(registry.announcement()).is_err() {
+        error!("Could not announce the message registry to the server");
+    }
     let read_network_settings = network_settings.clone();
     let write_network_settings = network_settings.clone();
 
-    net_res.server_connection = Some(Connection {
+    let connection = Connection {
         send_task: Box::new(runtime.spawn(async move {
             trace!("Starting send task");
-            NCP::send_loop(write_half, outgoing_rx, write_network_settings).await;
+            let send = future::zip(
+                NCP::send_loop(write_half, outgoing_rx, write_network_settings),
+                future::zip(prioritized, future::zip(heartbeats, chunks)),
+            );
+            send_until_closed(send, closed_rx, CLOSE_TIMEOUT).await;
         })),
+        closed: closed_tx,
         receive_task: Box::new(runtime.spawn(async move {
             trace!("Starting listen task");
-            NCP::recv_loop(read_half, incoming_tx, read_network_settings).await;
-
-            match network_event_sender
-                .send(ClientNetworkEvent::Disconnected)
-                .await
-            {
-                Ok(_) => (),
-                Err(_) => {
-                    error!("Could not send disconnected event, because channel is disconnected");
-                }
-            }
+            let reason = NCP::recv_loop(read_half, incoming_tx, read_network_settings).await;
+            let _ = recv_reason_tx.send(reason).await;
         })),
         map_receive_task: Box::new(runtime.spawn(async move {
-            while let Ok(packet) = incoming_rx.recv().await {
-                match recv_message_map.get_mut(&packet.kind[..]) {
+            // Whether the server still needs to be told why the connection ends
+            let (reason, tell_peer) = loop {
+                let packet = match heartbeat::recv(&incoming_rx, map_heartbeat.as_ref()).await {
+                    Ok(Some(packet)) => packet,
+                    // Nothing is left behind if the connection was dropped on our side
+                    Ok(None) => break (recv_reason_rx.recv().await.ok(), true),
+                    Err(reason) => break (Some(reason), true),
+                };
+
+                // Transfers are handled like any other message once all their chunks arrived
+                let packet = match packet.kind {
+                    PacketKind::Chunk => match reassembly.receive(packet.data) {
+                        Ok((progress, transferred)) => {
+                            let _ = map_transfer_progress
+                                .try_send(ClientNetworkEvent::Transfer(progress));
+                            match transferred {
+                                Some(packet) => packet,
+                                None => continue,
+                            }
+                        }
+                        Err(reason) => break (Some(reason), true),
+                    },
+                    _ => packet,
+                };
+                #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+                let packet = match packet.compressed {
+                    true => match compression::decompress(packet.data, map_compression.as_ref()) {
+                        Ok(data) => NetworkPacket {
+                            data,
+                            compressed: false,
+                            ..packet
+                        },
+                        Err(reason) => break (Some(reason), true),
+                    },
+                    false => packet,
+                };
+                // Without an algorithm we never asked for anything to be compressed
+                #[cfg(not(any(feature = "zstd", feature = "lz4", feature = "deflate")))]
+                let packet = match packet.compressed {
+                    true => {
+                        error!("The server sent a compressed message, but no algorithm is enabled");
+                        break (Some(DisconnectReason::DecodeError), true);
+                    }
+                    false => packet,
+                };
+
+                match packet.kind {
+                    PacketKind::Handshake => {
+                        if let Some(Err(err)) = pending_handshake
+                            .as_mut()
+                            .map(|pending| pending.receive(&packet.data))
+                        {
+                            error!("Could not read the handshake of the server: {}", err);
+                        }
+                        continue;
+                    }
+                    PacketKind::Registry => {
+                        if let Err(err) = map_peer_registry.update(&packet.data) {
+                            error!("Could not read the message registry of the server: {}", err);
+                        }
+
+                        // Checked right away, the server may not let us in otherwise
+                        if let Some(Err(err)) = pending_handshake
+                            .take()
+                            .map(|pending| pending.complete(&map_peer_registry))
+                        {
+                            let _ = admission_tx.send(Err(Refusal::Handshake(err))).await;
+                            return;
+                        }
+                        continue;
+                    }
+                    PacketKind::Admitted => {
+                        if pending_admission {
+                            pending_admission = false;
+                            let result = pending_handshake
+                                .take()
+                                .map_or(Ok(()), |pending| pending.complete(&map_peer_registry))
+                                .map_err(Refusal::Handshake);
+                            let accepted = result.is_ok();
+                            if admission_tx.send(result).await.is_err() || !accepted {
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    PacketKind::Heartbeat => continue,
+                    PacketKind::Session => {
+                        let _ = session_tokens.send(packet.data).await;
+                        continue;
+                    }
+                    PacketKind::Response => {
+                        let _ = responses.send(packet.data).await;
+                        continue;
+                    }
+                    PacketKind::Disconnect => {
+                        match DisconnectReason::from_announcement(&packet.data) {
+                            Ok(reason) => break (Some(reason), false),
+                            Err(err) => {
+                                error!("Could not read why the server disconnected: {}", err);
+                                break (Some(DisconnectReason::Eof), false);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+
+                match registry
+                    .name_of(&packet.kind)
+                    .and_then(|name| recv_message_map.get_mut(name))
+                {
                     Some(mut packets) => packets.push(packet.data),
                     None => {
                         error!(
@@ -265,17 +924,47 @@ pub fn handle_connection_event<NCP: NetworkClientProvider, RT: Runtime>(
                         );
                     }
                 }
+            };
+
+            let reason = match reason {
+                Some(reason) => reason,
+                None => return,
+            };
+            if tell_peer && reason != DisconnectReason::Eof {
+                let _ = map_outgoing_tx.try_send(reason.announcement());
+            }
+
+            if pending_admission {
+                let _ = admission_tx.send(Err(Refusal::Dropped(reason))).await;
+            } else {
+                info!("Disconnected from the server: {:?}", reason);
+                let _ = disconnected_tx.send(reason).await;
             }
         })),
-        send_message: outgoing_tx,
-    });
+        outgoing,
+        transfers: transfer_tx,
+        peer_registry,
+        #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+        compression: compression.as_deref().cloned(),
+        info,
+    };
+
+    net_res.disconnected = Some(disconnected);
+    net_res.pending_connection = Some((connection, admission_rx));
+}
 
-    events.send(ClientNetworkEvent::Connected);
+/// Why the client didn't get in
+#[derive(Debug)]
+enum Refusal {
+    /// The server failed our [`Handshake`]
+    Handshake(HandshakeError),
+    /// The connection ended before the server let us in
+    Dropped(DisconnectReason),
 }
 
 /// Takes events and forwards them to the server.
-pub fn send_client_network_events<NCP: NetworkClientProvider, RT: Runtime>(
-    client_server: ResMut<NetworkClient<NCP>>,
+pub fn send_client_network_events<NCP: NetworkClientProvider, RT: Runtime, C: Codec>(
+    client_server: ResMut<NetworkClient<NCP, C>>,
     mut client_network_events: EventWriter<ClientNetworkEvent>,
 ) {
     client_network_events.send_batch(
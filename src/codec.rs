@@ -0,0 +1,168 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::NetworkError;
+
+/// Turns messages into the bytes that are sent over the wire, and back.
+///
+/// A [`NetworkServer`](crate::NetworkServer) and [`NetworkClient`](crate::NetworkClient)
+/// are generic over their codec, both sides of a connection need to use the same one.
+/// [`JsonCodec`] is used unless a different one is picked in the
+/// [`ServerPlugin`](crate::ServerPlugin) or [`ClientPlugin`](crate::ClientPlugin).
+pub trait Codec: 'static + Send + Sync {
+    /// Serialize a message into its wire representation
+    fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, NetworkError>;
+
+    /// Deserialize a message from its wire representation
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, NetworkError>;
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+/// Encodes messages as JSON, this is the default codec.
+///
+/// Easy to inspect, but by far the largest and slowest of the included codecs.
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, NetworkError> {
+        serde_json::to_vec(message).map_err(|err| NetworkError::Serialization(Box::new(err)))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, NetworkError> {
+        serde_json::from_slice(bytes).map_err(|err| NetworkError::Deserialization(Box::new(err)))
+    }
+}
+
+#[cfg(feature = "bincode")]
+#[derive(Default, Copy, Clone, Debug)]
+/// Encodes messages with [`bincode`].
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, NetworkError> {
+        bincode::serialize(message).map_err(|err| NetworkError::Serialization(Box::new(err)))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, NetworkError> {
+        bincode::deserialize(bytes).map_err(|err| NetworkError::Deserialization(Box::new(err)))
+    }
+}
+
+#[cfg(feature = "postcard")]
+#[derive(Default, Copy, Clone, Debug)]
+/// Encodes messages with [`postcard`], the most compact of the included codecs.
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl Codec for PostcardCodec {
+    fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, NetworkError> {
+        postcard::to_allocvec(message).map_err(|err| NetworkError::Serialization(Box::new(err)))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, NetworkError> {
+        postcard::from_bytes(bytes).map_err(|err| NetworkError::Deserialization(Box::new(err)))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+#[derive(Default, Copy, Clone, Debug)]
+/// Encodes messages as MessagePack.
+///
+/// ## Note
+/// Structs are written as arrays, so fields are matched by position and not by name.
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePackCodec {
+    fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, NetworkError> {
+        rmp_serde::to_vec(message).map_err(|err| NetworkError::Serialization(Box::new(err)))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, NetworkError> {
+        rmp_serde::from_slice(bytes).map_err(|err| NetworkError::Deserialization(Box::new(err)))
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Default, Copy, Clone, Debug)]
+/// Encodes messages as CBOR.
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, NetworkError> {
+        let mut encoded = Vec::new();
+        ciborium::into_writer(message, &mut encoded)
+            .map_err(|err| NetworkError::Serialization(Box::new(err)))?;
+        Ok(encoded)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, NetworkError> {
+        ciborium::from_reader(bytes).map_err(|err| NetworkError::Deserialization(Box::new(err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Message {
+        id: u32,
+        name: String,
+        position: (f32, f32),
+        tags: Vec<String>,
+        parent: Option<u64>,
+    }
+
+    /// Encodes a message and decodes it again, then checks garbage is refused
+    fn round_trip<C: Codec>() {
+        let message = Message {
+            id: 7,
+            name: String::from("player"),
+            position: (1.5, -2.0),
+            tags: vec![String::from("a"), String::from("b")],
+            parent: None,
+        };
+
+        let encoded = C::encode(&message).expect("message is serializable");
+        let decoded: Message = C::decode(&encoded).expect("message round trips");
+        assert_eq!(decoded, message);
+
+        assert!(matches!(
+            C::decode::<Message>(&[0xff; 3]),
+            Err(NetworkError::Deserialization(_))
+        ));
+    }
+
+    #[test]
+    fn json_round_trips() {
+        round_trip::<JsonCodec>();
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trips() {
+        round_trip::<BincodeCodec>();
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn postcard_round_trips() {
+        round_trip::<PostcardCodec>();
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_round_trips() {
+        round_trip::<MessagePackCodec>();
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_round_trips() {
+        round_trip::<CborCodec>();
+    }
+}
@@ -0,0 +1,283 @@
+use bevy::log::error;
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::DisconnectReason;
+
+#[cfg(feature = "zstd")]
+const ZSTD: u8 = 1;
+#[cfg(feature = "lz4")]
+const LZ4: u8 = 2;
+#[cfg(feature = "deflate")]
+const DEFLATE: u8 = 3;
+
+/// The algorithm and the uncompressed length, in front of the compressed data
+const HEADER_LEN: usize = 1 + 4;
+
+/// How large a message may get once decompressed, without a [`Compression`] resource
+const DEFAULT_MAX_LENGTH: usize = 256 * 1024 * 1024;
+
+/// Insert this as a resource, on the server or the client, to compress large messages before they are sent.
+///
+/// Only messages the receiving side registered with [`MessageOptions::compress`](crate::MessageOptions::compress)
+/// are compressed, and only when they are larger than the [`Compression::threshold`].
+///
+/// Only available with one of the `zstd`, `lz4` or `deflate` features enabled.
+///
+/// ## Note
+/// The receiving side doesn't need this resource, but it has to have the feature of the algorithm enabled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    /// How messages are compressed
+    pub algorithm: CompressionAlgorithm,
+
+    /// How many bytes a message needs to have to be compressed, smaller ones aren't worth it
+    ///
+    /// ## Default
+    /// The default is set to 1KiB
+    pub threshold: usize,
+
+    /// How large a received message may get once decompressed, larger ones drop the connection
+    /// with [`DisconnectReason::Oversize`]
+    ///
+    /// ## Default
+    /// The default is set to 256MiB
+    pub max_length: usize,
+}
+
+impl Compression {
+    /// Create a new instance of [`Compression`]
+    pub fn new(algorithm: CompressionAlgorithm) -> Self {
+        Self {
+            algorithm,
+            threshold: 1024,
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+
+    /// Compress the data of a message, if that makes it any smaller
+    ///
+    /// Returns whether it was compressed.
+    pub(crate) fn compress(&self, data: Bytes) -> (Bytes, bool) {
+        if data.len() < self.threshold || data.len() > u32::MAX as usize {
+            return (data, false);
+        }
+
+        let body = match self.algorithm.compress(&data) {
+            Ok(body) if HEADER_LEN + body.len() < data.len() => body,
+            Ok(_) => return (data, false),
+            Err(err) => {
+                error!("Could not compress message: {}", err);
+                return (data, false);
+            }
+        };
+
+        let mut compressed = Vec::with_capacity(HEADER_LEN + body.len());
+        compressed.put_u8(self.algorithm.id());
+        compressed.put_u32_le(data.len() as u32);
+        compressed.put_slice(&body);
+        (compressed.into(), true)
+    }
+}
+
+/// The algorithms messages can be compressed with, each one behind a feature of the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    /// Compresses well and fast, with [`zstd`]
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// The fastest, but compresses the least, with [`lz4_flex`]
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Widely supported, but slower than the others, with [`flate2`]
+    #[cfg(feature = "deflate")]
+    Deflate,
+}
+
+impl CompressionAlgorithm {
+    fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => ZSTD,
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4 => LZ4,
+            #[cfg(feature = "deflate")]
+            CompressionAlgorithm::Deflate => DEFLATE,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            #[cfg(feature = "zstd")]
+            ZSTD => Some(CompressionAlgorithm::Zstd),
+            #[cfg(feature = "lz4")]
+            LZ4 => Some(CompressionAlgorithm::Lz4),
+            #[cfg(feature = "deflate")]
+            DEFLATE => Some(CompressionAlgorithm::Deflate),
+            _ => None,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => {
+                zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)
+            }
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4 => Ok(lz4_flex::block::compress(data)),
+            #[cfg(feature = "deflate")]
+            CompressionAlgorithm::Deflate => {
+                use std::io::Write;
+
+                let level = flate2::Compression::default();
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn decompress(self, data: &[u8], length: usize) -> std::io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => zstd::bulk::decompress(data, length),
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4 => lz4_flex::block::decompress(data, length)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
+            #[cfg(feature = "deflate")]
+            CompressionAlgorithm::Deflate => {
+                use std::io::Read;
+
+                let mut decompressed = Vec::with_capacity(length);
+                flate2::read::DeflateDecoder::new(data)
+                    .take(length as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+/// Decompress the data of a message that was sent compressed
+pub(crate) fn decompress(
+    mut data: Bytes,
+    compression: Option<&Compression>,
+) -> Result<Bytes, DisconnectReason> {
+    let max_length = compression.map_or(DEFAULT_MAX_LENGTH, |compression| compression.max_length);
+
+    if data.remaining() < HEADER_LEN {
+        error!("Received a compressed message whose header is cut off");
+        return Err(DisconnectReason::DecodeError);
+    }
+    let algorithm = data.get_u8();
+    let length = data.get_u32_le() as usize;
+
+    let algorithm = match CompressionAlgorithm::from_id(algorithm) {
+        Some(algorithm) => algorithm,
+        None => {
+            error!(
+                "Received a message compressed with an unknown algorithm: {}",
+                algorithm
+            );
+            return Err(DisconnectReason::DecodeError);
+        }
+    };
+    if length > max_length {
+        error!(
+            "Received too large compressed message: {} > {}",
+            length, max_length
+        );
+        return Err(DisconnectReason::Oversize);
+    }
+
+    match algorithm.decompress(&data, length) {
+        Ok(decompressed) if decompressed.len() == length => Ok(decompressed.into()),
+        Ok(_) => {
+            error!("Received a compressed message of the wrong length");
+            Err(DisconnectReason::DecodeError)
+        }
+        Err(err) => {
+            error!("Could not decompress message: {}", err);
+            Err(DisconnectReason::DecodeError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every algorithm that is enabled
+    fn algorithms() -> Vec<CompressionAlgorithm> {
+        vec![
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd,
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4,
+            #[cfg(feature = "deflate")]
+            CompressionAlgorithm::Deflate,
+        ]
+    }
+
+    fn snapshot() -> Bytes {
+        r#"{"entities":[{"x":1,"y":2},{"x":1,"y":2}]}"#.repeat(100).into()
+    }
+
+    #[test]
+    fn messages_survive_compression() {
+        for algorithm in algorithms() {
+            let compression = Compression::new(algorithm);
+            let (compressed, is_compressed) = compression.compress(snapshot());
+            assert!(is_compressed, "{:?} should compress", algorithm);
+            assert!(compressed.len() < snapshot().len());
+
+            let decompressed = decompress(compressed, Some(&compression));
+            assert_eq!(decompressed.ok(), Some(snapshot()), "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn only_compresses_when_it_pays_off() {
+        for algorithm in algorithms() {
+            let compression = Compression::new(algorithm);
+
+            let small = Bytes::from_static(b"too small to bother");
+            assert_eq!(compression.compress(small.clone()), (small, false));
+
+            let noise: Bytes = (0..4096)
+                .map(|_| fastrand::u8(..))
+                .collect::<Vec<_>>()
+                .into();
+            assert_eq!(compression.compress(noise.clone()), (noise, false));
+        }
+    }
+
+    #[test]
+    fn refuses_to_decompress_too_much() {
+        for algorithm in algorithms() {
+            let compression = Compression::new(algorithm);
+            let (compressed, _) = compression.compress(snapshot());
+
+            let limited = Compression {
+                max_length: snapshot().len() - 1,
+                ..compression
+            };
+            assert_eq!(
+                decompress(compressed, Some(&limited)),
+                Err(DisconnectReason::Oversize)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_algorithms() {
+        let data = Bytes::from_static(&[0xff, 4, 0, 0, 0, 1, 2, 3, 4]);
+        assert_eq!(decompress(data, None), Err(DisconnectReason::DecodeError));
+
+        let cut_off = Bytes::from_static(&[0xff, 4]);
+        assert_eq!(
+            decompress(cut_off, None),
+            Err(DisconnectReason::DecodeError)
+        );
+    }
+}
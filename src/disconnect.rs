@@ -0,0 +1,92 @@
+use bytes::{BufMut, Bytes};
+
+use crate::{error::NetworkError, network_packet::PacketKind, Channel, NetworkPacket};
+
+const EOF: u8 = 0;
+const TIMED_OUT: u8 = 1;
+const OVERSIZE: u8 = 2;
+const DECODE_ERROR: u8 = 3;
+const KICKED: u8 = 4;
+const SHUTDOWN: u8 = 5;
+const LEFT: u8 = 6;
+const QUEUE_FULL: u8 = 7;
+const REJECTED: u8 = 8;
+
+/// Why a connection ended
+///
+/// Whenever possible the side ending a connection tells the other one why, so both
+/// sides' `Disconnected` events carry the same reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The connection was closed, or broke, without a reason being given
+    Eof,
+    /// Nothing was received for longer than the [`Heartbeat::timeout`](crate::Heartbeat::timeout)
+    TimedOut,
+    /// A packet was larger than the provider's maximum packet length
+    Oversize,
+    /// A packet did not follow the wire format
+    DecodeError,
+    /// The server dropped the client, see [`NetworkServer::kick`](crate::NetworkServer::kick)
+    Kicked(String),
+    /// The server stopped, see [`NetworkServer::stop`](crate::NetworkServer::stop)
+    Shutdown,
+    /// The client left, see [`NetworkClient::disconnect`](crate::NetworkClient::disconnect)
+    Left,
+    /// Too many packets were waiting to be sent to the peer, see [`QueuePolicy::Disconnect`](crate::QueuePolicy::Disconnect)
+    QueueFull,
+    /// The server didn't let the client in, see [`Authentication`](crate::Authentication)
+    Rejected(String),
+}
+
+impl DisconnectReason {
+    /// The final packet telling the peer why the connection ends
+    pub(crate) fn announcement(&self) -> NetworkPacket {
+        let mut data = Vec::with_capacity(1);
+        match self {
+            DisconnectReason::Eof => data.put_u8(EOF),
+            DisconnectReason::TimedOut => data.put_u8(TIMED_OUT),
+            DisconnectReason::Oversize => data.put_u8(OVERSIZE),
+            DisconnectReason::DecodeError => data.put_u8(DECODE_ERROR),
+            DisconnectReason::Kicked(message) => {
+                data.put_u8(KICKED);
+                data.put_slice(message.as_bytes());
+            }
+            DisconnectReason::Shutdown => data.put_u8(SHUTDOWN),
+            DisconnectReason::Left => data.put_u8(LEFT),
+            DisconnectReason::QueueFull => data.put_u8(QUEUE_FULL),
+            DisconnectReason::Rejected(message) => {
+                data.put_u8(REJECTED);
+                data.put_slice(message.as_bytes());
+            }
+        }
+
+        NetworkPacket {
+            kind: PacketKind::Disconnect,
+            data: Bytes::from(data),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Read the peer's [`DisconnectReason::announcement`]
+    pub(crate) fn from_announcement(announcement: &[u8]) -> Result<Self, NetworkError> {
+        let reason = match announcement.first() {
+            Some(&EOF) => DisconnectReason::Eof,
+            Some(&TIMED_OUT) => DisconnectReason::TimedOut,
+            Some(&OVERSIZE) => DisconnectReason::Oversize,
+            Some(&DECODE_ERROR) => DisconnectReason::DecodeError,
+            Some(&KICKED) => {
+                DisconnectReason::Kicked(String::from_utf8_lossy(&announcement[1..]).into_owned())
+            }
+            Some(&SHUTDOWN) => DisconnectReason::Shutdown,
+            Some(&LEFT) => DisconnectReason::Left,
+            Some(&QUEUE_FULL) => DisconnectReason::QueueFull,
+            Some(&REJECTED) => {
+                DisconnectReason::Rejected(String::from_utf8_lossy(&announcement[1..]).into_owned())
+            }
+            _ => return Err(NetworkError::MalformedPacket("unknown disconnect reason")),
+        };
+
+        Ok(reason)
+    }
+}
@@ -1,5 +1,47 @@
 use crate::ConnectionId;
 
+/// Why a [`Handshake`](crate::Handshake) failed
+#[derive(thiserror::Error, Debug)]
+pub enum HandshakeError {
+    /// The peer sent its registry without a handshake, so it isn't checking connections.
+    #[error("The peer did not send a handshake")]
+    Missing,
+
+    /// The peer uses a different version of the protocol.
+    #[error("Protocol version {remote} does not match ours ({local}), please update")]
+    VersionMismatch {
+        /// Our protocol version
+        local: u32,
+        /// The peer's protocol version
+        remote: u32,
+    },
+
+    /// The peer doesn't listen for messages we expect it to.
+    #[error("The peer does not listen for: {0:?}")]
+    MissingMessages(Vec<String>),
+
+    /// The connection closed before the handshake was done.
+    #[error("The connection closed during the handshake")]
+    Closed,
+}
+
+/// Why a client's [`Credentials`](crate::Credentials) were not accepted by the [`Authentication`](crate::Authentication)
+#[derive(thiserror::Error, Debug)]
+pub enum AuthenticationError {
+    /// The client didn't present any credentials.
+    #[error("No credentials were presented")]
+    MissingCredentials,
+
+    /// The validator rejected the credentials, telling why.
+    #[error("The credentials were rejected: {0}")]
+    Rejected(String),
+
+    /// The validator didn't decide within [`Authentication::timeout`](crate::Authentication::timeout),
+    /// or the client wasn't let in within [`Authentication::admission_timeout`](crate::Authentication::admission_timeout).
+    #[error("The credentials could not be checked in time")]
+    TimedOut,
+}
+
 /// Internal errors used by Spicy
 #[derive(thiserror::Error, Debug)]
 pub enum NetworkError {
@@ -26,4 +68,34 @@ pub enum NetworkError {
     /// An error occured when trying to connect.
     #[error("An error occured when trying to connect: {0}")]
     Connection(std::io::Error),
+
+    /// A message could not be serialized by the codec.
+    #[error("Could not serialize message: {0}")]
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+
+    /// The [`Handshake`](crate::Handshake) with a new connection failed, it was dropped.
+    #[error("Handshake with {0} failed: {1}")]
+    Handshake(ConnectionId, HandshakeError),
+
+    /// A client was not let in by the [`Authentication`](crate::Authentication), it was dropped.
+    ///
+    /// Clients get this too, when the server didn't let them in.
+    #[error("Authentication of {0} failed: {1}")]
+    Unauthorized(ConnectionId, AuthenticationError),
+
+    /// A packet did not follow the wire format.
+    #[error("Received a malformed packet: {0}")]
+    MalformedPacket(&'static str),
+
+    /// A message could not be deserialized by the codec.
+    #[error("Could not deserialize message: {0}")]
+    Deserialization(Box<dyn std::error::Error + Send + Sync>),
+
+    /// The server did not respond to a request in time.
+    #[error("The server did not respond to the request in time")]
+    RequestTimedOut,
+
+    /// Too many packets are waiting to be sent on the connection, see [`SendQueue`](crate::SendQueue).
+    #[error("The send queue of {0} is full")]
+    QueueFull(ConnectionId),
 }
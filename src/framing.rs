@@ -0,0 +1,291 @@
+use std::{
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    async_channel::{Receiver, Sender},
+    DisconnectReason, NetworkPacket,
+};
+use async_io::Timer;
+use bevy::log::{debug, error, info, trace};
+use futures_lite::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Write several packets at once, instead of one by one, see the `batching` of the `NetworkSettings`.
+///
+/// Every packet that is waiting to be sent is collected, which usually is everything sent
+/// during the same frame. The peer reads them one by one as usual, it needs no setup.
+#[derive(Clone, Debug)]
+pub struct Batching {
+    /// How many bytes are collected at most, before they are written
+    ///
+    /// ## Default
+    /// The default is set to 64KiB
+    pub max_size: usize,
+
+    /// How long to wait for more packets to arrive, after the first one
+    ///
+    /// ## Default
+    /// The default is set to zero, only packets that are already waiting are collected
+    pub max_delay: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self {
+            max_size: 64 * 1024,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// Reads packets from the stream and forwards them to eventwork until the stream closes.
+pub(crate) async fn recv_loop<R: AsyncRead + Unpin>(
+    mut read_half: R,
+    messages: Sender<NetworkPacket>,
+    max_packet_length: usize,
+) -> DisconnectReason {
+    loop {
+        trace!("Reading message length");
+        let mut length = [0; 8];
+        let length = match read_half.read_exact(&mut length).await {
+            Ok(()) => u64::from_le_bytes(length) as usize,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                // EOF, meaning the stream has closed.
+                info!("Peer disconnected");
+                return DisconnectReason::Eof;
+            }
+            Err(err) => {
+                error!("Encountered error while fetching length: {}", err);
+                return DisconnectReason::Eof;
+            }
+        };
+        debug!("Receiving new message of size: {}", length);
+
+        if length > max_packet_length {
+            error!(
+                "Received too large packet: {} > {}",
+                length, max_packet_length
+            );
+            return DisconnectReason::Oversize;
+        }
+
+        trace!("Reading message into buffer");
+        let mut buffer = vec![0; length];
+        match read_half.read_exact(&mut buffer).await {
+            Ok(()) => (),
+            Err(err) => {
+                error!(
+                    "Encountered error while fetching stream of length {}: {}",
+                    length, err
+                );
+                return DisconnectReason::Eof;
+            }
+        }
+        trace!("Message read");
+
+        let packet = match NetworkPacket::decode(buffer) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to decode network packet from: {}", err);
+                return DisconnectReason::DecodeError;
+            }
+        };
+
+        if messages.send(packet).await.is_err() {
+            error!("Failed to send decoded message to eventwork");
+            return DisconnectReason::Eof;
+        }
+        trace!("Message deserialized and sent to eventwork");
+    }
+}
+
+/// Writes every packet eventwork hands over to the stream, collecting them first if there is a [`Batching`].
+pub(crate) async fn send_loop<W: AsyncWrite + Unpin>(
+    mut write_half: W,
+    messages: Receiver<NetworkPacket>,
+    batching: Option<&Batching>,
+) {
+    let mut buffer = Vec::new();
+
+    while let Ok(message) = messages.recv().await {
+        buffer.clear();
+        let mut count = 1;
+        frame(&message, &mut buffer);
+
+        if let Some(batching) = batching {
+            let deadline = Instant::now() + batching.max_delay;
+            while buffer.len() < batching.max_size {
+                match next(&messages, deadline).await {
+                    Some(message) => frame(&message, &mut buffer),
+                    None => break,
+                }
+                count += 1;
+            }
+        }
+
+        debug!("Sending {} bytes in {} packets", buffer.len(), count);
+        match write_half.write_all(&buffer).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not send {} packets: {}", count, err);
+                break;
+            }
+        }
+
+        trace!("Succesfully written all!");
+    }
+
+    // Lets the peer know nothing else is coming
+    let _ = write_half.close().await;
+}
+
+/// Append the packet to `buffer`, prefixed with its length
+fn frame(message: &NetworkPacket, buffer: &mut Vec<u8>) {
+    let encoded = message.encode();
+    buffer.extend_from_slice(&(encoded.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(&encoded);
+}
+
+/// The next packet that is already waiting, or arrives before the deadline
+async fn next(messages: &Receiver<NetworkPacket>, deadline: Instant) -> Option<NetworkPacket> {
+    if let Ok(message) = messages.try_recv() {
+        return Some(message);
+    }
+
+    // Packets are handed over one at a time, this lets the next one catch up
+    future::yield_now().await;
+    if let Ok(message) = messages.try_recv() {
+        return Some(message);
+    }
+
+    future::or(async { messages.recv().await.ok() }, async {
+        Timer::at(deadline).await;
+        None
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use async_channel::unbounded;
+    use futures_lite::io::Cursor;
+
+    use super::*;
+    use crate::{network_packet::PacketKind, Channel};
+
+    /// Remembers every single write, to tell how the packets were batched
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl AsyncWrite for Writes {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.0.push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn packet(id: u16) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Id(id),
+            data: vec![id as u8; 100].into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Send `count` packets that are all waiting at once, and return every write
+    fn send(count: u16, batching: Option<&Batching>) -> Vec<Vec<u8>> {
+        let (messages_tx, messages_rx) = unbounded();
+        for id in 0..count {
+            messages_tx.try_send(packet(id)).expect("channel is open");
+        }
+        messages_tx.close();
+
+        let mut writes = Writes::default();
+        future::block_on(send_loop(&mut writes, messages_rx, batching));
+        writes.0
+    }
+
+    /// Read back everything that was written
+    fn receive(writes: Vec<Vec<u8>>) -> (Vec<NetworkPacket>, DisconnectReason) {
+        let (messages_tx, messages_rx) = unbounded();
+        let stream = Cursor::new(writes.concat());
+        let reason = future::block_on(recv_loop(stream, messages_tx, usize::MAX));
+
+        let mut packets = Vec::new();
+        while let Ok(packet) = messages_rx.try_recv() {
+            packets.push(packet);
+        }
+        (packets, reason)
+    }
+
+    fn assert_in_order(packets: &[NetworkPacket], count: u16) {
+        assert_eq!(packets.len(), count as usize);
+        for (id, received) in (0..count).zip(packets) {
+            assert_eq!(received.kind, PacketKind::Id(id));
+            assert_eq!(received.data, packet(id).data);
+        }
+    }
+
+    #[test]
+    fn waiting_packets_are_written_at_once() {
+        let writes = send(50, Some(&Batching::default()));
+        assert_eq!(writes.len(), 1);
+
+        let (packets, reason) = receive(writes);
+        assert_in_order(&packets, 50);
+        assert_eq!(reason, DisconnectReason::Eof);
+    }
+
+    #[test]
+    fn batches_stop_at_their_size() {
+        let batching = Batching {
+            max_size: 1000,
+            ..Default::default()
+        };
+        let writes = send(50, Some(&batching));
+        assert!(writes.len() > 1);
+        // A batch only goes over the limit by the packet that filled it up
+        let framed = packet(0).encode().len() + 8;
+        assert!(writes.iter().all(|write| write.len() < 1000 + framed));
+
+        let (packets, _) = receive(writes);
+        assert_in_order(&packets, 50);
+    }
+
+    #[test]
+    fn packets_are_written_one_by_one_without_batching() {
+        let writes = send(50, None);
+        assert_eq!(writes.len(), 50);
+
+        let (packets, _) = receive(writes);
+        assert_in_order(&packets, 50);
+    }
+
+    #[test]
+    fn oversized_packets_drop_the_connection() {
+        let (messages_tx, _messages_rx) = unbounded();
+        let stream = Cursor::new(send(1, None).concat());
+        let reason = future::block_on(recv_loop(stream, messages_tx, 10));
+        assert_eq!(reason, DisconnectReason::Oversize);
+    }
+}
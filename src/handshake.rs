@@ -0,0 +1,192 @@
+use bytes::{Buf, BufMut};
+
+use crate::{
+    error::{HandshakeError, NetworkError},
+    network_packet::PacketKind,
+    registry::PeerRegistry,
+    Channel, NetworkPacket,
+};
+
+/// Insert this as a resource on both the server and the client to check each new
+/// connection before it counts as connected.
+///
+/// Both sides exchange their protocol version next to the registry of the messages they listen for.
+/// Connections with a different version are dropped with a [`NetworkError::Handshake`] instead of
+/// emitting `Connected`, and so are peers missing the other half of a request: a client that
+/// listens for the response of a request needs a server that listens for the request, and a
+/// server that answers a request needs clients that listen for the response.
+///
+/// Which other messages a side sends isn't known up front, bump the version whenever they change.
+///
+/// ## Example
+/// ```rust,no_run
+/// use bevy::prelude::*;
+/// use bevy_eventwork::Handshake;
+///
+/// // Shared between the server and the client
+/// const PROTOCOL_VERSION: u32 = 3;
+///
+/// let mut app = App::new();
+/// app.insert_resource(Handshake::new(PROTOCOL_VERSION));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Handshake {
+    /// The version of your protocol, only peers with the same version are accepted
+    pub protocol_version: u32,
+}
+
+impl Handshake {
+    /// Create a new instance of [`Handshake`]
+    pub fn new(protocol_version: u32) -> Self {
+        Self { protocol_version }
+    }
+
+    /// The packet telling the peer our version
+    pub(crate) fn announcement(&self) -> NetworkPacket {
+        let mut data = Vec::with_capacity(4);
+        data.put_u32_le(self.protocol_version);
+
+        NetworkPacket {
+            kind: PacketKind::Handshake,
+            data: data.into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Start checking a new connection, whose peer has to listen for `peer_messages`
+    pub(crate) fn expect(&self, peer_messages: Vec<&'static str>) -> PendingHandshake {
+        PendingHandshake::new(self.protocol_version, peer_messages)
+    }
+}
+
+/// A handshake that is waiting for the peer's side.
+///
+/// The peer's [`Handshake::announcement`] comes right before its registry, so
+/// the handshake can be completed as soon as the registry has arrived.
+#[derive(Debug)]
+pub(crate) struct PendingHandshake {
+    protocol_version: u32,
+    peer_messages: Vec<&'static str>,
+    peer_version: Option<u32>,
+}
+
+impl PendingHandshake {
+    fn new(protocol_version: u32, peer_messages: Vec<&'static str>) -> Self {
+        Self {
+            protocol_version,
+            peer_messages,
+            peer_version: None,
+        }
+    }
+
+    /// Read the peer's [`Handshake::announcement`]
+    pub(crate) fn receive(&mut self, mut announcement: &[u8]) -> Result<(), NetworkError> {
+        if announcement.remaining() < 4 {
+            return Err(NetworkError::MalformedPacket("handshake is cut off"));
+        }
+        self.peer_version = Some(announcement.get_u32_le());
+        Ok(())
+    }
+
+    /// Check the peer, once its registry is known
+    pub(crate) fn complete(self, peer_registry: &PeerRegistry) -> Result<(), HandshakeError> {
+        let remote = self.peer_version.ok_or(HandshakeError::Missing)?;
+        if remote != self.protocol_version {
+            return Err(HandshakeError::VersionMismatch {
+                local: self.protocol_version,
+                remote,
+            });
+        }
+
+        let missing: Vec<_> = self
+            .peer_messages
+            .into_iter()
+            .filter(|name| !peer_registry.contains(name))
+            .map(String::from)
+            .collect();
+        if !missing.is_empty() {
+            return Err(HandshakeError::MissingMessages(missing));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{registry::MessageRegistry, MessageOptions};
+
+    /// What the peer announces when it listens for `names`
+    fn peer_registry(names: &[&'static str]) -> PeerRegistry {
+        let registry =
+            MessageRegistry::new(names.iter().map(|name| (*name, MessageOptions::default())));
+        let peer = PeerRegistry::default();
+        peer.update(&registry.announcement().data)
+            .expect("announcement should be read");
+        peer
+    }
+
+    fn check(
+        local: Handshake,
+        remote: Handshake,
+        peer_messages: Vec<&'static str>,
+        peer: &[&'static str],
+    ) -> Result<(), HandshakeError> {
+        let mut pending = local.expect(peer_messages);
+        pending
+            .receive(&remote.announcement().data)
+            .expect("announcement should be read");
+        pending.complete(&peer_registry(peer))
+    }
+
+    #[test]
+    fn accepts_peers_with_the_same_version_and_messages() {
+        let result = check(
+            Handshake::new(3),
+            Handshake::new(3),
+            vec!["a:Answer"],
+            &["a:Answer", "a:Other"],
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn rejects_peers_with_a_different_version() {
+        let result = check(Handshake::new(3), Handshake::new(2), vec![], &[]);
+
+        assert!(matches!(
+            result,
+            Err(HandshakeError::VersionMismatch {
+                local: 3,
+                remote: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_peers_missing_a_message() {
+        let result = check(
+            Handshake::new(3),
+            Handshake::new(3),
+            vec!["a:Answer", "a:Other"],
+            &["a:Other"],
+        );
+
+        match result {
+            Err(HandshakeError::MissingMessages(missing)) => assert_eq!(missing, vec!["a:Answer"]),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_peers_without_a_handshake() {
+        let result = Handshake::new(3)
+            .expect(Vec::new())
+            .complete(&peer_registry(&[]));
+
+        assert!(matches!(result, Err(HandshakeError::Missing)));
+    }
+}
@@ -0,0 +1,120 @@
+use std::{future::Future, time::Duration};
+
+use async_channel::{bounded, Receiver, Sender};
+use async_io::Timer;
+use bevy::log::trace;
+use bytes::Bytes;
+use futures_lite::future;
+
+use crate::{network_packet::PacketKind, Channel, DisconnectReason, NetworkPacket};
+
+/// Insert this as a resource on both the server and the client to detect dead connections.
+///
+/// Idle connections send a small heartbeat packet every [`Heartbeat::interval`], and connections
+/// that haven't received anything for [`Heartbeat::timeout`] are dropped with
+/// [`DisconnectReason::TimedOut`].
+///
+/// ## Note
+/// Both sides need this resource, otherwise a quiet peer will look dead
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    /// How long a connection may go without sending anything, before a heartbeat is sent
+    ///
+    /// ## Default
+    /// The default is set to 1 second
+    pub interval: Duration,
+
+    /// How long a connection may go without receiving anything, before it is dropped
+    ///
+    /// ## Default
+    /// The default is set to 10 seconds
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Put heartbeats in between `messages` whenever they go quiet.
+///
+/// The returned receiver replaces `messages`, the future has to run alongside the provider's `send_loop`.
+pub(crate) fn keep_alive(
+    messages: Receiver<NetworkPacket>,
+    heartbeat: Option<&Heartbeat>,
+) -> (Receiver<NetworkPacket>, impl Future<Output = ()>) {
+    let (relay, relayed) = match heartbeat {
+        Some(heartbeat) => {
+            // Only holds a single packet, so a bounded queue in front still fills up
+            let (relayed_tx, relayed_rx) = bounded(1);
+            (Some((messages, relayed_tx, heartbeat.interval)), relayed_rx)
+        }
+        None => (None, messages),
+    };
+
+    let relay_loop = async move {
+        if let Some((messages, relayed, interval)) = relay {
+            relay_loop(messages, relayed, interval).await;
+        }
+    };
+
+    (relayed, relay_loop)
+}
+
+async fn relay_loop(
+    messages: Receiver<NetworkPacket>,
+    relayed: Sender<NetworkPacket>,
+    interval: Duration,
+) {
+    loop {
+        let next = future::or(async { Some(messages.recv().await) }, async {
+            Timer::after(interval).await;
+            None
+        })
+        .await;
+
+        let packet = match next {
+            Some(Ok(packet)) => packet,
+            // The connection was dropped on our side
+            Some(Err(_)) => break,
+            None => {
+                trace!("Sending heartbeat");
+                NetworkPacket {
+                    kind: PacketKind::Heartbeat,
+                    data: Bytes::new(),
+                    channel: Channel::DEFAULT,
+                    compressed: false,
+                }
+            }
+        };
+
+        if relayed.send(packet).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Receive the next packet, giving up once the peer has been quiet for longer than the timeout.
+///
+/// Returns `None` once the provider's `recv_loop` has stopped.
+pub(crate) async fn recv(
+    incoming: &Receiver<NetworkPacket>,
+    heartbeat: Option<&Heartbeat>,
+) -> Result<Option<NetworkPacket>, DisconnectReason> {
+    let received = async { Ok(incoming.recv().await.ok()) };
+
+    match heartbeat {
+        Some(heartbeat) => {
+            future::or(received, async {
+                Timer::after(heartbeat.timeout).await;
+                Err(DisconnectReason::TimedOut)
+            })
+            .await
+        }
+        None => received.await,
+    }
+}
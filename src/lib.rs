@@ -25,26 +25,25 @@ can start receiving packets as events of `NetworkData<T>`.
 ## Example Client
 ```rust,no_run
 use bevy::prelude::*;
-use bevy_eventwork::{ClientPlugin, NetworkData, NetworkMessage, ServerMessage, ClientNetworkEvent, AppNetworkServerMessage};
+use bevy::tasks::TaskPool;
+use bevy_eventwork::{ClientPlugin, NetworkData, ClientMessage, ClientNetworkEvent, AppNetworkClientMessage};
+use bevy_eventwork::tcp::TcpClientProvider;
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize)]
 struct WorldUpdate;
 
-#[typetag::serde]
-impl NetworkMessage for WorldUpdate {}
-
-impl ServerMessage for WorldUpdate {
+impl ClientMessage for WorldUpdate {
     const NAME: &'static str = "example:WorldUpdate";
 }
 
 fn main() {
-     let mut app = App::build();
-     app.add_plugin(ClientPlugin);
+     let mut app = App::new();
+     app.add_plugin(ClientPlugin::<TcpClientProvider, TaskPool>::default());
      // We are receiving this from the server, so we need to listen for it
-     app.listen_for_server_message::<WorldUpdate>();
-     app.add_system(handle_world_updates.system());
-     app.add_system(handle_connection_events.system());
+     app.listen_for_client_message::<WorldUpdate, TcpClientProvider>();
+     app.add_system(handle_world_updates);
+     app.add_system(handle_connection_events);
 }
 
 fn handle_world_updates(
@@ -69,30 +68,29 @@ fn handle_connection_events(mut network_events: EventReader<ClientNetworkEvent>,
 ## Example Server
 ```rust,no_run
 use bevy::prelude::*;
-use bevy_eventwork::{ServerPlugin, NetworkData, NetworkMessage, NetworkServer, ServerMessage, ClientMessage, ServerNetworkEvent, AppNetworkClientMessage};
+use bevy::tasks::TaskPool;
+use bevy_eventwork::{ServerPlugin, NetworkData, NetworkServer, ServerMessage, ClientMessage, ServerNetworkEvent, AppNetworkServerMessage};
+use bevy_eventwork::tcp::TcpServerProvider;
 
 use serde::{Serialize, Deserialize};
 #[derive(Serialize, Deserialize)]
 struct UserInput;
 
-#[typetag::serde]
-impl NetworkMessage for UserInput {}
-
-impl ClientMessage for UserInput {
+impl ServerMessage for UserInput {
     const NAME: &'static str = "example:UserInput";
 }
 
 fn main() {
-     let mut app = App::build();
-     app.add_plugin(ServerPlugin);
+     let mut app = App::new();
+     app.add_plugin(ServerPlugin::<TcpServerProvider, TaskPool>::default());
      // We are receiving this from a client, so we need to listen for it!
-     app.listen_for_client_message::<UserInput>();
-     app.add_system(handle_world_updates.system());
-     app.add_system(handle_connection_events.system());
+     app.listen_for_server_message::<UserInput, TcpServerProvider>();
+     app.add_system(handle_world_updates);
+     app.add_system(handle_connection_events);
 }
 
 fn handle_world_updates(
-    net: Res<NetworkServer>,
+    net: Res<NetworkServer<TcpServerProvider>>,
     mut chunk_updates: EventReader<NetworkData<UserInput>>,
 ) {
     for chunk in chunk_updates.iter() {
@@ -103,9 +101,6 @@ fn handle_world_updates(
 #[derive(Serialize, Deserialize)]
 struct PlayerUpdate;
 
-#[typetag::serde]
-impl NetworkMessage for PlayerUpdate {}
-
 impl ClientMessage for PlayerUpdate {
     const NAME: &'static str = "example:PlayerUpdate";
 }
@@ -117,13 +112,13 @@ impl PlayerUpdate {
 }
 
 fn handle_connection_events(
-    net: Res<NetworkServer>,
+    net: Res<NetworkServer<TcpServerProvider>>,
     mut network_events: EventReader<ServerNetworkEvent>,
 ) {
     for event in network_events.iter() {
         match event {
             &ServerNetworkEvent::Connected(conn_id) => {
-                net.send_message(conn_id, PlayerUpdate::new());
+                let _ = net.send_message(conn_id, PlayerUpdate::new());
                 info!("New client connected: {:?}", conn_id);
             }
             _ => (),
@@ -137,11 +132,28 @@ As you can see, they are both quite similar, and provide everything a basic netw
 Currently, Bevy's [TaskPool] is the default runtime used by Eventwork.
 */
 
+mod auth;
+mod channel;
 /// Contains all functionality for contenctin to a server, sending, and recieving messages with it.
 pub mod client;
+/// Contains the [`Codec`](codec::Codec) trait and the included wire formats.
+pub mod codec;
+#[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+mod compression;
+mod disconnect;
 /// Contains error enum.
 pub mod error;
+mod handshake;
+mod heartbeat;
 mod network_message;
+mod network_packet;
+mod queue;
+mod reconnect;
+mod registry;
+mod request;
+mod room;
+mod session;
+mod transfer;
 
 /// Contains all functionality for starting a server, sending, and recieving messages from clients.
 pub mod server;
@@ -150,23 +162,79 @@ mod runtime;
 use runtime::JoinHandle;
 pub use runtime::Runtime;
 
-use std::{fmt::Debug, marker::PhantomData};
+use std::{
+    fmt::Debug,
+    future::Future,
+    marker::PhantomData,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
 
 pub use async_channel;
 use async_channel::{unbounded, Receiver, Sender};
+use async_io::Timer;
 pub use async_trait::async_trait;
+pub use auth::{Authentication, Credentials};
 use bevy::{prelude::*, utils::Uuid};
+use bytes::Bytes;
+pub use channel::{Channel, Delivery};
 pub use client::{AppNetworkClientMessage, NetworkClient, NetworkClientProvider};
+pub use codec::{Codec, JsonCodec};
+#[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+pub use compression::{Compression, CompressionAlgorithm};
 use derive_more::{Deref, Display};
+pub use disconnect::DisconnectReason;
 use error::NetworkError;
-pub use network_message::{ClientMessage, ServerMessage};
-use serde::{Deserialize, Serialize};
+use futures_lite::future;
+pub use handshake::Handshake;
+pub use heartbeat::Heartbeat;
+pub use network_message::{ClientMessage, MessageOptions, RequestMessage, ServerMessage};
+pub use network_packet::NetworkPacket;
+use queue::Outgoing;
+pub use queue::{Priority, QueuePolicy, SendQueue};
+pub use reconnect::Reconnect;
+use registry::PeerRegistry;
+pub use request::{NetworkRequest, PendingResponse};
+pub use room::RoomId;
 pub use server::{AppNetworkServerMessage, NetworkServer, NetworkServerProvider};
+pub use session::SessionResumption;
+use transfer::Transfer;
+pub use transfer::{TransferDirection, TransferId, TransferProgress, Transfers};
+
+#[cfg(any(feature = "tcp", feature = "tls", all(unix, feature = "unix")))]
+/// The length prefixed framing shared by the stream based providers.
+mod framing;
+#[cfg(any(feature = "tcp", feature = "tls", all(unix, feature = "unix")))]
+pub use framing::Batching;
 
 #[cfg(feature = "tcp")]
 /// A default tcp provider to help get you started.
 pub mod tcp;
 
+#[cfg(feature = "tls")]
+/// A tcp provider that encrypts every connection with TLS.
+pub mod tls;
+
+#[cfg(all(unix, feature = "unix"))]
+/// A unix domain socket provider, for talking to other processes on the same host.
+pub mod unix;
+
+#[cfg(feature = "memory")]
+/// An in-process provider, for tests and for hosting a server and a client in the same app.
+pub mod memory;
+
+#[cfg(feature = "udp")]
+/// A udp provider with its own reliability layer, for when tcp's head-of-line blocking hurts.
+pub mod udp;
+
+#[cfg(feature = "websocket")]
+/// A websocket provider, for browsers and networks that only let http through.
+pub mod websocket;
+
+#[cfg(feature = "quic")]
+/// A QUIC provider, encrypted and without tcp's head-of-line blocking between connections.
+pub mod quic;
+
 struct AsyncChannel<T> {
     pub(crate) sender: Sender<T>,
     pub(crate) receiver: Receiver<T>,
@@ -181,7 +249,7 @@ impl<T> AsyncChannel<T> {
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Copy, Display, Debug)]
-#[display(fmt = "Connection with ID={}", /*addr,*/ uuid)]
+#[display(fmt = "Connection with ID={}", uuid)]
 /// A [`ConnectionId`] denotes a single connection
 ///
 /// Use [`ConnectionId::is_server`] whether it is a connection to a server
@@ -189,19 +257,9 @@ impl<T> AsyncChannel<T> {
 /// is no ambiguity.
 pub struct ConnectionId {
     uuid: Uuid,
-    //addr: SocketAddr,
 }
 
 impl ConnectionId {
-    /// Get the address associated to this connection id
-    ///
-    /// This contains the IP/Port information
-    /*
-    pub fn address(&self) -> SocketAddr {
-        self.addr
-    }
-    */
-
     pub(crate) fn server() -> Self {
         Self { uuid: Uuid::nil() }
     }
@@ -212,19 +270,17 @@ impl ConnectionId {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-/// [`NetworkPacket`]s are untyped packets to be sent over the wire
-pub struct NetworkPacket {
-    kind: String,
-    data: String,
-}
-
-impl Debug for NetworkPacket {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("NetworkPacket")
-            .field("kind", &self.kind)
-            .finish()
-    }
+#[derive(Clone, Copy, Debug)]
+/// Where a connection goes and when it was made
+///
+/// See [`NetworkServer::connection_info`] and [`NetworkClient::connection_info`]
+pub struct ConnectionInfo {
+    /// The address on the other side, if the provider connects over IP
+    pub peer_addr: Option<SocketAddr>,
+    /// The address on our side, if the provider connects over IP
+    pub local_addr: Option<SocketAddr>,
+    /// When the connection was made
+    pub connected_at: SystemTime,
 }
 
 /// A network event originating from a [`NetworkServer`]
@@ -233,7 +289,13 @@ pub enum ServerNetworkEvent {
     /// A new client has connected
     Connected(ConnectionId),
     /// A client has disconnected
-    Disconnected(ConnectionId),
+    Disconnected(ConnectionId, DisconnectReason),
+    /// A transfer to or from a client made progress, see [`NetworkServer::send_transfer`]
+    Transfer(ConnectionId, TransferProgress),
+    /// A client joined a room, see [`NetworkServer::join_room`]
+    JoinedRoom(ConnectionId, RoomId),
+    /// A client left a room, or disconnected while it was in there
+    LeftRoom(ConnectionId, RoomId),
     /// An error occured while trying to do a network operation
     Error(NetworkError),
 }
@@ -241,10 +303,19 @@ pub enum ServerNetworkEvent {
 #[derive(Debug)]
 /// A network event originating from a [`NetworkClient`]
 pub enum ClientNetworkEvent {
-    /// Connected to a server
+    /// Connected to a server, once it let the client in
     Connected,
     /// Disconnected from a server
-    Disconnected,
+    Disconnected(DisconnectReason),
+    /// Trying to get back to the server after losing it, see [`Reconnect`]
+    Reconnecting {
+        /// Counts up from 1 for every try
+        attempt: u32,
+    },
+    /// Gave up on getting back to the server, see [`Reconnect::max_attempts`]
+    ReconnectFailed,
+    /// A transfer to or from the server made progress, see [`NetworkClient::send_transfer`]
+    Transfer(TransferProgress),
     /// An error occured while trying to do a network operation
     Error(NetworkError),
 }
@@ -281,29 +352,161 @@ struct Connection {
     receive_task: Box<dyn JoinHandle>,
     map_receive_task: Box<dyn JoinHandle>,
     send_task: Box<dyn JoinHandle>,
-    send_message: Sender<NetworkPacket>,
+    /// Closed along with the connection, see [`send_until_closed`]
+    closed: Sender<()>,
+    outgoing: Outgoing,
+    transfers: Sender<Transfer>,
+    peer_registry: PeerRegistry,
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+    compression: Option<Compression>,
+    info: ConnectionInfo,
 }
 
 impl Connection {
-    fn stop(mut self) {
+    /// The packet carrying a message, compressed if the peer asked for it
+    fn packet(&self, name: &'static str, data: Bytes) -> NetworkPacket {
+        SharedMessage::new(name, data).packet_for(self)
+    }
+
+    /// Stop once everything queued has been sent, transfers that are still going are dropped
+    ///
+    /// A peer that doesn't read gets [`CLOSE_TIMEOUT`] to take it, then the connection is dropped anyway.
+    fn close(mut self) {
+        self.outgoing.close();
+        self.transfers.close();
+        self.closed.close();
+        self.receive_task.abort();
+        self.send_task.detach();
+    }
+
+    /// Stop all tasks, but keep the outgoing queue open to resume the connection later
+    fn suspend(&mut self) {
         self.receive_task.abort();
+        self.map_receive_task.abort();
         self.send_task.abort();
     }
+
+    /// Stop right away, dropping whatever is still queued
+    fn abort(mut self) {
+        self.outgoing.close();
+        self.transfers.close();
+        self.receive_task.abort();
+        self.map_receive_task.abort();
+        self.send_task.abort();
+    }
+
+    /// Tell the peer why the connection ends, then close it
+    ///
+    /// A connection whose queue is full is aborted instead, its peer isn't taking anything anymore.
+    fn close_with(self, reason: &DisconnectReason) {
+        if *reason == DisconnectReason::QueueFull {
+            return self.abort();
+        }
+
+        // The connection ends anyway, so this may drop whatever is still queued
+        let _ = self.outgoing.force(Priority::Low, reason.announcement());
+        self.close();
+    }
+}
+
+/// How long a closed connection may take to send what is still queued
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run the send side of a connection until it is done, or until `timeout` after `closed` was closed
+///
+/// Connections pass [`CLOSE_TIMEOUT`].
+async fn send_until_closed(send: impl Future, closed: Receiver<()>, timeout: Duration) {
+    future::or(
+        async move {
+            send.await;
+        },
+        async move {
+            // Nothing is ever sent, this only returns once the connection is closed
+            let _ = closed.recv().await;
+            Timer::after(timeout).await;
+        },
+    )
+    .await
+}
+
+/// A message that goes out to many connections, encoded only once
+///
+/// The data is shared between all their packets, and compressed once for every [`Compression`] they use.
+#[derive(Debug)]
+struct SharedMessage {
+    name: &'static str,
+    data: Bytes,
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+    compressed: Vec<(Compression, Bytes, bool)>,
 }
+
+impl SharedMessage {
+    fn new(name: &'static str, data: Bytes) -> Self {
+        Self {
+            name,
+            data,
+            #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+            compressed: Vec::new(),
+        }
+    }
+
+    /// The packet carrying the message to `connection`, on the channel its peer asked for
+    /// and compressed if it asked for that
+    fn packet_for(&mut self, connection: &Connection) -> NetworkPacket {
+        let (kind, options) = connection.peer_registry.route(self.name);
+        #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+        let (data, compressed) = match connection.compression.as_ref() {
+            Some(compression) if options.compress => self.compressed_with(compression),
+            _ => (self.data.clone(), false),
+        };
+        #[cfg(not(any(feature = "zstd", feature = "lz4", feature = "deflate")))]
+        let (data, compressed) = (self.data.clone(), false);
+
+        NetworkPacket {
+            kind,
+            data,
+            channel: options.channel,
+            compressed,
+        }
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+    fn compressed_with(&mut self, compression: &Compression) -> (Bytes, bool) {
+        if let Some((_, data, compressed)) = self
+            .compressed
+            .iter()
+            .find(|(used, ..)| used == compression)
+        {
+            return (data.clone(), *compressed);
+        }
+
+        let (data, compressed) = compression.compress(self.data.clone());
+        self.compressed
+            .push((compression.clone(), data.clone(), compressed));
+        (data, compressed)
+    }
+}
+
 #[derive(Default, Copy, Clone, Debug)]
 /// The plugin to add to your bevy [`App`](bevy::prelude::App) when you want
 /// to instantiate a server
-pub struct ServerPlugin<NSP: NetworkServerProvider, RT: Runtime = bevy::tasks::TaskPool>(
-    PhantomData<(NSP, RT)>,
-);
-
-impl<NSP: NetworkServerProvider + Default, RT: Runtime> Plugin for ServerPlugin<NSP, RT> {
+///
+/// Messages are encoded with [`JsonCodec`] unless a different [`Codec`] is given.
+pub struct ServerPlugin<
+    NSP: NetworkServerProvider,
+    RT: Runtime = bevy::tasks::TaskPool,
+    C: Codec = JsonCodec,
+>(PhantomData<(NSP, RT, C)>);
+
+impl<NSP: NetworkServerProvider + Default, RT: Runtime, C: Codec> Plugin
+    for ServerPlugin<NSP, RT, C>
+{
     fn build(&self, app: &mut App) {
-        app.insert_resource(server::NetworkServer::new(NSP::default()));
+        app.insert_resource(NetworkServer::<NSP, C>::new(NSP::default()));
         app.add_event::<ServerNetworkEvent>();
         app.add_system_to_stage(
             CoreStage::PreUpdate,
-            server::handle_new_incoming_connections::<NSP, RT>,
+            server::handle_new_incoming_connections::<NSP, RT, C>,
         );
     }
 }
@@ -311,21 +514,71 @@ impl<NSP: NetworkServerProvider + Default, RT: Runtime> Plugin for ServerPlugin<
 #[derive(Default, Copy, Clone, Debug)]
 /// The plugin to add to your bevy [`App`](bevy::prelude::App) when you want
 /// to instantiate a client
-pub struct ClientPlugin<NCP: NetworkClientProvider, RT: Runtime = bevy::tasks::TaskPool>(
-    PhantomData<(NCP, RT)>,
-);
-
-impl<NCP: NetworkClientProvider + Default, RT: Runtime> Plugin for ClientPlugin<NCP, RT> {
+///
+/// Messages are encoded with [`JsonCodec`] unless a different [`Codec`] is given.
+pub struct ClientPlugin<
+    NCP: NetworkClientProvider,
+    RT: Runtime = bevy::tasks::TaskPool,
+    C: Codec = JsonCodec,
+>(PhantomData<(NCP, RT, C)>);
+
+impl<NCP: NetworkClientProvider + Default, RT: Runtime, C: Codec> Plugin
+    for ClientPlugin<NCP, RT, C>
+{
     fn build(&self, app: &mut App) {
-        app.insert_resource(client::NetworkClient::new(NCP::default()));
+        app.insert_resource(NetworkClient::<NCP, C>::new(NCP::default()));
         app.add_event::<ClientNetworkEvent>();
         app.add_system_to_stage(
             CoreStage::PreUpdate,
-            client::send_client_network_events::<NCP, RT>,
+            client::send_client_network_events::<NCP, RT, C>,
         );
         app.add_system_to_stage(
             CoreStage::PreUpdate,
-            client::handle_connection_event::<NCP, RT>,
+            client::handle_connection_event::<NCP, RT, C>,
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn close_timeout_drops_a_stuck_send() {
+        let (closed_tx, closed_rx) = async_channel::bounded::<()>(1);
+        let timeout = Duration::from_millis(50);
+
+        future::block_on(async {
+            // Still waiting for the peer as long as the connection is open
+            let open = future::or(
+                async {
+                    send_until_closed(future::pending::<()>(), closed_rx.clone(), timeout).await;
+                    false
+                },
+                async {
+                    Timer::after(timeout * 2).await;
+                    true
+                },
+            )
+            .await;
+            assert!(open);
+
+            closed_tx.close();
+            let start = Instant::now();
+            send_until_closed(future::pending::<()>(), closed_rx, timeout).await;
+            assert!(start.elapsed() >= timeout);
+        });
+    }
+
+    #[test]
+    fn finished_sends_are_not_held_up() {
+        let (closed_tx, closed_rx) = async_channel::bounded::<()>(1);
+        closed_tx.close();
+
+        let start = Instant::now();
+        future::block_on(send_until_closed(async {}, closed_rx, CLOSE_TIMEOUT));
+        assert!(start.elapsed() < CLOSE_TIMEOUT);
+    }
+}
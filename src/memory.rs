@@ -0,0 +1,1007 @@
+use std::{io, sync::OnceLock};
+
+use crate::{
+    async_channel::{unbounded, Receiver, Sender},
+    async_trait,
+    client::NetworkClientProvider,
+    error::NetworkError,
+    server::NetworkServerProvider,
+    ClientNetworkEvent, DisconnectReason, NetworkPacket,
+};
+use bevy::log::{debug, error, info, trace};
+use dashmap::{mapref::entry::Entry, DashMap};
+
+/// Every listening [`MemoryServerProvider`] in this process, by name.
+fn listeners() -> &'static DashMap<String, Sender<MemorySocket>> {
+    static LISTENERS: OnceLock<DashMap<String, Sender<MemorySocket>>> = OnceLock::new();
+    LISTENERS.get_or_init(DashMap::new)
+}
+
+/// Removes a listener again once its accept loop is stopped.
+struct Listener {
+    name: String,
+    connections: Receiver<MemorySocket>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.connections.close();
+        // A new listener may already have taken over the name
+        listeners().remove_if(&self.name, |_, listener| listener.is_closed());
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides an in-process listener for eventwork.
+///
+/// Clients in the same process connect to it by name, packets are handed
+/// over through channels without ever touching the network.
+pub struct MemoryServerProvider;
+
+#[async_trait]
+impl NetworkServerProvider for MemoryServerProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = MemorySocket;
+
+    type ReadHalf = Receiver<NetworkPacket>;
+
+    type WriteHalf = Sender<NetworkPacket>;
+
+    async fn accept_loop(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<NetworkError>,
+    ) {
+        let (listener_tx, listener_rx) = unbounded();
+
+        let name_taken = match listeners().entry(network_settings.name.clone()) {
+            Entry::Occupied(existing) if !existing.get().is_closed() => true,
+            Entry::Occupied(mut stale) => {
+                stale.insert(listener_tx);
+                false
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(listener_tx);
+                false
+            }
+        };
+
+        if name_taken {
+            let err = io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already listening", network_settings.name),
+            );
+            if let Err(err) = errors.send(NetworkError::Listen(err)).await {
+                error!("Could not send listen error: {}", err);
+            }
+            return;
+        }
+        let listener = Listener {
+            name: network_settings.name,
+            connections: listener_rx,
+        };
+
+        while let Ok(socket) = listener.connections.recv().await {
+            if let Err(err) = new_connections.send(socket).await {
+                error!("Could not send listen error: {}", err);
+                return;
+            }
+            info!("New Connection Made!");
+        }
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        (combined.incoming, combined.outgoing)
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides an in-process connection for eventwork.
+pub struct MemoryClientProvider;
+
+#[async_trait]
+impl NetworkClientProvider for MemoryClientProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = MemorySocket;
+
+    type ReadHalf = Receiver<NetworkPacket>;
+
+    type WriteHalf = Sender<NetworkPacket>;
+
+    async fn connect_task(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<ClientNetworkEvent>,
+    ) {
+        info!("Beginning connection");
+        let socket = match connect(&network_settings) {
+            Ok(socket) => socket,
+            Err(error) => {
+                match errors
+                    .send(ClientNetworkEvent::Error(NetworkError::Connection(error)))
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(err) => {
+                        error!("Could not send error event: {}", err);
+                    }
+                }
+
+                return;
+            }
+        };
+
+        info!("Connected!");
+
+        match new_connections.send(socket).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not initiate connection: {}", err);
+            }
+        }
+
+        debug!("Connected to: {}", network_settings.name);
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        (combined.incoming, combined.outgoing)
+    }
+}
+
+/// Hands one end of a new channel pair to the named listener and returns the other.
+fn connect(settings: &NetworkSettings) -> io::Result<MemorySocket> {
+    let listener = listeners()
+        .get(&settings.name)
+        .map(|listener| listener.clone())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("Nothing is listening on {}", settings.name),
+            )
+        })?;
+
+    let (to_server, from_client) = unbounded();
+    let (to_client, from_server) = unbounded();
+
+    listener
+        .try_send(MemorySocket {
+            incoming: from_client,
+            outgoing: to_client,
+        })
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("{} stopped listening", settings.name),
+            )
+        })?;
+
+    Ok(MemorySocket {
+        incoming: from_server,
+        outgoing: to_server,
+    })
+}
+
+async fn recv_loop(
+    read_half: Receiver<NetworkPacket>,
+    messages: Sender<NetworkPacket>,
+    _settings: NetworkSettings,
+) -> DisconnectReason {
+    // The peer dropping its end closes the channel, just like an EOF
+    while let Ok(packet) = read_half.recv().await {
+        if messages.send(packet).await.is_err() {
+            error!("Failed to send decoded message to eventwork");
+            break;
+        }
+        trace!("Message sent to eventwork");
+    }
+    info!("Peer disconnected");
+    DisconnectReason::Eof
+}
+
+async fn send_loop(
+    write_half: Sender<NetworkPacket>,
+    messages: Receiver<NetworkPacket>,
+    _settings: NetworkSettings,
+) {
+    while let Ok(message) = messages.recv().await {
+        if let Err(err) = write_half.send(message).await {
+            error!("Could not send packet: {:?}", err.into_inner());
+            break;
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Both ends of an in-process connection, as seen from one side.
+pub struct MemorySocket {
+    incoming: Receiver<NetworkPacket>,
+    outgoing: Sender<NetworkPacket>,
+}
+
+#[derive(Clone, Debug)]
+/// Settings to configure the network, both client and server
+pub struct NetworkSettings {
+    /// Name to listen on or connect to, unique within the process
+    pub name: String,
+}
+
+impl NetworkSettings {
+    /// Create a new instance of [`NetworkSettings`]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use async_io::Timer;
+    use bevy::{
+        ecs::event::Events,
+        prelude::*,
+        tasks::{TaskPool, TaskPoolBuilder},
+    };
+    use bytes::Bytes;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        error::AuthenticationError, network_packet::PacketKind, registry::MessageRegistry, session,
+        AppNetworkClientMessage, AppNetworkServerMessage, Authentication, Channel, ClientMessage,
+        ClientPlugin, ConnectionId, Credentials, DisconnectReason, Heartbeat, NetworkClient,
+        NetworkData, NetworkRequest, NetworkServer, PendingResponse, Reconnect, RequestMessage,
+        ServerMessage, ServerNetworkEvent, ServerPlugin, SessionResumption,
+    };
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Hello(String);
+
+    impl ServerMessage for Hello {
+        const NAME: &'static str = "memory-tests:Hello";
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Welcome(String);
+
+    impl ClientMessage for Welcome {
+        const NAME: &'static str = "memory-tests:Welcome";
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Ask(u32);
+
+    impl ServerMessage for Ask {
+        const NAME: &'static str = "memory-tests:Ask";
+    }
+
+    impl RequestMessage for Ask {
+        type Response = Answer;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Answer(u32);
+
+    impl ClientMessage for Answer {
+        const NAME: &'static str = "memory-tests:Answer";
+    }
+
+    /// Everything both apps saw, in order
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    fn greet(
+        net: Res<NetworkServer<MemoryServerProvider>>,
+        mut events: EventReader<ServerNetworkEvent>,
+        mut hellos: EventReader<NetworkData<Hello>>,
+        mut log: ResMut<Log>,
+    ) {
+        for event in events.iter() {
+            match event {
+                ServerNetworkEvent::Connected(_) => log.0.push("server connected".into()),
+                ServerNetworkEvent::Disconnected(_, reason) => {
+                    log.0.push(format!("server disconnected {:?}", reason))
+                }
+                ServerNetworkEvent::LeftRoom(_, room) => {
+                    log.0.push(format!("server left room {}", room))
+                }
+                ServerNetworkEvent::Error(err) => log.0.push(format!("server error {}", err)),
+                _ => {}
+            }
+        }
+        for hello in hellos.iter() {
+            log.0.push(format!("server got {}", hello.0));
+            net.send_message(hello.source(), Welcome(format!("welcome {}", hello.0)))
+                .expect("client is connected");
+        }
+    }
+
+    fn say_hello(
+        net: Res<NetworkClient<MemoryClientProvider>>,
+        mut events: EventReader<ClientNetworkEvent>,
+        mut welcomes: EventReader<NetworkData<Welcome>>,
+        mut log: ResMut<Log>,
+    ) {
+        for event in events.iter() {
+            match event {
+                ClientNetworkEvent::Connected => {
+                    log.0.push("client connected".into());
+                    net.send_message(Hello("alice".into()))
+                        .expect("server is connected");
+                }
+                ClientNetworkEvent::Disconnected(reason) => {
+                    log.0.push(format!("client disconnected {:?}", reason))
+                }
+                ClientNetworkEvent::Error(err) => log.0.push(format!("client error {}", err)),
+                _ => {}
+            }
+        }
+        for welcome in welcomes.iter() {
+            log.0.push(format!("client got {}", welcome.0));
+        }
+    }
+
+    /// Runs both apps until `done` holds for their logs
+    fn run_until(server: &mut App, client: &mut App, done: impl Fn(&[String], &[String]) -> bool) {
+        for _ in 0..200 {
+            server.update();
+            client.update();
+            if done(
+                &server.world.resource::<Log>().0,
+                &client.world.resource::<Log>().0,
+            ) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!(
+            "gave up waiting, server saw {:?}, client saw {:?}",
+            server.world.resource::<Log>().0,
+            client.world.resource::<Log>().0
+        );
+    }
+
+    /// A server and a client talking over the memory provider called `name`, not connected yet
+    fn apps(name: &str) -> (App, App, NetworkSettings) {
+        let pool = TaskPoolBuilder::new().num_threads(2).build();
+        let settings = NetworkSettings::new(name);
+
+        let mut server = App::new();
+        server
+            .add_plugins(MinimalPlugins)
+            .insert_resource(pool.clone())
+            .insert_resource(settings.clone())
+            .init_resource::<Log>()
+            .add_plugin(ServerPlugin::<MemoryServerProvider, TaskPool>::default())
+            .add_system(greet);
+        server.listen_for_server_message::<Hello, MemoryServerProvider>();
+
+        let mut client = App::new();
+        client
+            .add_plugins(MinimalPlugins)
+            .insert_resource(pool)
+            .insert_resource(settings.clone())
+            .init_resource::<Log>()
+            .add_plugin(ClientPlugin::<MemoryClientProvider, TaskPool>::default())
+            .add_system(say_hello);
+        client.listen_for_client_message::<Welcome, MemoryClientProvider>();
+
+        (server, client, settings)
+    }
+
+    /// Start listening, once all resources are in place
+    fn listen(server: &mut App, settings: &NetworkSettings) {
+        let pool = server.world.resource::<TaskPool>().clone();
+        server
+            .world
+            .resource_mut::<NetworkServer<MemoryServerProvider>>()
+            .listen(&pool, settings)
+            .expect("name is free");
+        // The server starts listening in the background, connecting before then is refused
+        for _ in 0..200 {
+            if listeners().contains_key(&settings.name) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Start listening and connect, once all resources are in place
+    fn connect(server: &mut App, client: &mut App, settings: &NetworkSettings) {
+        listen(server, settings);
+        connect_client(client, settings);
+    }
+
+    /// Connect to a server that is listening already
+    fn connect_client(client: &mut App, settings: &NetworkSettings) {
+        let pool = client.world.resource::<TaskPool>().clone();
+        client
+            .world
+            .resource_mut::<NetworkClient<MemoryClientProvider>>()
+            .connect(&pool, settings);
+    }
+
+    #[test]
+    fn connects_sends_and_disconnects() {
+        let (mut server, mut client, settings) = apps("memory-tests");
+        connect(&mut server, &mut client, &settings);
+
+        run_until(&mut server, &mut client, |server, client| {
+            server.len() == 2 && client.len() == 2
+        });
+        assert_eq!(
+            client.world.resource::<Log>().0,
+            ["client connected", "client got welcome alice"]
+        );
+
+        client
+            .world
+            .resource_mut::<NetworkClient<MemoryClientProvider>>()
+            .disconnect();
+        run_until(&mut server, &mut client, |server, _| server.len() == 3);
+
+        assert_eq!(
+            server.world.resource::<Log>().0,
+            [
+                "server connected".to_string(),
+                "server got alice".to_string(),
+                format!("server disconnected {:?}", DisconnectReason::Left),
+            ]
+        );
+        assert!(!client
+            .world
+            .resource::<NetworkClient<MemoryClientProvider>>()
+            .is_connected());
+    }
+
+    #[test]
+    fn quiet_clients_time_out() {
+        let (mut server, mut client, settings) = apps("memory-tests-timeout");
+        // Only the server expects heartbeats, the client never sends any
+        server.insert_resource(Heartbeat {
+            interval: Duration::from_millis(20),
+            timeout: Duration::from_millis(100),
+        });
+        connect(&mut server, &mut client, &settings);
+
+        let timed_out = |log: &[String], side: &str| {
+            log.contains(&format!(
+                "{} disconnected {:?}",
+                side,
+                DisconnectReason::TimedOut
+            ))
+        };
+        run_until(&mut server, &mut client, |server, client| {
+            timed_out(server, "server") && timed_out(client, "client")
+        });
+    }
+
+    /// Keeps the ids of all clients that connected in a `Vec<ConnectionId>`, in order
+    fn track_clients(server: &mut App) {
+        server.init_resource::<Vec<ConnectionId>>().add_system(
+            |mut events: EventReader<ServerNetworkEvent>,
+             mut clients: ResMut<Vec<ConnectionId>>| {
+                for event in events.iter() {
+                    if let ServerNetworkEvent::Connected(conn_id) = event {
+                        clients.push(*conn_id);
+                    }
+                }
+            },
+        );
+    }
+
+    #[test]
+    fn kicked_clients_are_told_why() {
+        let (mut server, mut client, settings) = apps("memory-tests-kick");
+        track_clients(&mut server);
+        connect(&mut server, &mut client, &settings);
+        run_until(&mut server, &mut client, |_, client| client.len() == 2);
+
+        let conn_id = server.world.resource::<Vec<ConnectionId>>()[0];
+        server
+            .world
+            .resource::<NetworkServer<MemoryServerProvider>>()
+            .kick(conn_id, "no alices allowed")
+            .expect("client is connected");
+
+        let kicked = format!(
+            "client disconnected {:?}",
+            DisconnectReason::Kicked("no alices allowed".into())
+        );
+        run_until(&mut server, &mut client, |_, client| {
+            client.contains(&kicked)
+        });
+    }
+
+    #[test]
+    fn clients_are_told_the_server_stopped() {
+        let (mut server, mut client, settings) = apps("memory-tests-stop");
+        connect(&mut server, &mut client, &settings);
+        run_until(&mut server, &mut client, |_, client| client.len() == 2);
+
+        server
+            .world
+            .resource_mut::<NetworkServer<MemoryServerProvider>>()
+            .stop();
+
+        let stopped = format!("client disconnected {:?}", DisconnectReason::Shutdown);
+        run_until(&mut server, &mut client, |_, client| {
+            client.contains(&stopped)
+        });
+    }
+
+    /// Lets the client come back right away once the server dropped it for being quiet
+    fn resuming_apps(name: &str, grace_period: Duration) -> (App, App, NetworkSettings) {
+        let (mut server, mut client, settings) = apps(name);
+        server
+            .insert_resource(SessionResumption { grace_period })
+            .insert_resource(Heartbeat {
+                interval: Duration::from_millis(20),
+                timeout: Duration::from_millis(100),
+            });
+        client.insert_resource(Reconnect {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(20),
+            jitter: 0.0,
+            max_attempts: 5,
+        });
+        (server, client, settings)
+    }
+
+    #[test]
+    fn suspended_sessions_resume() {
+        let (mut server, mut client, settings) =
+            resuming_apps("memory-tests-resume", Duration::from_secs(30));
+        connect(&mut server, &mut client, &settings);
+
+        let greeted = |log: &[String]| {
+            log.iter()
+                .filter(|line| *line == "server got alice")
+                .count()
+        };
+        run_until(&mut server, &mut client, |server, _| greeted(server) == 2);
+
+        // The client came back as the same connection, without the server noticing it was gone
+        assert_eq!(
+            server.world.resource::<Log>().0,
+            ["server connected", "server got alice", "server got alice"]
+        );
+    }
+
+    #[test]
+    fn expired_sessions_are_not_resumed() {
+        let (mut server, mut client, settings) =
+            resuming_apps("memory-tests-expired", Duration::ZERO);
+        connect(&mut server, &mut client, &settings);
+
+        let connected = |log: &[String]| {
+            log.iter()
+                .filter(|line| *line == "server connected")
+                .count()
+        };
+        run_until(&mut server, &mut client, |server, _| connected(server) == 2);
+
+        let server_log = &server.world.resource::<Log>().0;
+        let timed_out = format!("server disconnected {:?}", DisconnectReason::TimedOut);
+        assert_eq!(
+            server_log[..4],
+            [
+                "server connected".to_string(),
+                "server got alice".to_string(),
+                timed_out,
+                "server connected".to_string(),
+            ]
+        );
+    }
+
+    /// Connects without a client app, sending `packets` as they are
+    fn raw_connect(settings: &NetworkSettings, packets: Vec<NetworkPacket>) -> MemorySocket {
+        let socket = super::connect(settings).expect("server is listening");
+        for packet in packets {
+            socket
+                .outgoing
+                .try_send(packet)
+                .expect("server is connected");
+        }
+        socket
+    }
+
+    /// Runs the server until `done` holds for what the raw client received
+    fn receive_until(
+        server: &mut App,
+        socket: &MemorySocket,
+        done: impl Fn(&[NetworkPacket]) -> bool,
+    ) -> Vec<NetworkPacket> {
+        let mut received = Vec::new();
+        for _ in 0..200 {
+            server.update();
+            while let Ok(packet) = socket.incoming.try_recv() {
+                received.push(packet);
+            }
+            if done(&received) {
+                return received;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("gave up waiting, the client received {:?}", received);
+    }
+
+    fn has(kind: PacketKind) -> impl Fn(&[NetworkPacket]) -> bool {
+        move |received| received.iter().any(|packet| packet.kind == kind)
+    }
+
+    /// Registers nothing, which is enough to be let in
+    fn registry() -> NetworkPacket {
+        MessageRegistry::new(std::iter::empty()).announcement()
+    }
+
+    #[test]
+    fn sessions_are_handed_out_once_admitted() {
+        let (mut server, _, settings) = apps("memory-tests-session-admission");
+        server
+            .insert_resource(SessionResumption::default())
+            .insert_resource(Authentication::new(|credentials, _info| async move {
+                match credentials.as_str() {
+                    Some("alice") => Ok("alice".to_string()),
+                    _ => Err("unknown user".to_string()),
+                }
+            }));
+        listen(&mut server, &settings);
+
+        let bob = raw_connect(
+            &settings,
+            vec![Credentials::new("bob").announcement(), registry()],
+        );
+        let received = receive_until(&mut server, &bob, has(PacketKind::Disconnect));
+        assert!(!has(PacketKind::Session)(&received));
+
+        let alice = raw_connect(
+            &settings,
+            vec![Credentials::new("alice").announcement(), registry()],
+        );
+        let received = receive_until(&mut server, &alice, has(PacketKind::Admitted));
+        assert!(has(PacketKind::Session)(&received));
+    }
+
+    #[test]
+    fn unknown_tokens_start_a_new_session() {
+        let (mut server, mut client, settings) = apps("memory-tests-unknown-token");
+        server.insert_resource(SessionResumption::default());
+        connect(&mut server, &mut client, &settings);
+        run_until(&mut server, &mut client, |server, _| server.len() == 2);
+
+        let unknown = Bytes::from_static(&[7; 16]);
+        let socket = raw_connect(
+            &settings,
+            vec![session::resume_request(unknown.clone()), registry()],
+        );
+        let received = receive_until(&mut server, &socket, has(PacketKind::Admitted));
+
+        let token = received
+            .iter()
+            .find(|packet| packet.kind == PacketKind::Session)
+            .map(|packet| packet.data.clone());
+        assert!(token.is_some() && token != Some(unknown));
+        assert_eq!(
+            server.world.resource::<Log>().0,
+            ["server connected", "server got alice", "server connected"]
+        );
+    }
+
+    /// Lets both apps ask and answer [`Ask`]
+    fn asking_apps(name: &str) -> (App, App, NetworkSettings) {
+        let (mut server, mut client, settings) = apps(name);
+        server.listen_for_server_request::<Ask, MemoryServerProvider>();
+        client.listen_for_client_response::<Ask, MemoryClientProvider>();
+        connect(&mut server, &mut client, &settings);
+        run_until(&mut server, &mut client, |_, client| client.len() == 2);
+        (server, client, settings)
+    }
+
+    /// Runs both apps until the response arrived, or failed
+    fn response<T>(
+        server: &mut App,
+        client: &mut App,
+        pending: &PendingResponse<T>,
+    ) -> Result<T, NetworkError> {
+        for _ in 0..200 {
+            server.update();
+            client.update();
+            if let Some(response) = pending.try_recv() {
+                return response;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("gave up waiting for the response");
+    }
+
+    #[test]
+    fn responses_find_their_request() {
+        let (mut server, mut client, _) = asking_apps("memory-tests-requests");
+        // Answers the requests in the opposite order they were asked in
+        server.add_system(
+            |net: Res<NetworkServer<MemoryServerProvider>>,
+             mut requests: ResMut<Events<NetworkRequest<Ask>>>,
+             mut waiting: Local<Vec<NetworkRequest<Ask>>>| {
+                waiting.extend(requests.drain());
+                if waiting.len() == 2 {
+                    for request in waiting.drain(..).rev() {
+                        let answer = Answer(request.0 * 10);
+                        request.respond(&net, answer).expect("client is connected");
+                    }
+                }
+            },
+        );
+
+        let net = client
+            .world
+            .resource::<NetworkClient<MemoryClientProvider>>();
+        let first = net.request(Ask(1)).expect("server is connected");
+        let second = net.request(Ask(2)).expect("server is connected");
+
+        let second = response(&mut server, &mut client, &second).expect("server answered");
+        let first = response(&mut server, &mut client, &first).expect("server answered");
+        assert_eq!((first.0, second.0), (10, 20));
+    }
+
+    #[test]
+    fn unanswered_requests_time_out() {
+        let (mut server, mut client, _) = asking_apps("memory-tests-request-timeout");
+        let mut net = client
+            .world
+            .resource_mut::<NetworkClient<MemoryClientProvider>>();
+        net.set_request_timeout(Duration::from_millis(50));
+        let pending = net.request(Ask(1)).expect("server is connected");
+
+        let response = response(&mut server, &mut client, &pending);
+        assert!(matches!(response, Err(NetworkError::RequestTimedOut)));
+    }
+
+    /// A raw client that was let in, registering nothing so it's sent messages by name
+    fn raw_client(server: &mut App, settings: &NetworkSettings) -> MemorySocket {
+        let socket = raw_connect(settings, vec![registry()]);
+        receive_until(server, &socket, has(PacketKind::Admitted));
+        socket
+    }
+
+    fn welcome() -> PacketKind {
+        PacketKind::Name(Welcome::NAME.to_owned())
+    }
+
+    #[test]
+    fn rooms_only_reach_their_members() {
+        let (mut server, _, settings) = apps("memory-tests-rooms");
+        track_clients(&mut server);
+        listen(&mut server, &settings);
+        let alice = raw_client(&mut server, &settings);
+        let bob = raw_client(&mut server, &settings);
+
+        let clients = server.world.resource::<Vec<ConnectionId>>().clone();
+        let net = server
+            .world
+            .resource::<NetworkServer<MemoryServerProvider>>();
+        net.join_room(clients[0], "lobby")
+            .expect("client is connected");
+        net.broadcast_to_room("lobby", Welcome("lobby".into()));
+
+        receive_until(&mut server, &alice, has(welcome()));
+        let received = receive_until(&mut server, &bob, |_| true);
+        assert!(!has(welcome())(&received));
+
+        // Leaving is taken care of for clients that are gone
+        drop(alice);
+        for _ in 0..200 {
+            server.update();
+            if server
+                .world
+                .resource::<Log>()
+                .0
+                .contains(&"server left room lobby".to_string())
+            {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(
+            server.world.resource::<Log>().0,
+            [
+                "server connected".to_string(),
+                "server connected".to_string(),
+                format!("server disconnected {:?}", DisconnectReason::Eof),
+                "server left room lobby".to_string(),
+            ]
+        );
+        let net = server
+            .world
+            .resource::<NetworkServer<MemoryServerProvider>>();
+        assert!(net.room_members("lobby").is_empty());
+    }
+
+    #[test]
+    fn messages_to_many_are_encoded_once() {
+        let (mut server, _, settings) = apps("memory-tests-many");
+        track_clients(&mut server);
+        listen(&mut server, &settings);
+        let alice = raw_client(&mut server, &settings);
+        let bob = raw_client(&mut server, &settings);
+
+        let mut clients = server.world.resource::<Vec<ConnectionId>>().clone();
+        // Clients that aren't connected are skipped
+        clients.push(ConnectionId::server());
+        server
+            .world
+            .resource::<NetworkServer<MemoryServerProvider>>()
+            .send_to_many(&clients, Welcome("everyone".into()))
+            .expect("message can be encoded");
+
+        let received: Vec<_> = [alice, bob]
+            .iter()
+            .map(|socket| {
+                receive_until(&mut server, socket, has(welcome()))
+                    .into_iter()
+                    .find(|packet| packet.kind == welcome())
+                    .expect("client got the message")
+            })
+            .collect();
+        // Both packets share the very same bytes
+        assert_eq!(received[0].data, received[1].data);
+        assert_eq!(received[0].data.as_ptr(), received[1].data.as_ptr());
+    }
+
+    /// Lets in alice, takes forever to decide on anyone called slow, and rejects everyone else
+    fn authenticating_apps(name: &str) -> (App, App, NetworkSettings) {
+        let (mut server, client, settings) = apps(name);
+        let mut authentication = Authentication::new(|credentials, _info| async move {
+            match credentials.as_str() {
+                Some("alice") => Ok("alice-id".to_string()),
+                Some("slow") => {
+                    Timer::after(Duration::from_secs(5)).await;
+                    Ok("slow-id".to_string())
+                }
+                _ => Err("unknown user".to_string()),
+            }
+        });
+        authentication.timeout = Duration::from_millis(300);
+        server.insert_resource(authentication);
+        track_clients(&mut server);
+        (server, client, settings)
+    }
+
+    /// Why the server dropped a raw client, once it did
+    fn dropped_because(server: &mut App, socket: &MemorySocket) -> DisconnectReason {
+        let received = receive_until(server, socket, has(PacketKind::Disconnect));
+        let announcement = received
+            .iter()
+            .find(|packet| packet.kind == PacketKind::Disconnect)
+            .expect("server said why");
+        DisconnectReason::from_announcement(&announcement.data).expect("reason can be read")
+    }
+
+    #[test]
+    fn accepted_clients_get_in_with_their_identity() {
+        let (mut server, mut client, settings) = authenticating_apps("memory-tests-auth-accept");
+        client.insert_resource(Credentials::new("alice"));
+        connect(&mut server, &mut client, &settings);
+
+        run_until(&mut server, &mut client, |_, client| client.len() == 2);
+        assert_eq!(
+            client.world.resource::<Log>().0,
+            ["client connected", "client got welcome alice"]
+        );
+        let conn_id = server.world.resource::<Vec<ConnectionId>>()[0];
+        let identity = server
+            .world
+            .resource::<NetworkServer<MemoryServerProvider>>()
+            .identity(conn_id);
+        assert_eq!(identity.as_deref(), Some("alice-id"));
+    }
+
+    #[test]
+    fn rejected_clients_are_told_why() {
+        let (mut server, mut client, settings) = authenticating_apps("memory-tests-auth-reject");
+        client.insert_resource(Credentials::new("bob"));
+        connect(&mut server, &mut client, &settings);
+
+        let rejected = format!(
+            "client error {}",
+            NetworkError::Unauthorized(
+                ConnectionId::server(),
+                AuthenticationError::Rejected("unknown user".into())
+            )
+        );
+        run_until(&mut server, &mut client, |server, client| {
+            !server.is_empty() && client.contains(&rejected)
+        });
+        assert_eq!(client.world.resource::<Log>().0, [rejected]);
+        let server_log = &server.world.resource::<Log>().0;
+        assert_eq!(server_log.len(), 1);
+        assert!(server_log[0].starts_with("server error Authentication of"));
+    }
+
+    #[test]
+    fn slow_validators_hold_up_no_one_else() {
+        let (mut server, mut client, settings) = authenticating_apps("memory-tests-auth-slow");
+        client.insert_resource(Credentials::new("alice"));
+        listen(&mut server, &settings);
+        let slow = raw_connect(
+            &settings,
+            vec![Credentials::new("slow").announcement(), registry()],
+        );
+        connect_client(&mut client, &settings);
+
+        run_until(&mut server, &mut client, |_, client| client.len() == 2);
+        assert_eq!(
+            dropped_because(&mut server, &slow),
+            DisconnectReason::Rejected(AuthenticationError::TimedOut.to_string())
+        );
+    }
+
+    #[test]
+    fn clients_that_never_log_in_are_dropped() {
+        let (mut server, _, settings) = authenticating_apps("memory-tests-auth-idle");
+        server
+            .world
+            .resource_mut::<Authentication>()
+            .admission_timeout = Duration::from_millis(50);
+        listen(&mut server, &settings);
+        let idle = raw_connect(&settings, Vec::new());
+
+        assert_eq!(
+            dropped_because(&mut server, &idle),
+            DisconnectReason::Rejected(AuthenticationError::TimedOut.to_string())
+        );
+    }
+
+    #[test]
+    fn clients_may_not_send_transfers_before_they_are_let_in() {
+        let (mut server, _, settings) = authenticating_apps("memory-tests-auth-chunk");
+        listen(&mut server, &settings);
+        let chunk = NetworkPacket {
+            kind: PacketKind::Chunk,
+            data: Bytes::from_static(&[0; 64]),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        };
+        let eager = raw_connect(&settings, vec![chunk]);
+
+        assert!(matches!(
+            dropped_because(&mut server, &eager),
+            DisconnectReason::Rejected(_)
+        ));
+    }
+}
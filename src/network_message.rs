@@ -1,10 +1,57 @@
 use serde::{de::DeserializeOwned, Serialize};
 
-/// Any type that should be sent over the wire has to implement [`NetworkMessage`].
+use crate::Channel;
+
+/// How a message is sent, chosen by the side that listens for it when registering it
+///
+/// The options are announced to the peer along with the message, so the sending side
+/// follows them without having to know them.
+///
+/// ## Example
+/// ```rust,ignore
+/// const POSITIONS: Channel = Channel::new("positions", Delivery::UnreliableSequenced);
+///
+/// app.listen_for_server_message_with_options::<PlayerPosition, TcpProvider, JsonCodec>(
+///     MessageOptions::default().with_channel(POSITIONS),
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageOptions {
+    /// The channel the message is sent on, which decides how reliably it is delivered
+    ///
+    /// ## Default
+    /// The default is [`Channel::DEFAULT`], which is reliable and ordered
+    pub channel: Channel,
+
+    /// Whether the message is compressed when it's large, see [`Compression`](crate::Compression)
+    ///
+    /// ## Default
+    /// The default is `false`
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+    pub compress: bool,
+}
+
+impl MessageOptions {
+    /// Send the message on `channel`
+    pub fn with_channel(mut self, channel: Channel) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// Opt the message in or out of being compressed
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+}
+
+/// Any type that should be sent over the wire has to implement [`ServerMessage`] or [`ClientMessage`] (or both)
+/// to signal which direction this message can be sent.
 ///
 /// ## Example
 /// ```rust
-/// use bevy_spicy_networking::NetworkMessage;
+/// use bevy_eventwork::ServerMessage;
 /// use serde::{Serialize, Deserialize};
 ///
 /// #[derive(Serialize, Deserialize)]
@@ -13,12 +60,10 @@ use serde::{de::DeserializeOwned, Serialize};
 ///     position: (u32, u32, u32)
 /// }
 ///
-/// #[typetag::serde]
-/// impl NetworkMessage for PlayerInformation {}
+/// impl ServerMessage for PlayerInformation {
+///     const NAME: &'static str = "example:PlayerInformation";
+/// }
 /// ```
-/// You will also have to mark it with either [`ServerMessage`] or [`ClientMessage`] (or both)
-/// to signal which direction this message can be sent.
-
 /**
 A marker trait to signal that this message should be sent *to* a server
 
@@ -46,3 +91,21 @@ pub trait ClientMessage: Serialize + DeserializeOwned + Send + Sync + 'static {
     /// A good combination is crate name + struct name
     const NAME: &'static str;
 }
+
+/**
+A [`ServerMessage`] that the server answers with a [`RequestMessage::Response`]
+
+Clients send it with [`NetworkClient::request`](crate::NetworkClient::request), servers receive
+it as a [`NetworkRequest`](crate::NetworkRequest) and answer with
+[`NetworkRequest::respond`](crate::NetworkRequest::respond).
+
+## Note
+
+Register it with `listen_for_server_request` on the server, instead of listening for it as a
+message, and with `listen_for_client_response` on the client. Responses are matched to their
+request, so the response type may still be listened for as a client message of its own.
+*/
+pub trait RequestMessage: ServerMessage {
+    /// What the server answers with
+    type Response: ClientMessage;
+}
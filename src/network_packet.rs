@@ -0,0 +1,261 @@
+use std::fmt::Debug;
+
+use bytes::{BufMut, Bytes};
+
+use crate::{error::NetworkError, Channel};
+
+/// A message sent by the name it was registered with
+const BY_NAME: u8 = 0;
+/// A message sent by the id the peer assigned to its name
+const BY_ID: u8 = 1;
+/// The peer's list of registered messages
+const REGISTRY: u8 = 2;
+/// The peer's protocol version
+const HANDSHAKE: u8 = 3;
+/// Sent when there was nothing else to send for a while
+const HEARTBEAT: u8 = 4;
+/// The last packet on a connection, telling the peer why it ends
+const DISCONNECT: u8 = 5;
+/// The token a client can resume its session with
+const SESSION: u8 = 6;
+/// A client asking to resume its last session
+const RESUME: u8 = 7;
+/// The answer to a request, routed by the request id in front of it
+const RESPONSE: u8 = 8;
+/// A piece of a message that is too large to be sent at once
+const CHUNK: u8 = 9;
+/// The credentials a client presents to be let in
+const AUTH: u8 = 10;
+/// Tells a client that it was let in
+const ADMITTED: u8 = 11;
+/// Set on the packet type when the content is compressed
+const COMPRESSED: u8 = 0x80;
+
+/// What a [`NetworkPacket`] carries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PacketKind {
+    /// A message, identified by its full name
+    Name(String),
+    /// A message, identified by the id the receiving side assigned to it
+    Id(u16),
+    /// The messages the sender is listening for, see [`MessageRegistry`](crate::registry::MessageRegistry)
+    Registry,
+    /// The sender's protocol version, see [`Handshake`](crate::Handshake)
+    Handshake,
+    /// Keeps the connection from timing out, see [`Heartbeat`](crate::Heartbeat)
+    Heartbeat,
+    /// Ends the connection, see [`DisconnectReason`](crate::DisconnectReason)
+    Disconnect,
+    /// The token to resume a session with, see [`SessionResumption`](crate::SessionResumption)
+    Session,
+    /// Presents the token of the last session, to resume it
+    Resume,
+    /// Answers a request, see [`RequestMessage`](crate::RequestMessage)
+    Response,
+    /// A piece of a transfer, see [`Transfers`](crate::Transfers)
+    Chunk,
+    /// A client's credentials, see [`Authentication`](crate::Authentication)
+    Auth,
+    /// The server let the client in, it may start sending messages
+    Admitted,
+}
+
+/// [`NetworkPacket`]s are untyped packets to be sent over the wire
+///
+/// ## Wire format
+/// Every packet starts with a small header, followed by its content as is:
+///
+/// | Bytes | Content                        |
+/// |:-----:|:------------------------------:|
+/// | 1     | The packet type, the highest bit is set if the content is compressed |
+/// | 2     | Only for messages: the id, or the length of the name, little endian |
+/// | n     | Only for messages sent by name: the name, as UTF-8 |
+/// | rest  | The message, encoded by the [`Codec`](crate::Codec) |
+///
+/// The [`Channel`] is not part of it, providers that honour it put what they need on the wire.
+pub struct NetworkPacket {
+    pub(crate) kind: PacketKind,
+    pub(crate) data: Bytes,
+    pub(crate) channel: Channel,
+    /// Whether `data` is compressed, see [`Compression`](crate::Compression)
+    pub(crate) compressed: bool,
+}
+
+impl NetworkPacket {
+    /// The channel the packet is sent on, so providers know how to deliver it
+    ///
+    /// Packets decoded from the wire are always on [`Channel::DEFAULT`]
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// Encode the packet into its wire format
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(3 + self.data.len());
+        let flags = if self.compressed { COMPRESSED } else { 0 };
+        match &self.kind {
+            PacketKind::Name(name) => {
+                encoded.put_u8(BY_NAME | flags);
+                // Message names are checked against this limit when they are registered
+                encoded.put_u16_le(name.len() as u16);
+                encoded.put_slice(name.as_bytes());
+            }
+            PacketKind::Id(id) => {
+                encoded.put_u8(BY_ID | flags);
+                encoded.put_u16_le(*id);
+            }
+            PacketKind::Registry => encoded.put_u8(REGISTRY | flags),
+            PacketKind::Handshake => encoded.put_u8(HANDSHAKE | flags),
+            PacketKind::Heartbeat => encoded.put_u8(HEARTBEAT | flags),
+            PacketKind::Disconnect => encoded.put_u8(DISCONNECT | flags),
+            PacketKind::Session => encoded.put_u8(SESSION | flags),
+            PacketKind::Resume => encoded.put_u8(RESUME | flags),
+            PacketKind::Response => encoded.put_u8(RESPONSE | flags),
+            PacketKind::Chunk => encoded.put_u8(CHUNK | flags),
+            PacketKind::Auth => encoded.put_u8(AUTH | flags),
+            PacketKind::Admitted => encoded.put_u8(ADMITTED | flags),
+        }
+        encoded.put_slice(&self.data);
+        encoded
+    }
+
+    /// Decode a packet from its wire format
+    ///
+    /// The content itself is not copied, it keeps pointing into `encoded`.
+    pub fn decode(encoded: impl Into<Bytes>) -> Result<Self, NetworkError> {
+        let encoded = encoded.into();
+
+        let read_u16 = |at: usize| {
+            encoded
+                .get(at..at + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .ok_or(NetworkError::MalformedPacket("header is cut off"))
+        };
+
+        let compressed = encoded.first().is_some_and(|kind| kind & COMPRESSED != 0);
+        let (kind, header_length) = match encoded.first().map(|kind| kind & !COMPRESSED) {
+            Some(BY_NAME) => {
+                let name_end = 3 + read_u16(1)? as usize;
+                let name = encoded
+                    .get(3..name_end)
+                    .ok_or(NetworkError::MalformedPacket("name is cut off"))?;
+                let name = std::str::from_utf8(name)
+                    .map_err(|_| NetworkError::MalformedPacket("name is not valid UTF-8"))?;
+                (PacketKind::Name(name.to_owned()), name_end)
+            }
+            Some(BY_ID) => (PacketKind::Id(read_u16(1)?), 3),
+            Some(REGISTRY) => (PacketKind::Registry, 1),
+            Some(HANDSHAKE) => (PacketKind::Handshake, 1),
+            Some(HEARTBEAT) => (PacketKind::Heartbeat, 1),
+            Some(DISCONNECT) => (PacketKind::Disconnect, 1),
+            Some(SESSION) => (PacketKind::Session, 1),
+            Some(RESUME) => (PacketKind::Resume, 1),
+            Some(RESPONSE) => (PacketKind::Response, 1),
+            Some(CHUNK) => (PacketKind::Chunk, 1),
+            Some(AUTH) => (PacketKind::Auth, 1),
+            Some(ADMITTED) => (PacketKind::Admitted, 1),
+            Some(_) => return Err(NetworkError::MalformedPacket("unknown packet type")),
+            None => return Err(NetworkError::MalformedPacket("packet is empty")),
+        };
+
+        Ok(Self {
+            kind,
+            data: encoded.slice(header_length..),
+            channel: Channel::DEFAULT,
+            compressed,
+        })
+    }
+
+    /// Whether `name` fits into the packet header
+    pub(crate) fn is_valid_name(name: &str) -> bool {
+        name.len() <= u16::MAX as usize
+    }
+}
+
+impl Debug for NetworkPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NetworkPacket")
+            .field("kind", &self.kind)
+            .field("length", &self.data.len())
+            .field("channel", &self.channel.name())
+            .field("compressed", &self.compressed)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(kind: PacketKind, compressed: bool) {
+        let packet = NetworkPacket {
+            kind: kind.clone(),
+            data: Bytes::from_static(b"\x00\x01payload\xff"),
+            channel: Channel::DEFAULT,
+            compressed,
+        };
+
+        let decoded = NetworkPacket::decode(packet.encode()).expect("packet should decode");
+        assert_eq!(decoded.kind, kind);
+        assert_eq!(decoded.data, packet.data);
+        assert_eq!(decoded.compressed, compressed);
+    }
+
+    #[test]
+    fn every_kind_survives_the_wire() {
+        let kinds = [
+            PacketKind::Name("example:Message".to_owned()),
+            PacketKind::Name(String::new()),
+            PacketKind::Id(0),
+            PacketKind::Id(u16::MAX),
+            PacketKind::Registry,
+            PacketKind::Handshake,
+            PacketKind::Heartbeat,
+            PacketKind::Disconnect,
+            PacketKind::Session,
+            PacketKind::Resume,
+            PacketKind::Chunk,
+            PacketKind::Auth,
+            PacketKind::Admitted,
+            PacketKind::Response,
+        ];
+
+        for kind in kinds {
+            round_trip(kind.clone(), false);
+            round_trip(kind, true);
+        }
+    }
+
+    #[test]
+    fn empty_content_survives_the_wire() {
+        let packet = NetworkPacket {
+            kind: PacketKind::Id(7),
+            data: Bytes::new(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        };
+
+        let encoded = packet.encode();
+        assert_eq!(encoded, [BY_ID, 7, 0]);
+        let decoded = NetworkPacket::decode(encoded).expect("packet should decode");
+        assert!(decoded.data.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        let malformed: [&[u8]; 5] = [
+            &[],
+            &[0x7f],
+            &[BY_ID, 1],
+            &[BY_NAME, 5, 0, b'a'],
+            &[BY_NAME, 1, 0, 0xff],
+        ];
+
+        for encoded in malformed {
+            assert!(matches!(
+                NetworkPacket::decode(encoded.to_vec()),
+                Err(NetworkError::MalformedPacket(_))
+            ));
+        }
+    }
+}
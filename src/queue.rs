@@ -0,0 +1,407 @@
+use std::future::Future;
+
+use async_channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use futures_lite::future;
+
+use crate::NetworkPacket;
+
+/// Insert this as a resource, on the server or the client, to limit how many packets
+/// may wait to be sent on a connection.
+///
+/// Without it every connection queues up whatever it is given, so a peer that stops
+/// reading lets the queue, and with it memory use, grow without bound.
+#[derive(Debug, Clone)]
+pub struct SendQueue {
+    /// How many packets of each [`Priority`] may wait to be sent on a single connection
+    ///
+    /// Every priority has a queue of its own, so up to three times as many packets may wait in
+    /// total. The packets a connection needs to get going, like its handshake, don't count towards it.
+    ///
+    /// ## Default
+    /// The default is set to 1024
+    pub capacity_per_priority: usize,
+
+    /// What happens to a packet that doesn't fit into the queue anymore
+    ///
+    /// ## Default
+    /// The default is [`QueuePolicy::Error`]
+    pub when_full: QueuePolicy,
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        Self {
+            capacity_per_priority: 1024,
+            when_full: QueuePolicy::Error,
+        }
+    }
+}
+
+/// What to do when a packet is sent on a connection whose [`SendQueue`] is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Make room by dropping the packet that has been waiting the longest
+    DropOldest,
+    /// Drop the packet that was just sent, sending still succeeds
+    DropNewest,
+    /// Drop the connection, see [`DisconnectReason::QueueFull`](crate::DisconnectReason::QueueFull)
+    Disconnect,
+    /// Fail sending with [`NetworkError::QueueFull`](crate::error::NetworkError::QueueFull)
+    Error,
+}
+
+/// How urgently a packet has to be sent, compared to the others waiting on its connection
+///
+/// Packets with a higher priority are always sent first, those with the same one in order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// For bulk transfers that may wait, like map chunks
+    Low,
+    /// What messages are sent with, unless they ask for something else
+    #[default]
+    Normal,
+    /// For messages that must not wait behind anything else
+    High,
+}
+
+impl Priority {
+    /// Every priority, highest first
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    fn index(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// Why a packet could not be queued
+#[derive(Debug)]
+pub(crate) enum QueueError {
+    /// The connection has stopped sending
+    Closed,
+    /// The queue is full, see [`QueuePolicy::Error`]
+    Full,
+    /// The queue is full, and the connection has to go, see [`QueuePolicy::Disconnect`]
+    Disconnect,
+}
+
+/// The queues of packets waiting to be sent on a connection, one per [`Priority`]
+#[derive(Debug)]
+pub(crate) struct Outgoing {
+    senders: [Sender<NetworkPacket>; 3],
+    /// For the packets the connection itself needs to get going, never limited by the [`SendQueue`]
+    control: Sender<NetworkPacket>,
+    /// The other end of bounded queues, to make room in them
+    queued: Option<Queued>,
+    when_full: QueuePolicy,
+}
+
+impl Outgoing {
+    /// Create the queues for a new connection, see [`Queued::relay`] for the other end
+    pub(crate) fn new(queue: Option<&SendQueue>) -> (Self, Queued) {
+        let channel = || match queue {
+            Some(queue) => bounded(queue.capacity_per_priority.max(1)),
+            None => unbounded(),
+        };
+        let (high, normal, low) = (channel(), channel(), channel());
+        let (control_tx, control_rx) = unbounded();
+
+        let queued = Queued {
+            receivers: [high.1, normal.1, low.1],
+            control: control_rx,
+        };
+        let outgoing = Self {
+            senders: [high.0, normal.0, low.0],
+            control: control_tx,
+            queued: queue.map(|_| queued.clone()),
+            // Unbounded queues are never full, so the policy doesn't matter for them
+            when_full: queue.map_or(QueuePolicy::Error, |queue| queue.when_full),
+        };
+        (outgoing, queued)
+    }
+
+    /// Queue a packet, following the [`QueuePolicy`] once its queue is full
+    pub(crate) fn send(&self, priority: Priority, packet: NetworkPacket) -> Result<(), QueueError> {
+        let packet = match self.senders[priority.index()].try_send(packet) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Closed(_)) => return Err(QueueError::Closed),
+            Err(TrySendError::Full(packet)) => packet,
+        };
+
+        match self.when_full {
+            QueuePolicy::DropOldest => self.force(priority, packet),
+            QueuePolicy::DropNewest => Ok(()),
+            QueuePolicy::Disconnect => Err(QueueError::Disconnect),
+            QueuePolicy::Error => Err(QueueError::Full),
+        }
+    }
+
+    /// Queue a packet, dropping the oldest one of the same priority if there is no room for it
+    pub(crate) fn force(
+        &self,
+        priority: Priority,
+        mut packet: NetworkPacket,
+    ) -> Result<(), QueueError> {
+        loop {
+            match self.senders[priority.index()].try_send(packet) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(_)) => return Err(QueueError::Closed),
+                Err(TrySendError::Full(full)) => packet = full,
+            }
+
+            match self.queued.as_ref() {
+                // Either this drops the oldest packet, or the relay just took it
+                Some(queued) => drop(queued.receivers[priority.index()].try_recv()),
+                None => return Err(QueueError::Full),
+            }
+        }
+    }
+
+    /// Stop taking new packets, those already queued are still sent
+    pub(crate) fn close(&self) {
+        for sender in &self.senders {
+            sender.close();
+        }
+        self.control.close();
+    }
+
+    /// Queue a packet the connection needs to get going, like its handshake, ahead of everything else
+    ///
+    /// These always fit, however small the [`SendQueue`] is.
+    pub(crate) fn send_control(&self, packet: NetworkPacket) -> Result<(), QueueError> {
+        self.control
+            .try_send(packet)
+            .map_err(|_| QueueError::Closed)
+    }
+
+    /// A sender that skips the [`QueuePolicy`], for packets the connection itself sends
+    pub(crate) fn sender(&self, priority: Priority) -> Sender<NetworkPacket> {
+        self.senders[priority.index()].clone()
+    }
+}
+
+/// The other end of an [`Outgoing`] queue
+#[derive(Debug, Clone)]
+pub(crate) struct Queued {
+    /// Highest priority first
+    receivers: [Receiver<NetworkPacket>; 3],
+    /// Belongs to this very connection, so it isn't handed over when a session is resumed
+    control: Receiver<NetworkPacket>,
+}
+
+impl Queued {
+    /// Take the next message to send, if any is waiting
+    ///
+    /// Control packets are left alone, see [`Outgoing::send_control`].
+    pub(crate) fn try_recv(&self) -> Option<(Priority, NetworkPacket)> {
+        Priority::ALL.into_iter().find_map(|priority| {
+            Some((priority, self.receivers[priority.index()].try_recv().ok()?))
+        })
+    }
+
+    /// Hand the packets over one by one, control packets first, then the highest priority
+    ///
+    /// The chunks of `transfers` only go out when no other packet is waiting.
+    /// The returned receiver is for the provider's `send_loop`, the future has to run alongside it.
+    pub(crate) fn relay(
+        self,
+        transfers: Receiver<NetworkPacket>,
+    ) -> (Receiver<NetworkPacket>, impl Future<Output = ()>) {
+        // Only holds a single packet, so whatever comes in later can still overtake the rest
+        let (relayed_tx, relayed_rx) = bounded(1);
+
+        let relay_loop = async move {
+            loop {
+                let next = self
+                    .control
+                    .try_recv()
+                    .ok()
+                    .or_else(|| self.try_recv().map(|(_, packet)| packet))
+                    .or_else(|| transfers.try_recv().ok());
+                // All queues are closed at once
+                let closed = self.control.is_closed();
+                let packet = match next {
+                    Some(packet) => packet,
+                    None if closed && transfers.is_closed() => break,
+                    None => {
+                        let [high, normal, low] = &self.receivers;
+                        let queued = future::or(
+                            self.control.recv(),
+                            future::or(high.recv(), future::or(normal.recv(), low.recv())),
+                        );
+                        let received = if transfers.is_closed() {
+                            queued.await
+                        } else if closed {
+                            transfers.recv().await
+                        } else {
+                            future::or(queued, transfers.recv()).await
+                        };
+                        match received {
+                            Ok(packet) => packet,
+                            Err(_) => continue,
+                        }
+                    }
+                };
+
+                if relayed_tx.send(packet).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        (relayed_rx, relay_loop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{network_packet::PacketKind, Channel};
+
+    use super::*;
+
+    fn packet(id: u16) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Id(id),
+            data: Default::default(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    fn bounded_queue(capacity_per_priority: usize, when_full: QueuePolicy) -> (Outgoing, Queued) {
+        Outgoing::new(Some(&SendQueue {
+            capacity_per_priority,
+            when_full,
+        }))
+    }
+
+    /// The ids of every packet that is still waiting, in the order the relay sends them
+    fn drain(outgoing: Outgoing, queued: Queued, transfers: Receiver<NetworkPacket>) -> Vec<u16> {
+        outgoing.close();
+        let (relayed, relay) = queued.relay(transfers);
+
+        let collect = async {
+            let mut ids = Vec::new();
+            while let Ok(packet) = relayed.recv().await {
+                match packet.kind {
+                    PacketKind::Id(id) => ids.push(id),
+                    kind => panic!("unexpected packet: {:?}", kind),
+                }
+            }
+            ids
+        };
+        future::block_on(future::zip(relay, collect)).1
+    }
+
+    #[test]
+    fn sends_control_packets_and_higher_priorities_first() {
+        let (outgoing, queued) = Outgoing::new(None);
+        let (transfers_tx, transfers_rx) = unbounded();
+
+        transfers_tx
+            .try_send(packet(9))
+            .expect("transfers are open");
+        transfers_tx.close();
+        outgoing
+            .send(Priority::Low, packet(5))
+            .expect("queue is open");
+        outgoing
+            .send(Priority::Normal, packet(3))
+            .expect("queue is open");
+        outgoing
+            .send(Priority::High, packet(1))
+            .expect("queue is open");
+        outgoing
+            .send(Priority::Normal, packet(4))
+            .expect("queue is open");
+        outgoing
+            .send(Priority::High, packet(2))
+            .expect("queue is open");
+        outgoing.send_control(packet(0)).expect("queue is open");
+
+        assert_eq!(drain(outgoing, queued, transfers_rx), [0, 1, 2, 3, 4, 5, 9]);
+    }
+
+    #[test]
+    fn full_queues_follow_their_policy() {
+        let fill = |when_full| {
+            let (outgoing, queued) = bounded_queue(2, when_full);
+            let results: Vec<_> = (1..=3)
+                .map(|id| outgoing.send(Priority::Normal, packet(id)))
+                .collect();
+            let (_, transfers) = unbounded();
+            (results, drain(outgoing, queued, transfers))
+        };
+
+        let (results, sent) = fill(QueuePolicy::DropOldest);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(sent, [2, 3]);
+
+        let (results, sent) = fill(QueuePolicy::DropNewest);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(sent, [1, 2]);
+
+        let (results, sent) = fill(QueuePolicy::Error);
+        assert!(matches!(results[2], Err(QueueError::Full)));
+        assert_eq!(sent, [1, 2]);
+
+        let (results, _) = fill(QueuePolicy::Disconnect);
+        assert!(matches!(results[2], Err(QueueError::Disconnect)));
+    }
+
+    #[test]
+    fn priorities_are_limited_on_their_own() {
+        let (outgoing, queued) = bounded_queue(1, QueuePolicy::Error);
+
+        outgoing
+            .send(Priority::Low, packet(2))
+            .expect("low has room");
+        outgoing
+            .send(Priority::High, packet(1))
+            .expect("high has room");
+        assert!(matches!(
+            outgoing.send(Priority::High, packet(3)),
+            Err(QueueError::Full)
+        ));
+
+        let (_, transfers) = unbounded();
+        assert_eq!(drain(outgoing, queued, transfers), [1, 2]);
+    }
+
+    #[test]
+    fn control_packets_always_fit() {
+        let (outgoing, queued) = bounded_queue(1, QueuePolicy::Error);
+
+        for id in 0..10 {
+            outgoing
+                .send_control(packet(id))
+                .expect("control always fits");
+        }
+        // Control packets aren't handed over when a session is resumed
+        assert!(queued.try_recv().is_none());
+
+        let (_, transfers) = unbounded();
+        assert_eq!(
+            drain(outgoing, queued, transfers),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn closed_queues_refuse_packets() {
+        let (outgoing, _queued) = Outgoing::new(None);
+        outgoing.close();
+
+        assert!(matches!(
+            outgoing.send(Priority::Normal, packet(1)),
+            Err(QueueError::Closed)
+        ));
+        assert!(matches!(
+            outgoing.send_control(packet(1)),
+            Err(QueueError::Closed)
+        ));
+    }
+}
@@ -0,0 +1,867 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    async_channel::{Receiver, Sender},
+    async_trait,
+    channel::Sequences,
+    client::NetworkClientProvider,
+    error::NetworkError,
+    server::NetworkServerProvider,
+    ClientNetworkEvent, Delivery, DisconnectReason, NetworkPacket,
+};
+use async_io::Timer;
+use bevy::log::{debug, error, info, trace};
+use bytes::Bytes;
+use futures_lite::{future, StreamExt};
+use futures_util::stream::FuturesUnordered;
+use quinn::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+        RootCertStore,
+    },
+    ClientConfig, Connection, Endpoint, ReadToEndError, RecvStream, SendStream, ServerConfig,
+};
+
+/// Written by the client when it opens its stream, QUIC only tells the
+/// server about a new stream once something was sent on it.
+const STREAM_PREAMBLE: u8 = 0;
+
+/// A datagram carrying an encoded [`NetworkPacket`] as is.
+const UNRELIABLE: u8 = 0;
+/// A datagram numbered per channel, so stale packets can be dropped.
+const SEQUENCED: u8 = 1;
+
+/// Kind, channel id and sequence number in front of every [`SEQUENCED`] datagram.
+const SEQUENCED_HEADER_LEN: usize = 9;
+
+#[derive(Default, Debug)]
+/// Provides a QUIC endpoint for eventwork.
+///
+/// Every client opens a single bidirectional stream, packets on it are framed like in the tcp provider.
+///
+/// Packets on a [`Delivery::ReliableUnordered`] channel get a unidirectional stream each,
+/// unreliable ones are sent as QUIC datagrams. Packets too large for a datagram go on the stream.
+pub struct QuicServerProvider;
+
+#[async_trait]
+impl NetworkServerProvider for QuicServerProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = QuicConnection;
+
+    type ReadHalf = QuicReadHalf;
+
+    type WriteHalf = QuicWriteHalf;
+
+    async fn accept_loop(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<NetworkError>,
+    ) {
+        let endpoint = match network_settings
+            .server_config()
+            .and_then(|config| Endpoint::server(config, network_settings.addr))
+        {
+            Ok(endpoint) => endpoint,
+            Err(err) => {
+                if let Err(err) = errors.send(NetworkError::Listen(err)).await {
+                    error!("Could not send listen error: {}", err);
+                }
+                return;
+            }
+        };
+
+        enum Accepted {
+            Incoming(Option<Box<quinn::Incoming>>),
+            Established(io::Result<QuicConnection>),
+        }
+
+        // Handshakes run concurrently, so a slow client can't hold up everyone else,
+        // and they time out, so one that never finishes doesn't stick around forever
+        let mut handshakes = FuturesUnordered::new();
+        loop {
+            let accepted = future::or(
+                async { Accepted::Incoming(endpoint.accept().await.map(Box::new)) },
+                async {
+                    match handshakes.next().await {
+                        Some(established) => Accepted::Established(established),
+                        None => future::pending().await,
+                    }
+                },
+            )
+            .await;
+
+            let error = match accepted {
+                Accepted::Incoming(Some(incoming)) => {
+                    let timeout = network_settings.handshake_timeout;
+                    handshakes.push(future::or(
+                        accept(endpoint.clone(), *incoming),
+                        async move {
+                            Timer::after(timeout).await;
+                            Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "The QUIC handshake took too long",
+                            ))
+                        },
+                    ));
+                    continue;
+                }
+                Accepted::Incoming(None) => {
+                    error!("QUIC endpoint was closed");
+                    return;
+                }
+                Accepted::Established(Ok(connection)) => {
+                    if let Err(err) = new_connections.send(connection).await {
+                        error!("Could not send listen error: {}", err);
+                        return;
+                    }
+                    info!("New Connection Made!");
+                    continue;
+                }
+                Accepted::Established(Err(error)) => error,
+            };
+
+            if let Err(err) = errors.send(NetworkError::Accept(error)).await {
+                error!("Could not send listen error: {}", err);
+                return;
+            };
+        }
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        combined.split()
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        Some(socket.connection.remote_address())
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.endpoint.local_addr().ok()
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides a QUIC connection for eventwork.
+pub struct QuicClientProvider;
+
+#[async_trait]
+impl NetworkClientProvider for QuicClientProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = QuicConnection;
+
+    type ReadHalf = QuicReadHalf;
+
+    type WriteHalf = QuicWriteHalf;
+
+    async fn connect_task(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<ClientNetworkEvent>,
+    ) {
+        info!("Beginning connection");
+        let connection = match connect(&network_settings).await {
+            Ok(connection) => connection,
+            Err(error) => {
+                match errors
+                    .send(ClientNetworkEvent::Error(NetworkError::Connection(error)))
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(err) => {
+                        error!("Could not send error event: {}", err);
+                    }
+                }
+
+                return;
+            }
+        };
+
+        info!("Connected!");
+
+        let addr = connection.connection.remote_address();
+
+        match new_connections.send(connection).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not initiate connection: {}", err);
+            }
+        }
+
+        debug!("Connected to: {:?}", addr);
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        combined.split()
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        Some(socket.connection.remote_address())
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.endpoint.local_addr().ok()
+    }
+}
+
+/// Finishes the QUIC handshake and waits for the client to open its stream.
+async fn accept(endpoint: Endpoint, incoming: quinn::Incoming) -> io::Result<QuicConnection> {
+    let connection = incoming.await?;
+    let (send, mut recv) = connection.accept_bi().await?;
+
+    let mut preamble = [0; 1];
+    recv.read_exact(&mut preamble)
+        .await
+        .map_err(io::Error::other)?;
+    if preamble[0] != STREAM_PREAMBLE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Client opened its stream with an unknown preamble",
+        ));
+    }
+
+    Ok(QuicConnection {
+        endpoint,
+        connection,
+        send,
+        recv,
+    })
+}
+
+async fn connect(settings: &NetworkSettings) -> io::Result<QuicConnection> {
+    let local_addr: SocketAddr = if settings.addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+
+    let mut endpoint = Endpoint::client(local_addr)?;
+    endpoint.set_default_client_config(settings.client_config()?);
+
+    let connection = endpoint
+        .connect(settings.addr, &settings.server_name)
+        .map_err(io::Error::other)?
+        .await?;
+    let (mut send, recv) = connection.open_bi().await?;
+    send.write_all(&[STREAM_PREAMBLE])
+        .await
+        .map_err(io::Error::other)?;
+
+    Ok(QuicConnection {
+        endpoint,
+        connection,
+        send,
+        recv,
+    })
+}
+
+async fn recv_loop(
+    read_half: QuicReadHalf,
+    messages: Sender<NetworkPacket>,
+    settings: NetworkSettings,
+) -> DisconnectReason {
+    let connection = read_half.connection.clone();
+
+    // Whichever stops first ends the connection
+    future::or(
+        recv_stream(read_half.recv, &messages, &settings),
+        future::or(
+            recv_unordered(&connection, &messages, &settings),
+            recv_datagrams(&connection, &messages, &settings),
+        ),
+    )
+    .await
+}
+
+/// Reads the packets on the stream opened with the connection, in order.
+async fn recv_stream(
+    mut recv: RecvStream,
+    messages: &Sender<NetworkPacket>,
+    settings: &NetworkSettings,
+) -> DisconnectReason {
+    loop {
+        trace!("Reading message length");
+        let mut length = [0; 8];
+        if let Err(err) = recv.read_exact(&mut length).await {
+            // Both a finished stream and a closed connection end up here
+            info!("Peer disconnected: {}", err);
+            return DisconnectReason::Eof;
+        }
+        let length = u64::from_le_bytes(length) as usize;
+        debug!("Receiving new message of size: {}", length);
+
+        if length > settings.max_packet_length {
+            error!(
+                "Received too large packet: {} > {}",
+                length, settings.max_packet_length
+            );
+            return DisconnectReason::Oversize;
+        }
+
+        trace!("Reading message into buffer");
+        let mut buffer = vec![0; length];
+        if let Err(err) = recv.read_exact(&mut buffer).await {
+            error!(
+                "Encountered error while fetching stream of length {}: {}",
+                length, err
+            );
+            return DisconnectReason::Eof;
+        }
+        trace!("Message read");
+
+        if let Err(reason) = forward(buffer, messages).await {
+            return reason;
+        }
+    }
+}
+
+/// Reads the packets that come in a unidirectional stream each, as soon as they're complete.
+async fn recv_unordered(
+    connection: &Connection,
+    messages: &Sender<NetworkPacket>,
+    settings: &NetworkSettings,
+) -> DisconnectReason {
+    enum Received {
+        Stream(Result<RecvStream, quinn::ConnectionError>),
+        Packet(Result<Vec<u8>, ReadToEndError>),
+    }
+
+    // Streams are read concurrently, so a lost packet doesn't hold up the others
+    let mut streams = FuturesUnordered::new();
+    loop {
+        let received = future::or(
+            async { Received::Stream(connection.accept_uni().await) },
+            async {
+                match streams.next().await {
+                    Some(packet) => Received::Packet(packet),
+                    None => future::pending().await,
+                }
+            },
+        )
+        .await;
+
+        match received {
+            Received::Stream(Ok(mut stream)) => {
+                let max_packet_length = settings.max_packet_length;
+                streams.push(async move { stream.read_to_end(max_packet_length).await });
+            }
+            Received::Stream(Err(err)) => {
+                info!("Peer disconnected: {}", err);
+                return DisconnectReason::Eof;
+            }
+            Received::Packet(Ok(packet)) => {
+                if let Err(reason) = forward(packet, messages).await {
+                    return reason;
+                }
+            }
+            Received::Packet(Err(ReadToEndError::TooLong)) => {
+                error!(
+                    "Received too large packet: > {}",
+                    settings.max_packet_length
+                );
+                return DisconnectReason::Oversize;
+            }
+            Received::Packet(Err(err)) => {
+                error!(
+                    "Encountered error while reading an unordered packet: {}",
+                    err
+                );
+                return DisconnectReason::Eof;
+            }
+        }
+    }
+}
+
+/// Reads the packets sent as datagrams, dropping stale ones.
+async fn recv_datagrams(
+    connection: &Connection,
+    messages: &Sender<NetworkPacket>,
+    settings: &NetworkSettings,
+) -> DisconnectReason {
+    let mut sequences = Sequences::default();
+    loop {
+        let datagram = match connection.read_datagram().await {
+            Ok(datagram) => datagram,
+            Err(err) => {
+                info!("Peer disconnected: {}", err);
+                return DisconnectReason::Eof;
+            }
+        };
+
+        if datagram.len() > settings.max_packet_length {
+            error!(
+                "Received too large packet: {} > {}",
+                datagram.len(),
+                settings.max_packet_length
+            );
+            return DisconnectReason::Oversize;
+        }
+
+        let packet = match datagram.first() {
+            Some(&UNRELIABLE) => datagram.slice(1..),
+            Some(&SEQUENCED) if datagram.len() >= SEQUENCED_HEADER_LEN => {
+                let channel = read_u32(&datagram[1..5]);
+                let seq = read_u32(&datagram[5..9]);
+
+                if !sequences.accept(channel, seq) {
+                    trace!("Dropping stale datagram {} on channel {}", seq, channel);
+                    continue;
+                }
+                datagram.slice(SEQUENCED_HEADER_LEN..)
+            }
+            _ => {
+                error!("Failed to decode network packet from: malformed datagram");
+                return DisconnectReason::DecodeError;
+            }
+        };
+
+        if let Err(reason) = forward(packet, messages).await {
+            return reason;
+        }
+    }
+}
+
+/// Decodes a complete packet and hands it over to eventwork.
+async fn forward(
+    encoded: impl Into<Bytes>,
+    messages: &Sender<NetworkPacket>,
+) -> Result<(), DisconnectReason> {
+    let packet = match NetworkPacket::decode(encoded) {
+        Ok(packet) => packet,
+        Err(err) => {
+            error!("Failed to decode network packet from: {}", err);
+            return Err(DisconnectReason::DecodeError);
+        }
+    };
+
+    if messages.send(packet).await.is_err() {
+        error!("Failed to send decoded message to eventwork");
+        return Err(DisconnectReason::Eof);
+    }
+    trace!("Message deserialized and sent to eventwork");
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut le_bytes = [0; 4];
+    le_bytes.copy_from_slice(bytes);
+    u32::from_le_bytes(le_bytes)
+}
+
+async fn send_loop(
+    mut write_half: QuicWriteHalf,
+    messages: Receiver<NetworkPacket>,
+    _settings: NetworkSettings,
+) {
+    let mut sequences = Sequences::default();
+    while let Ok(message) = messages.recv().await {
+        let encoded = message.encode();
+        let channel = message.channel();
+
+        let max_datagram_size = write_half.connection.max_datagram_size().unwrap_or(0);
+        let delivery = match channel.delivery() {
+            // Packets that don't fit into a datagram go on the stream, just like on tcp
+            Delivery::Unreliable | Delivery::UnreliableSequenced
+                if encoded.len() + SEQUENCED_HEADER_LEN > max_datagram_size =>
+            {
+                Delivery::ReliableOrdered
+            }
+            delivery => delivery,
+        };
+
+        match delivery {
+            Delivery::ReliableOrdered => (),
+            Delivery::ReliableUnordered => {
+                send_unordered(&write_half.connection, &encoded).await;
+                continue;
+            }
+            Delivery::Unreliable => {
+                let mut datagram = Vec::with_capacity(1 + encoded.len());
+                datagram.push(UNRELIABLE);
+                datagram.extend_from_slice(&encoded);
+                send_datagram(&write_half.connection, datagram);
+                continue;
+            }
+            Delivery::UnreliableSequenced => {
+                let seq = sequences.next(channel.id());
+
+                let mut datagram = Vec::with_capacity(SEQUENCED_HEADER_LEN + encoded.len());
+                datagram.push(SEQUENCED);
+                datagram.extend_from_slice(&channel.id().to_le_bytes());
+                datagram.extend_from_slice(&seq.to_le_bytes());
+                datagram.extend_from_slice(&encoded);
+                send_datagram(&write_half.connection, datagram);
+                continue;
+            }
+        }
+
+        let len = encoded.len() as u64;
+        debug!("Sending a new message of size: {}", len);
+
+        if let Err(err) = write_half.send.write_all(&len.to_le_bytes()).await {
+            error!("Could not send packet length: {:?}: {}", len, err);
+            break;
+        }
+
+        trace!("Sending the content of the message!");
+
+        if let Err(err) = write_half.send.write_all(&encoded).await {
+            error!("Could not send packet: {:?}: {}", message, err);
+            break;
+        }
+
+        trace!("Succesfully written all!");
+    }
+
+    let _ = write_half.send.finish();
+    // Dropping the connection right away could lose whatever is still in flight
+    let _ = write_half.send.stopped().await;
+}
+
+/// Sends a packet on a unidirectional stream of its own.
+async fn send_unordered(connection: &Connection, encoded: &[u8]) {
+    let mut stream = match connection.open_uni().await {
+        Ok(stream) => stream,
+        Err(err) => {
+            error!("Could not open a stream for an unordered packet: {}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = stream.write_all(encoded).await {
+        error!("Could not send unordered packet: {}", err);
+        return;
+    }
+    // The rest is sent in the background, even once the stream is dropped
+    let _ = stream.finish();
+}
+
+fn send_datagram(connection: &Connection, datagram: Vec<u8>) {
+    if let Err(err) = connection.send_datagram(datagram.into()) {
+        error!("Could not send datagram: {}", err);
+    }
+}
+
+#[derive(Debug)]
+/// A QUIC connection together with the stream eventwork sends its packets on.
+pub struct QuicConnection {
+    endpoint: Endpoint,
+    connection: Connection,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuicConnection {
+    /// The underlying QUIC connection
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    fn split(self) -> (QuicReadHalf, QuicWriteHalf) {
+        (
+            QuicReadHalf {
+                _endpoint: self.endpoint.clone(),
+                connection: self.connection.clone(),
+                recv: self.recv,
+            },
+            QuicWriteHalf {
+                _endpoint: self.endpoint,
+                connection: self.connection,
+                send: self.send,
+            },
+        )
+    }
+}
+
+#[derive(Debug)]
+/// The receiving half of a [`QuicConnection`].
+pub struct QuicReadHalf {
+    _endpoint: Endpoint,
+    /// The connection is closed once both halves are dropped.
+    connection: Connection,
+    recv: RecvStream,
+}
+
+#[derive(Debug)]
+/// The sending half of a [`QuicConnection`].
+pub struct QuicWriteHalf {
+    _endpoint: Endpoint,
+    /// The connection is closed once both halves are dropped.
+    connection: Connection,
+    send: SendStream,
+}
+
+#[derive(Clone, Debug)]
+/// Settings to configure the network, both client and server
+pub struct NetworkSettings {
+    /// Maximum packet size in bytes. If a client ever exceeds this size, they will be disconnected
+    ///
+    /// Larger messages can be sent in chunks instead, see [`Transfers`](crate::Transfers).
+    /// Every chunk is a packet of its own, so [`Transfers::chunk_size`](crate::Transfers::chunk_size)
+    /// has to stay a few bytes below this, to leave room for the headers. How large a whole
+    /// transfer may get is up to [`Transfers::max_length`](crate::Transfers::max_length) instead.
+    ///
+    /// ## Default
+    /// The default is set to 128KiB, twice the default chunk size
+    pub max_packet_length: usize,
+
+    /// Address to connect to or port to open
+    pub addr: SocketAddr,
+
+    /// The name the client expects in the server's certificate
+    ///
+    /// ## Default
+    /// The default is set to `localhost`
+    pub server_name: String,
+
+    /// The certificates presented by the server, starting with its own
+    pub certificate_chain: Vec<CertificateDer<'static>>,
+
+    /// The private key belonging to the server's certificate, only needed by the server
+    pub private_key: Option<Arc<PrivateKeyDer<'static>>>,
+
+    /// The certificates a client accepts as the root of the server's certificate chain
+    pub trusted_certificates: Vec<CertificateDer<'static>>,
+
+    /// How long a client may take to set up its connection and open its stream before it is dropped
+    ///
+    /// ## Default
+    /// The default is set to 10 seconds
+    pub handshake_timeout: Duration,
+}
+
+impl NetworkSettings {
+    /// Create a new instance of [`NetworkSettings`]
+    ///
+    /// ## Note
+    /// No certificates are set up, see [`NetworkSettings::self_signed`] for local development.
+    pub fn new(addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            max_packet_length: 128 * 1024,
+            addr: addr.into(),
+            server_name: String::from("localhost"),
+            certificate_chain: Vec::new(),
+            private_key: None,
+            trusted_certificates: Vec::new(),
+            handshake_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Create a new instance of [`NetworkSettings`] with a freshly generated certificate for `localhost`
+    ///
+    /// The certificate is both served and trusted, so the same settings work for a server and its clients.
+    pub fn self_signed(addr: impl Into<SocketAddr>) -> Result<Self, rcgen::Error> {
+        let certified = rcgen::generate_simple_self_signed(vec![String::from("localhost")])?;
+        let certificate = certified.cert.der().clone();
+        let private_key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der()).into();
+
+        Ok(Self {
+            certificate_chain: vec![certificate.clone()],
+            private_key: Some(Arc::new(private_key)),
+            trusted_certificates: vec![certificate],
+            ..Self::new(addr)
+        })
+    }
+
+    fn server_config(&self) -> io::Result<ServerConfig> {
+        let private_key = self.private_key.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A private key is needed to accept QUIC connections",
+            )
+        })?;
+
+        ServerConfig::with_single_cert(self.certificate_chain.clone(), private_key.clone_key())
+            .map_err(io::Error::other)
+    }
+
+    fn client_config(&self) -> io::Result<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for certificate in &self.trusted_certificates {
+            roots.add(certificate.clone()).map_err(io::Error::other)?;
+        }
+
+        ClientConfig::with_root_certificates(Arc::new(roots)).map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_channel::unbounded, network_packet::PacketKind, Channel};
+
+    /// Settings for a server on a free port of the loopback interface
+    fn settings() -> NetworkSettings {
+        let addr = std::net::UdpSocket::bind(("127.0.0.1", 0))
+            .and_then(|socket| socket.local_addr())
+            .expect("loopback is available");
+        NetworkSettings::self_signed(addr).expect("certificate can be generated")
+    }
+
+    fn packet(data: Vec<u8>) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Name("test".to_owned()),
+            data: data.into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Runs `test` next to a server, which binds before the test gets to connect
+    fn with_server(
+        settings: &NetworkSettings,
+        connections_tx: Sender<QuicConnection>,
+        test: impl future::Future<Output = ()>,
+    ) -> Receiver<NetworkError> {
+        let (errors_tx, errors_rx) = unbounded();
+        future::block_on(future::or(
+            async {
+                QuicServerProvider::accept_loop(settings.clone(), connections_tx, errors_tx).await;
+                panic!("server stopped");
+            },
+            test,
+        ));
+        errors_rx
+    }
+
+    /// Sends `packets` from a new client and returns what the server made of them
+    fn send_to_server(
+        settings: &NetworkSettings,
+        packets: Vec<NetworkPacket>,
+    ) -> (Vec<NetworkPacket>, DisconnectReason) {
+        let (connections_tx, connections_rx) = unbounded();
+        let (received_tx, received_rx) = unbounded();
+        let mut reason = None;
+
+        with_server(settings, connections_tx, async {
+            let client = connect(settings).await.expect("server is listening");
+            let accepted = connections_rx
+                .recv()
+                .await
+                .expect("server accepted the client");
+            let (_client_read, client_write) = client.split();
+            let (server_read, _server_write) = accepted.split();
+
+            let (messages_tx, messages_rx) = unbounded();
+            for packet in packets {
+                messages_tx.try_send(packet).expect("channel is open");
+            }
+            // The client finishes its stream once everything is sent
+            messages_tx.close();
+
+            reason = Some(
+                future::or(
+                    recv_loop(server_read, received_tx, settings.clone()),
+                    async {
+                        send_loop(client_write, messages_rx, settings.clone()).await;
+                        future::pending().await
+                    },
+                )
+                .await,
+            );
+        });
+
+        (
+            std::iter::from_fn(|| received_rx.try_recv().ok()).collect(),
+            reason.expect("test ran"),
+        )
+    }
+
+    #[test]
+    fn packets_arrive_until_the_client_disconnects() {
+        let (received, reason) = send_to_server(
+            &settings(),
+            vec![packet(vec![1, 2, 3]), packet(vec![4; 2000])],
+        );
+
+        assert_eq!(
+            received
+                .iter()
+                .map(NetworkPacket::encode)
+                .collect::<Vec<_>>(),
+            vec![
+                packet(vec![1, 2, 3]).encode(),
+                packet(vec![4; 2000]).encode()
+            ]
+        );
+        assert_eq!(reason, DisconnectReason::Eof);
+    }
+
+    #[test]
+    fn drops_clients_sending_too_large_packets() {
+        let settings = NetworkSettings {
+            max_packet_length: 100,
+            ..settings()
+        };
+        let (received, reason) = send_to_server(&settings, vec![packet(vec![0; 200])]);
+
+        assert!(received.is_empty());
+        assert_eq!(reason, DisconnectReason::Oversize);
+    }
+
+    #[test]
+    fn drops_clients_that_never_open_their_stream() {
+        let settings = NetworkSettings {
+            handshake_timeout: Duration::from_millis(200),
+            ..settings()
+        };
+        let (connections_tx, connections_rx) = unbounded();
+
+        let errors = with_server(&settings, connections_tx, async {
+            let mut endpoint =
+                Endpoint::client((Ipv4Addr::LOCALHOST, 0).into()).expect("loopback is available");
+            endpoint.set_default_client_config(settings.client_config().expect("valid config"));
+            let _connection = endpoint
+                .connect(settings.addr, &settings.server_name)
+                .expect("valid address")
+                .await
+                .expect("server finishes the QUIC handshake");
+
+            Timer::after(settings.handshake_timeout * 2).await;
+        });
+
+        assert!(connections_rx.is_empty());
+        match errors.try_recv().expect("server reports the timeout") {
+            NetworkError::Accept(err) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use crate::DisconnectReason;
+
+/// Insert this as a resource on the client to reconnect automatically after losing the server.
+///
+/// Only connections that broke are retried, see [`DisconnectReason::Eof`] and
+/// [`DisconnectReason::TimedOut`]. A client that left, was kicked, or whose server shut
+/// down stays disconnected. Each attempt is announced with
+/// [`ClientNetworkEvent::Reconnecting`](crate::ClientNetworkEvent::Reconnecting)
+/// and waits twice as long as the one before, up to [`Reconnect::max_delay`]. Attempts that fail
+/// are reported with [`ClientNetworkEvent::Error`](crate::ClientNetworkEvent::Error). After
+/// [`Reconnect::max_attempts`] failed attempts the client gives up with
+/// [`ClientNetworkEvent::ReconnectFailed`](crate::ClientNetworkEvent::ReconnectFailed).
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    /// How long to wait before the first attempt
+    ///
+    /// ## Default
+    /// The default is set to 500 milliseconds
+    pub initial_delay: Duration,
+
+    /// The longest to wait in between two attempts
+    ///
+    /// ## Default
+    /// The default is set to 30 seconds
+    pub max_delay: Duration,
+
+    /// How much every delay may randomly be shortened or lengthened, as a fraction of it,
+    /// so that clients dropped at the same time don't all come back at once
+    ///
+    /// ## Default
+    /// The default is set to 0.25
+    pub jitter: f32,
+
+    /// How many attempts to make before giving up
+    ///
+    /// ## Default
+    /// The default is set to 10
+    pub max_attempts: u32,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.25,
+            max_attempts: 10,
+        }
+    }
+}
+
+impl Reconnect {
+    /// The attempt after `previous`, or `None` once all attempts are used up
+    pub(crate) fn next_attempt(&self, previous: Option<u32>) -> Option<u32> {
+        let attempt = previous.map_or(1, |attempt| attempt + 1);
+        (attempt <= self.max_attempts).then_some(attempt)
+    }
+
+    /// How long to wait before the given attempt, starting at 1
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let backoff = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .initial_delay
+            .checked_mul(backoff)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        let jitter = self.jitter.clamp(0.0, 1.0) * (fastrand::f32() * 2.0 - 1.0);
+        delay.mul_f32(1.0 + jitter)
+    }
+}
+
+/// Whether the client tries to get back to the server after losing it for `reason`
+pub(crate) fn is_retried(reason: &DisconnectReason) -> bool {
+    matches!(reason, DisconnectReason::Eof | DisconnectReason::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn without_jitter() -> Reconnect {
+        Reconnect {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+            max_attempts: 3,
+        }
+    }
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let reconnect = without_jitter();
+
+        // Scaling by the jitter may be off by a few nanoseconds
+        let delays: Vec<_> = (1..=6)
+            .map(|attempt| reconnect.delay(attempt).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(reconnect.delay(u32::MAX).as_millis(), 1000);
+    }
+
+    #[test]
+    fn jitter_stays_within_its_fraction() {
+        let reconnect = Reconnect {
+            jitter: 0.25,
+            ..without_jitter()
+        };
+
+        for _ in 0..100 {
+            let delay = reconnect.delay(2);
+            assert!(delay >= Duration::from_millis(150));
+            assert!(delay <= Duration::from_millis(250));
+        }
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let reconnect = without_jitter();
+
+        assert_eq!(reconnect.next_attempt(None), Some(1));
+        assert_eq!(reconnect.next_attempt(Some(1)), Some(2));
+        assert_eq!(reconnect.next_attempt(Some(2)), Some(3));
+        assert_eq!(reconnect.next_attempt(Some(3)), None);
+    }
+
+    #[test]
+    fn only_broken_connections_are_retried() {
+        assert!(is_retried(&DisconnectReason::Eof));
+        assert!(is_retried(&DisconnectReason::TimedOut));
+
+        for reason in [
+            DisconnectReason::Kicked(String::from("bye")),
+            DisconnectReason::Shutdown,
+            DisconnectReason::Left,
+            DisconnectReason::Oversize,
+            DisconnectReason::DecodeError,
+            DisconnectReason::QueueFull,
+            DisconnectReason::Rejected(String::from("who are you")),
+        ] {
+            assert!(!is_retried(&reason), "{:?} is retried", reason);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut};
+use dashmap::DashMap;
+
+use crate::{
+    channel::Delivery, error::NetworkError, network_packet::PacketKind, Channel, MessageOptions,
+    NetworkPacket,
+};
+
+/// The messages one side listens for, and how it wants them to be sent.
+///
+/// Its position in the list is the id a message is sent with, once the peer got the
+/// list through [`MessageRegistry::announcement`].
+#[derive(Debug, Clone)]
+pub(crate) struct MessageRegistry {
+    messages: Arc<[(&'static str, MessageOptions)]>,
+}
+
+impl MessageRegistry {
+    pub(crate) fn new(messages: impl Iterator<Item = (&'static str, MessageOptions)>) -> Self {
+        let mut messages: Vec<_> = messages.collect();
+        messages.sort_unstable_by_key(|(name, _)| *name);
+        messages.dedup_by_key(|(name, _)| *name);
+        // Anything past this can still be sent by name
+        messages.truncate(u16::MAX as usize + 1);
+
+        Self {
+            messages: messages.into(),
+        }
+    }
+
+    /// The name of the message a packet of `kind` carries
+    pub(crate) fn name_of<'a>(&self, kind: &'a PacketKind) -> Option<&'a str> {
+        match kind {
+            PacketKind::Name(name) => Some(name),
+            PacketKind::Id(id) => self.messages.get(*id as usize).map(|(name, _)| *name),
+            PacketKind::Registry
+            | PacketKind::Handshake
+            | PacketKind::Heartbeat
+            | PacketKind::Disconnect
+            | PacketKind::Session
+            | PacketKind::Resume
+            | PacketKind::Response
+            | PacketKind::Chunk
+            | PacketKind::Auth
+            | PacketKind::Admitted => None,
+        }
+    }
+
+    /// The packet telling the peer which ids to use
+    pub(crate) fn announcement(&self) -> NetworkPacket {
+        let mut data = Vec::new();
+        data.put_u32_le(self.messages.len() as u32);
+        for (name, options) in self.messages.iter() {
+            data.put_u16_le(name.len() as u16);
+            data.put_slice(name.as_bytes());
+            // Channel names are checked against the same limit as message names
+            let channel = options.channel.name();
+            data.put_u16_le(channel.len() as u16);
+            data.put_slice(channel.as_bytes());
+            data.put_u8(options.channel.delivery().to_byte());
+            #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+            data.put_u8(options.compress as u8);
+            #[cfg(not(any(feature = "zstd", feature = "lz4", feature = "deflate")))]
+            data.put_u8(0);
+        }
+
+        NetworkPacket {
+            kind: PacketKind::Registry,
+            data: data.into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+}
+
+/// The ids the peer assigned to the messages it listens for, and how it wants them to be sent.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeerRegistry {
+    messages: Arc<DashMap<String, (u16, MessageOptions)>>,
+}
+
+impl PeerRegistry {
+    /// Take over the ids from the peer's [`MessageRegistry::announcement`]
+    pub(crate) fn update(&self, mut announcement: &[u8]) -> Result<(), NetworkError> {
+        let cut_off = || NetworkError::MalformedPacket("registry is cut off");
+
+        if announcement.remaining() < 4 {
+            return Err(cut_off());
+        }
+        let count = announcement.get_u32_le().min(u16::MAX as u32 + 1);
+
+        for id in 0..count {
+            let name = read_name(&mut announcement)?;
+            let channel = read_name(&mut announcement)?;
+            if announcement.remaining() < 2 {
+                return Err(cut_off());
+            }
+            let delivery = Delivery::from_byte(announcement.get_u8())?;
+
+            let options =
+                MessageOptions::default().with_channel(Channel::announced(channel, delivery));
+            #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+            let options = options.with_compression(announcement.get_u8() != 0);
+            // Without an algorithm we send everything as it is, whatever the peer asked for
+            #[cfg(not(any(feature = "zstd", feature = "lz4", feature = "deflate")))]
+            announcement.advance(1);
+            self.messages.insert(name, (id as u16, options));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the peer listens for a message called `name`
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.messages.contains_key(name)
+    }
+
+    /// How to address a message called `name`, and how the peer wants it to be sent
+    ///
+    /// Falls back to the name itself, and the default options, while the peer's registry is unknown.
+    pub(crate) fn route(&self, name: &str) -> (PacketKind, MessageOptions) {
+        match self.messages.get(name) {
+            Some(entry) => {
+                let (id, options) = entry.value();
+                (PacketKind::Id(*id), options.clone())
+            }
+            None => (PacketKind::Name(name.to_owned()), MessageOptions::default()),
+        }
+    }
+}
+
+/// Read a length prefixed name out of a [`MessageRegistry::announcement`]
+fn read_name(announcement: &mut &[u8]) -> Result<String, NetworkError> {
+    if announcement.remaining() < 2 {
+        return Err(NetworkError::MalformedPacket("registry is cut off"));
+    }
+    let length = announcement.get_u16_le() as usize;
+    if announcement.remaining() < length {
+        return Err(NetworkError::MalformedPacket("registry is cut off"));
+    }
+    let name = std::str::from_utf8(&announcement[..length])
+        .map_err(|_| NetworkError::MalformedPacket("name is not valid UTF-8"))?
+        .to_owned();
+    announcement.advance(length);
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: Channel = Channel::new("positions", Delivery::UnreliableSequenced);
+
+    fn announced(registry: &MessageRegistry) -> PeerRegistry {
+        let peer = PeerRegistry::default();
+        peer.update(&registry.announcement().data)
+            .expect("announcement should be read");
+        peer
+    }
+
+    #[test]
+    fn peers_address_messages_by_the_announced_ids() {
+        let registry = MessageRegistry::new(
+            ["b:Input", "a:Chat", "c:Position"]
+                .into_iter()
+                .map(|name| (name, MessageOptions::default())),
+        );
+        let peer = announced(&registry);
+
+        for name in ["a:Chat", "b:Input", "c:Position"] {
+            let (kind, _) = peer.route(name);
+            assert!(matches!(kind, PacketKind::Id(_)));
+            assert_eq!(registry.name_of(&kind), Some(name));
+        }
+    }
+
+    #[test]
+    fn peers_send_with_the_announced_options() {
+        let options = MessageOptions::default().with_channel(POSITIONS);
+        #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+        let options = options.with_compression(true);
+        let registry = MessageRegistry::new(
+            [
+                ("a:Chat", MessageOptions::default()),
+                ("c:Position", options.clone()),
+            ]
+            .into_iter(),
+        );
+        let peer = announced(&registry);
+
+        assert_eq!(peer.route("c:Position").1, options);
+        assert_eq!(peer.route("a:Chat").1, MessageOptions::default());
+    }
+
+    #[test]
+    fn unknown_messages_are_sent_by_name() {
+        let peer = announced(&MessageRegistry::new(std::iter::empty()));
+
+        let (kind, options) = peer.route("a:Chat");
+        assert_eq!(kind, PacketKind::Name("a:Chat".to_owned()));
+        assert_eq!(options, MessageOptions::default());
+        assert!(!peer.contains("a:Chat"));
+    }
+
+    #[test]
+    fn duplicate_names_share_an_id() {
+        let registry = MessageRegistry::new(
+            ["a:Sum", "a:Sum", "b:Other"]
+                .into_iter()
+                .map(|name| (name, MessageOptions::default())),
+        );
+
+        assert_eq!(registry.name_of(&PacketKind::Id(0)), Some("a:Sum"));
+        assert_eq!(registry.name_of(&PacketKind::Id(1)), Some("b:Other"));
+        assert_eq!(registry.name_of(&PacketKind::Id(2)), None);
+    }
+
+    #[test]
+    fn rejects_cut_off_announcements() {
+        let registry = MessageRegistry::new(
+            [("a:Chat", MessageOptions::default().with_channel(POSITIONS))].into_iter(),
+        );
+        let announcement = registry.announcement().data;
+
+        for length in 0..announcement.len() {
+            assert!(PeerRegistry::default()
+                .update(&announcement[..length])
+                .is_err());
+        }
+    }
+}
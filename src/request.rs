@@ -0,0 +1,125 @@
+use std::time::Instant;
+
+use async_channel::{Receiver, TryRecvError};
+use async_io::Timer;
+use bytes::{Buf, BufMut, Bytes};
+use derive_more::Deref;
+use futures_lite::future;
+
+use crate::{
+    codec::Codec, error::NetworkError, network_message::RequestMessage,
+    server::NetworkServerProvider, ConnectionId, NetworkServer,
+};
+
+/// A [`RequestMessage`] sent by a client, waiting to be answered
+///
+/// Like [`NetworkData`](crate::NetworkData), this is what is sent over the bevy event system
+#[derive(Debug, Deref)]
+pub struct NetworkRequest<T: RequestMessage> {
+    source: ConnectionId,
+    id: u32,
+    #[deref]
+    inner: T,
+}
+
+impl<T: RequestMessage> NetworkRequest<T> {
+    pub(crate) fn new(source: ConnectionId, id: u32, inner: T) -> Self {
+        Self { source, id, inner }
+    }
+
+    /// The client that sent this request
+    pub fn source(&self) -> ConnectionId {
+        self.source
+    }
+
+    /// Answer the request, the client only takes the first response
+    pub fn respond<NSP: NetworkServerProvider, C: Codec>(
+        &self,
+        server: &NetworkServer<NSP, C>,
+        response: T::Response,
+    ) -> Result<(), NetworkError> {
+        server.send_response(self.source, self.id, &response)
+    }
+
+    /// Get the inner data out of it
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// The answer to a [`NetworkClient::request`](crate::NetworkClient::request), once it arrives
+#[derive(Debug)]
+pub struct PendingResponse<T> {
+    response: Receiver<Bytes>,
+    deadline: Instant,
+    decode: fn(&[u8]) -> Result<T, NetworkError>,
+}
+
+impl<T> PendingResponse<T> {
+    pub(crate) fn new(
+        response: Receiver<Bytes>,
+        deadline: Instant,
+        decode: fn(&[u8]) -> Result<T, NetworkError>,
+    ) -> Self {
+        Self {
+            response,
+            deadline,
+            decode,
+        }
+    }
+
+    /// Check for the response without waiting, to poll it from a system
+    ///
+    /// Returns `None` while the response is still on its way. Fails with
+    /// [`NetworkError::RequestTimedOut`] once the timeout has passed, and with
+    /// [`NetworkError::NotConnected`] if the client disconnected or the response was already taken.
+    pub fn try_recv(&self) -> Option<Result<T, NetworkError>> {
+        match self.response.try_recv() {
+            Ok(response) => Some((self.decode)(&response)),
+            Err(TryRecvError::Empty) if Instant::now() < self.deadline => None,
+            Err(_) => Some(Err(self.failure())),
+        }
+    }
+
+    /// Wait for the response, see [`PendingResponse::try_recv`] for how this fails
+    pub async fn recv(self) -> Result<T, NetworkError> {
+        let response = async {
+            match self.response.recv().await {
+                Ok(response) => (self.decode)(&response),
+                Err(_) => Err(self.failure()),
+            }
+        };
+
+        future::or(response, async {
+            Timer::at(self.deadline).await;
+            Err(NetworkError::RequestTimedOut)
+        })
+        .await
+    }
+
+    /// Why there is no response, the client forgets about requests once they timed out
+    fn failure(&self) -> NetworkError {
+        if Instant::now() < self.deadline {
+            NetworkError::NotConnected
+        } else {
+            NetworkError::RequestTimedOut
+        }
+    }
+}
+
+/// Put the id of a request in front of it, or its response
+pub(crate) fn tag(id: u32, message: &[u8]) -> Bytes {
+    let mut tagged = Vec::with_capacity(4 + message.len());
+    tagged.put_u32_le(id);
+    tagged.put_slice(message);
+    tagged.into()
+}
+
+/// Split a [`tag`]ged request, or response, into its id and the message
+pub(crate) fn untag(mut tagged: Bytes) -> Result<(u32, Bytes), NetworkError> {
+    if tagged.remaining() < 4 {
+        return Err(NetworkError::MalformedPacket("request id is cut off"));
+    }
+    let id = tagged.get_u32_le();
+    Ok((id, tagged))
+}
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use dashmap::DashMap;
+use derive_more::Display;
+
+use crate::ConnectionId;
+
+/// A [`RoomId`] names a group of clients, to send messages to all of them at once
+///
+/// See [`NetworkServer::join_room`](crate::NetworkServer::join_room) and
+/// [`NetworkServer::broadcast_to_room`](crate::NetworkServer::broadcast_to_room)
+#[derive(Hash, PartialEq, Eq, Clone, Debug, Display)]
+pub enum RoomId {
+    /// A room known by name, like a chat channel
+    Name(String),
+    /// A room known by number, like a match or a lobby
+    Id(u64),
+}
+
+impl From<&str> for RoomId {
+    fn from(name: &str) -> Self {
+        RoomId::Name(name.to_owned())
+    }
+}
+
+impl From<String> for RoomId {
+    fn from(name: String) -> Self {
+        RoomId::Name(name)
+    }
+}
+
+impl From<u64> for RoomId {
+    fn from(id: u64) -> Self {
+        RoomId::Id(id)
+    }
+}
+
+/// Which clients are in which room
+///
+/// A room only exists while there is someone in it.
+#[derive(Debug, Default)]
+pub(crate) struct Rooms {
+    members: DashMap<RoomId, HashSet<ConnectionId>>,
+}
+
+impl Rooms {
+    /// Returns whether the client wasn't in the room yet
+    pub(crate) fn join(&self, conn_id: ConnectionId, room: RoomId) -> bool {
+        self.members.entry(room).or_default().insert(conn_id)
+    }
+
+    /// Returns whether the client was in the room
+    pub(crate) fn leave(&self, conn_id: ConnectionId, room: &RoomId) -> bool {
+        let left = match self.members.get_mut(room) {
+            Some(mut members) => members.remove(&conn_id),
+            None => return false,
+        };
+        self.members
+            .remove_if(room, |_, members| members.is_empty());
+        left
+    }
+
+    /// Take the client out of every room it is in, returning those rooms
+    pub(crate) fn leave_all(&self, conn_id: ConnectionId) -> Vec<RoomId> {
+        let rooms: Vec<_> = self
+            .members
+            .iter()
+            .filter(|members| members.contains(&conn_id))
+            .map(|members| members.key().clone())
+            .collect();
+        rooms
+            .into_iter()
+            .filter(|room| self.leave(conn_id, room))
+            .collect()
+    }
+
+    /// Everyone in the room, empty if there is no such room
+    pub(crate) fn members(&self, room: &RoomId) -> HashSet<ConnectionId> {
+        self.members
+            .get(room)
+            .map(|members| members.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::Uuid;
+
+    use super::*;
+
+    fn client() -> ConnectionId {
+        ConnectionId {
+            uuid: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    fn rooms_only_exist_while_someone_is_in_them() {
+        let rooms = Rooms::default();
+        let (alice, bob) = (client(), client());
+        let lobby = RoomId::from("lobby");
+
+        assert!(rooms.join(alice, lobby.clone()));
+        assert!(!rooms.join(alice, lobby.clone()));
+        assert!(rooms.join(bob, lobby.clone()));
+        assert_eq!(rooms.members(&lobby), HashSet::from([alice, bob]));
+
+        assert!(rooms.leave(alice, &lobby));
+        assert!(!rooms.leave(alice, &lobby));
+        assert!(rooms.leave(bob, &lobby));
+        assert!(rooms.members(&lobby).is_empty());
+        assert!(rooms.members.is_empty());
+    }
+
+    #[test]
+    fn clients_leave_all_their_rooms_at_once() {
+        let rooms = Rooms::default();
+        let (alice, bob) = (client(), client());
+        rooms.join(alice, RoomId::from("lobby"));
+        rooms.join(alice, RoomId::from(7));
+        rooms.join(bob, RoomId::from(7));
+
+        let mut left = rooms.leave_all(alice);
+        left.sort_by_key(|room| room.to_string());
+        assert_eq!(left, [RoomId::from(7), RoomId::from("lobby")]);
+        assert_eq!(rooms.members(&RoomId::from(7)), HashSet::from([bob]));
+        assert!(rooms.leave_all(alice).is_empty());
+    }
+}
@@ -15,4 +15,9 @@ pub trait Runtime: 'static + Send + Sync {
 pub trait JoinHandle: 'static + Send + Sync {
     /// Stop the task.
     fn abort(&mut self);
+
+    /// Let the task finish on its own, even after the handle is dropped.
+    ///
+    /// The default does nothing, which is right for runtimes that don't cancel tasks on drop.
+    fn detach(&mut self) {}
 }
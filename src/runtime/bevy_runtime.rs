@@ -17,4 +17,10 @@ impl JoinHandle for Option<bevy::tasks::Task<()>> {
     fn abort(&mut self) {
         self.take();
     }
+
+    fn detach(&mut self) {
+        if let Some(task) = self.take() {
+            task.detach();
+        }
+    }
 }
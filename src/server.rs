@@ -1,16 +1,41 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use async_channel::{unbounded, Receiver, Sender};
+use async_io::Timer;
 use async_trait::async_trait;
 use bevy::{prelude::*, utils::Uuid};
-use dashmap::DashMap;
+use bytes::Bytes;
+use dashmap::{DashMap, DashSet};
+use futures_lite::future;
 
+#[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+use crate::compression::{self, Compression};
 use crate::{
-    error::NetworkError,
-    network_message::{ClientMessage, ServerMessage},
+    auth::{Authentication, Credentials},
+    codec::{Codec, JsonCodec},
+    error::{AuthenticationError, HandshakeError, NetworkError},
+    handshake::Handshake,
+    heartbeat::{self, Heartbeat},
+    network_message::{ClientMessage, MessageOptions, RequestMessage, ServerMessage},
+    network_packet::PacketKind,
+    queue::{Outgoing, Priority, QueueError, SendQueue},
+    registry::{MessageRegistry, PeerRegistry},
+    request::{self, NetworkRequest},
+    room::{RoomId, Rooms},
     runtime::JoinHandle,
-    AsyncChannel, Connection, ConnectionId, NetworkData, NetworkPacket, Runtime,
-    ServerNetworkEvent,
+    send_until_closed,
+    session::{self, Session, SessionResumption},
+    transfer::{self, Reassembly, Transfer, TransferId, TransferProgress, Transfers},
+    AsyncChannel, Channel, Connection, ConnectionId, ConnectionInfo, DisconnectReason, NetworkData,
+    NetworkPacket, Runtime, ServerNetworkEvent, SharedMessage, CLOSE_TIMEOUT,
 };
 
 /// A trait used by [`NetworkServer`] to drive a server, this is responsible
@@ -38,11 +63,13 @@ pub trait NetworkServerProvider: 'static + Send + Sync {
     );
 
     /// Recieves messages from the client, forwards them to Spicy via a sender.
+    ///
+    /// Returns why it stopped receiving.
     async fn recv_loop(
         read_half: Self::ReadHalf,
         messages: Sender<NetworkPacket>,
         settings: Self::NetworkSettings,
-    );
+    ) -> DisconnectReason;
 
     /// Sends messages to the client, receives packages from Spicy via receiver.
     async fn send_loop(
@@ -54,21 +81,50 @@ pub trait NetworkServerProvider: 'static + Send + Sync {
     /// Split the socket into a read and write half, so that the two actions
     /// can be handled concurrently.
     fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf);
+
+    /// The address on the other side of the socket
+    ///
+    /// Returns `None` by default, for providers that don't connect over IP.
+    fn peer_addr(_socket: &Self::Socket) -> Option<SocketAddr> {
+        None
+    }
+
+    /// The address on our side of the socket
+    ///
+    /// Returns `None` by default, for providers that don't connect over IP.
+    fn local_addr(_socket: &Self::Socket) -> Option<SocketAddr> {
+        None
+    }
 }
 
 /// An instance of a [`NetworkServer`] is used to listen for new client connections
 /// using [`NetworkServer::listen`]
-pub struct NetworkServer<NSP: NetworkServerProvider> {
-    recv_message_map: Arc<DashMap<&'static str, Vec<(ConnectionId, String)>>>,
+///
+/// Messages are encoded with the [`Codec`] `C`, which has to match the one the clients use.
+pub struct NetworkServer<NSP: NetworkServerProvider, C: Codec = JsonCodec> {
+    recv_message_map: Arc<DashMap<&'static str, Vec<(ConnectionId, Bytes)>>>,
+    message_options: DashMap<&'static str, MessageOptions>,
+    /// The responses to the requests we answer, checked by the [`Handshake`]
+    peer_messages: DashSet<&'static str>,
     established_connections: Arc<DashMap<ConnectionId, Connection>>,
+    pending_connections: DashMap<ConnectionId, Connection>,
+    admissions: AsyncChannel<Admission>,
+    sessions: DashMap<ConnectionId, Session>,
     new_connections: AsyncChannel<NSP::Socket>,
-    disconnected_connections: AsyncChannel<ConnectionId>,
+    disconnected_connections: AsyncChannel<(ConnectionId, DisconnectReason)>,
+    dropped_connections: AsyncChannel<(ConnectionId, DisconnectReason)>,
     error_channel: AsyncChannel<NetworkError>,
+    transfer_progress: AsyncChannel<(ConnectionId, TransferProgress)>,
+    next_transfer_id: AtomicU32,
+    rooms: Rooms,
+    identities: DashMap<ConnectionId, String>,
+    room_events: AsyncChannel<ServerNetworkEvent>,
     server_handle: Option<Box<dyn JoinHandle>>,
     provider: PhantomData<NSP>,
+    codec: PhantomData<C>,
 }
 
-impl<NSP: NetworkServerProvider> std::fmt::Debug for NetworkServer<NSP> {
+impl<NSP: NetworkServerProvider, C: Codec> std::fmt::Debug for NetworkServer<NSP, C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
@@ -78,16 +134,28 @@ impl<NSP: NetworkServerProvider> std::fmt::Debug for NetworkServer<NSP> {
     }
 }
 
-impl<NSP: NetworkServerProvider> NetworkServer<NSP> {
+impl<NSP: NetworkServerProvider, C: Codec> NetworkServer<NSP, C> {
     pub(crate) fn new(_provider: NSP) -> Self {
         Self {
             recv_message_map: Arc::new(DashMap::new()),
+            message_options: DashMap::new(),
+            peer_messages: DashSet::new(),
             established_connections: Arc::new(DashMap::new()),
+            pending_connections: DashMap::new(),
+            admissions: AsyncChannel::new(),
+            sessions: DashMap::new(),
             new_connections: AsyncChannel::new(),
             disconnected_connections: AsyncChannel::new(),
+            dropped_connections: AsyncChannel::new(),
             error_channel: AsyncChannel::new(),
+            transfer_progress: AsyncChannel::new(),
+            next_transfer_id: AtomicU32::new(0),
+            rooms: Rooms::default(),
+            identities: DashMap::new(),
+            room_events: AsyncChannel::new(),
             server_handle: None,
             provider: PhantomData,
+            codec: PhantomData,
         }
     }
 
@@ -119,138 +187,959 @@ impl<NSP: NetworkServerProvider> NetworkServer<NSP> {
         &self,
         client_id: ConnectionId,
         message: T,
+    ) -> Result<(), NetworkError> {
+        self.send_message_with_priority(client_id, message, Priority::Normal)
+    }
+
+    /// Send a message to a specific client, ahead of or behind the others waiting for it
+    pub fn send_message_with_priority<T: ClientMessage>(
+        &self,
+        client_id: ConnectionId,
+        message: T,
+        priority: Priority,
+    ) -> Result<(), NetworkError> {
+        let data = C::encode(&message)?.into();
+        self.send_data(client_id, T::NAME, priority, data)
+    }
+
+    /// Answer the request `request_id` of a client
+    pub(crate) fn send_response<T: ClientMessage>(
+        &self,
+        client_id: ConnectionId,
+        request_id: u32,
+        response: &T,
+    ) -> Result<(), NetworkError> {
+        let data = request::tag(request_id, &C::encode(response)?);
+        // Sent as a response rather than by name, so the client routes it by the request id
+        self.send_packet(client_id, Priority::Normal, |connection| NetworkPacket {
+            kind: PacketKind::Response,
+            ..connection.packet(T::NAME, data)
+        })
+    }
+
+    fn send_data(
+        &self,
+        client_id: ConnectionId,
+        name: &'static str,
+        priority: Priority,
+        data: Bytes,
+    ) -> Result<(), NetworkError> {
+        self.send_packet(client_id, priority, |connection| {
+            connection.packet(name, data)
+        })
+    }
+
+    fn send_packet(
+        &self,
+        client_id: ConnectionId,
+        priority: Priority,
+        packet: impl FnOnce(&Connection) -> NetworkPacket,
     ) -> Result<(), NetworkError> {
         let connection = match self.established_connections.get(&client_id) {
             Some(conn) => conn,
             None => return Err(NetworkError::ConnectionNotFound(client_id)),
         };
 
-        let packet = NetworkPacket {
-            kind: String::from(T::NAME),
-            data: serde_json::to_string(&message).unwrap(),
+        let packet = packet(&connection);
+
+        let sent = connection.outgoing.send(priority, packet);
+        // The connection can only be dropped once it isn't borrowed anymore
+        drop(connection);
+
+        match sent {
+            Ok(()) => Ok(()),
+            Err(QueueError::Closed) => {
+                error!(
+                    "There was an error sending a packet: {} is closed",
+                    client_id
+                );
+                Err(NetworkError::ChannelClosed(client_id))
+            }
+            Err(QueueError::Full) => Err(NetworkError::QueueFull(client_id)),
+            Err(QueueError::Disconnect) => {
+                warn!("Dropping {}, its send queue is full", client_id);
+                let _ = self.drop_client(client_id, DisconnectReason::QueueFull);
+                Err(NetworkError::QueueFull(client_id))
+            }
+        }
+    }
+
+    /// Send a message to a specific client in chunks, for payloads too large to be sent at once
+    ///
+    /// The chunks only go out when nothing else is waiting, so other messages aren't held up
+    /// behind them. Both sides report the progress with [`ServerNetworkEvent::Transfer`] and
+    /// [`ClientNetworkEvent::Transfer`](crate::ClientNetworkEvent::Transfer), the client receives
+    /// the message as usual once it is complete. See [`Transfers`] for the settings.
+    ///
+    /// ## Note
+    /// Transfers are always delivered reliably and in order, whatever channel the message is on
+    pub fn send_transfer<T: ClientMessage>(
+        &self,
+        client_id: ConnectionId,
+        message: T,
+    ) -> Result<TransferId, NetworkError> {
+        let data = C::encode(&message)?.into();
+
+        let connection = match self.established_connections.get(&client_id) {
+            Some(conn) => conn,
+            None => return Err(NetworkError::ConnectionNotFound(client_id)),
         };
 
-        match connection.send_message.try_send(packet) {
-            Ok(_) => (),
-            Err(err) => {
-                error!("There was an error sending a packet: {}", err);
-                return Err(NetworkError::ChannelClosed(client_id));
+        let packet = connection.packet(T::NAME, data);
+        let id = self.next_transfer_id.fetch_add(1, Ordering::Relaxed);
+        let transfer = Transfer::new(id, client_id, packet);
+        let id = transfer.id;
+
+        match connection.transfers.try_send(transfer) {
+            Ok(()) => Ok(id),
+            Err(_) => {
+                error!(
+                    "There was an error sending a transfer: {} is closed",
+                    client_id
+                );
+                Err(NetworkError::ChannelClosed(client_id))
             }
         }
+    }
 
-        Ok(())
+    /// Who a client is, as the [`Authentication`] found when it was let in
+    ///
+    /// Returns `None` if the client isn't connected, or there is no [`Authentication`].
+    pub fn identity(&self, conn_id: ConnectionId) -> Option<String> {
+        self.identities
+            .get(&conn_id)
+            .map(|identity| identity.clone())
+    }
+
+    /// Where a client is connected from and since when, `None` if it isn't connected
+    pub fn connection_info(&self, conn_id: ConnectionId) -> Option<ConnectionInfo> {
+        self.established_connections
+            .get(&conn_id)
+            .map(|connection| connection.info)
     }
 
     /// Broadcast a message to all connected clients
-    pub fn broadcast<T: ClientMessage + Clone>(&self, message: T) {
-        for connection in self.established_connections.iter() {
-            let serialized_message = serde_json::to_string(&message).unwrap();
-            let packet = NetworkPacket {
-                kind: String::from(T::NAME),
-                data: serialized_message,
-            };
-
-            match connection.send_message.try_send(packet) {
-                Ok(_) => (),
-                Err(err) => {
-                    warn!("Could not send to client because: {}", err);
+    ///
+    /// The message is only encoded once, however many clients there are.
+    pub fn broadcast<T: ClientMessage>(&self, message: T) {
+        self.broadcast_with_priority(message, Priority::Normal)
+    }
+
+    /// Broadcast a message to all connected clients, ahead of or behind the others waiting for them
+    pub fn broadcast_with_priority<T: ClientMessage>(&self, message: T, priority: Priority) {
+        self.broadcast_filtered(message, priority, |_| true)
+    }
+
+    /// Broadcast a message to all connected clients but one, like the one it came from
+    pub fn broadcast_except<T: ClientMessage>(&self, except: ConnectionId, message: T) {
+        self.broadcast_filtered(message, Priority::Normal, |conn_id| *conn_id != except)
+    }
+
+    /// Broadcast a message to every client in a room
+    ///
+    /// Does nothing if there is no one in the room.
+    pub fn broadcast_to_room<T: ClientMessage>(&self, room: impl Into<RoomId>, message: T) {
+        let members = self.rooms.members(&room.into());
+        if !members.is_empty() {
+            self.broadcast_filtered(message, Priority::Normal, |conn_id| {
+                members.contains(conn_id)
+            })
+        }
+    }
+
+    /// Send a message to some of the clients, encoding it only once
+    ///
+    /// Fails only if the message can't be encoded, clients that aren't connected or
+    /// can't take the message right now are skipped, like with [`NetworkServer::broadcast`].
+    pub fn send_to_many<T: ClientMessage>(
+        &self,
+        clients: &[ConnectionId],
+        message: T,
+    ) -> Result<(), NetworkError> {
+        let data = C::encode(&message)?.into();
+        let mut shared = SharedMessage::new(T::NAME, data);
+
+        let mut overflowing = Vec::new();
+        for conn_id in clients {
+            match self.established_connections.get(conn_id) {
+                Some(connection) => {
+                    let packet = shared.packet_for(&connection);
+                    queue_shared(
+                        *conn_id,
+                        &connection,
+                        Priority::Normal,
+                        packet,
+                        &mut overflowing,
+                    );
                 }
+                None => warn!(
+                    "Could not send to client because: {} is not connected",
+                    conn_id
+                ),
+            }
+        }
+
+        self.drop_overflowing(overflowing);
+        Ok(())
+    }
+
+    fn broadcast_filtered<T: ClientMessage>(
+        &self,
+        message: T,
+        priority: Priority,
+        include: impl Fn(&ConnectionId) -> bool,
+    ) {
+        let data = match C::encode(&message) {
+            Ok(data) => data.into(),
+            Err(err) => {
+                error!("Could not serialize broadcast message: {}", err);
+                return;
             }
+        };
+        let mut shared = SharedMessage::new(T::NAME, data);
+
+        let mut overflowing = Vec::new();
+        for connection in self
+            .established_connections
+            .iter()
+            .filter(|connection| include(connection.key()))
+        {
+            let packet = shared.packet_for(&connection);
+            queue_shared(
+                *connection.key(),
+                &connection,
+                priority,
+                packet,
+                &mut overflowing,
+            );
+        }
+
+        self.drop_overflowing(overflowing);
+    }
+
+    /// Drop the clients whose send queue overflowed, see [`QueuePolicy::Disconnect`](crate::QueuePolicy::Disconnect)
+    fn drop_overflowing(&self, overflowing: Vec<ConnectionId>) {
+        for conn_id in overflowing {
+            warn!("Dropping {}, its send queue is full", conn_id);
+            let _ = self.drop_client(conn_id, DisconnectReason::QueueFull);
+        }
+    }
+
+    /// Add a client to a room, creating the room if no one is in it yet
+    ///
+    /// Sends [`ServerNetworkEvent::JoinedRoom`], unless the client already was in the room.
+    /// Clients leave all their rooms when they disconnect.
+    pub fn join_room(
+        &self,
+        conn_id: ConnectionId,
+        room: impl Into<RoomId>,
+    ) -> Result<(), NetworkError> {
+        if !self.established_connections.contains_key(&conn_id) {
+            return Err(NetworkError::ConnectionNotFound(conn_id));
+        }
+
+        let room = room.into();
+        if self.rooms.join(conn_id, room.clone()) {
+            let _ = self
+                .room_events
+                .sender
+                .try_send(ServerNetworkEvent::JoinedRoom(conn_id, room));
+        }
+        Ok(())
+    }
+
+    /// Take a client out of a room, the room is gone once the last one left
+    ///
+    /// Sends [`ServerNetworkEvent::LeftRoom`] and returns `true`, if the client was in the room.
+    pub fn leave_room(&self, conn_id: ConnectionId, room: impl Into<RoomId>) -> bool {
+        let room = room.into();
+        let left = self.rooms.leave(conn_id, &room);
+        if left {
+            let _ = self
+                .room_events
+                .sender
+                .try_send(ServerNetworkEvent::LeftRoom(conn_id, room));
+        }
+        left
+    }
+
+    /// The clients in a room, empty if there is no one in it
+    pub fn room_members(&self, room: impl Into<RoomId>) -> Vec<ConnectionId> {
+        self.rooms.members(&room.into()).into_iter().collect()
+    }
+
+    /// Forget about a client that is gone for good, taking it out of all its rooms
+    fn forget(&self, conn_id: ConnectionId) {
+        self.identities.remove(&conn_id);
+        for room in self.rooms.leave_all(conn_id) {
+            let _ = self
+                .room_events
+                .sender
+                .try_send(ServerNetworkEvent::LeftRoom(conn_id, room));
         }
     }
 
     /// Disconnect all clients and stop listening for new ones
     ///
+    /// Clients are told the server shut down, see [`DisconnectReason::Shutdown`]
+    ///
     /// ## Notes
     /// This operation is idempotent and will do nothing if you are not actively listening
     pub fn stop(&mut self) {
         if let Some(mut conn) = self.server_handle.take() {
             conn.abort();
-            for conn in self.established_connections.iter() {
-                let _ = self.disconnected_connections.sender.send(*conn.key());
+            let reason = DisconnectReason::Shutdown;
+            let established: Vec<_> = self
+                .established_connections
+                .iter()
+                .map(|conn| *conn.key())
+                .collect();
+            for conn_id in established {
+                if let Some((_, connection)) = self.established_connections.remove(&conn_id) {
+                    connection.close_with(&reason);
+                    let _ = self
+                        .dropped_connections
+                        .sender
+                        .try_send((conn_id, reason.clone()));
+                    self.forget(conn_id);
+                }
+            }
+            let pending: Vec<_> = self
+                .pending_connections
+                .iter()
+                .map(|conn| *conn.key())
+                .collect();
+            for conn_id in pending {
+                if let Some((_, connection)) = self.pending_connections.remove(&conn_id) {
+                    connection.close_with(&reason);
+                }
             }
-            self.established_connections.clear();
+            self.sessions.clear();
             self.recv_message_map.clear();
 
-            while let Ok(_) = self.new_connections.receiver.try_recv() {}
+            while self.new_connections.receiver.try_recv().is_ok() {}
         }
     }
 
     /// Disconnect a specific client
+    ///
+    /// This is the same as [`NetworkServer::kick`] without a message
     pub fn disconnect(&self, conn_id: ConnectionId) -> Result<(), NetworkError> {
-        let connection = if let Some(conn) = self.established_connections.remove(&conn_id) {
-            conn
+        self.kick(conn_id, String::new())
+    }
+
+    /// Disconnect a specific client, telling it why
+    ///
+    /// The client receives [`DisconnectReason::Kicked`] with `message`
+    ///
+    /// ## Note
+    /// This also drops clients that are still in the middle of their [`Handshake`]
+    pub fn kick(
+        &self,
+        conn_id: ConnectionId,
+        message: impl Into<String>,
+    ) -> Result<(), NetworkError> {
+        self.drop_client(conn_id, DisconnectReason::Kicked(message.into()))
+    }
+
+    /// Close the connection to a client, telling it why
+    fn drop_client(
+        &self,
+        conn_id: ConnectionId,
+        reason: DisconnectReason,
+    ) -> Result<(), NetworkError> {
+        self.sessions.remove(&conn_id);
+
+        if let Some((_, connection)) = self.established_connections.remove(&conn_id) {
+            connection.close_with(&reason);
+            let _ = self.dropped_connections.sender.try_send((conn_id, reason));
+            self.forget(conn_id);
+        } else if let Some((_, connection)) = self.pending_connections.remove(&conn_id) {
+            connection.close_with(&reason);
         } else {
             return Err(NetworkError::ConnectionNotFound(conn_id));
-        };
-
-        connection.1.stop();
+        }
 
         Ok(())
     }
 }
 
-pub(crate) fn handle_new_incoming_connections<NSP: NetworkServerProvider, RT: Runtime>(
-    server: ResMut<NetworkServer<NSP>>,
+/// Hand a client that was just let in the token to resume its session with
+fn send_session_token(connection: &Connection, session: &Session, conn_id: ConnectionId) {
+    if connection
+        .outgoing
+        .send_control(session.announcement())
+        .is_err()
+    {
+        error!("Could not send the session token to {}", conn_id);
+    }
+}
+
+/// Tell a client it was let in, so it starts sending messages
+fn admit(connection: &Connection, conn_id: ConnectionId) {
+    let admitted = NetworkPacket {
+        kind: PacketKind::Admitted,
+        data: Bytes::new(),
+        channel: Channel::DEFAULT,
+        compressed: false,
+    };
+    if connection.outgoing.send_control(admitted).is_err() {
+        error!("Could not tell {} it was let in", conn_id);
+    }
+}
+
+/// Queue a packet that goes out to many clients, only warning about those that can't take it
+///
+/// Clients that have to be dropped are collected in `overflowing`, since the connection is still borrowed.
+fn queue_shared(
+    conn_id: ConnectionId,
+    connection: &Connection,
+    priority: Priority,
+    packet: NetworkPacket,
+    overflowing: &mut Vec<ConnectionId>,
+) {
+    match connection.outgoing.send(priority, packet) {
+        Ok(()) => (),
+        Err(QueueError::Closed) => {
+            warn!("Could not send to client because: {} is closed", conn_id);
+        }
+        Err(QueueError::Full) => {
+            warn!(
+                "Could not send to client because: the send queue of {} is full",
+                conn_id
+            );
+        }
+        Err(QueueError::Disconnect) => overflowing.push(conn_id),
+    }
+}
+
+/// A new connection that is ready to be let in, once its handshake is done
+#[derive(Debug)]
+struct Admission {
+    conn_id: ConnectionId,
+    result: Result<(), Refusal>,
+    /// Who the [`Authentication`] found the client to be
+    identity: Option<String>,
+    /// The token of the session the client asked to resume
+    resume: Option<Bytes>,
+    /// Tells the connection which id it ended up with
+    assigned: Sender<ConnectionId>,
+}
+
+/// Why a client was not let in
+#[derive(thiserror::Error, Debug)]
+enum Refusal {
+    #[error(transparent)]
+    Handshake(#[from] HandshakeError),
+    #[error(transparent)]
+    Unauthorized(#[from] AuthenticationError),
+}
+
+impl Refusal {
+    /// What the client is told
+    fn reason(&self) -> DisconnectReason {
+        match self {
+            Refusal::Handshake(err) => DisconnectReason::Kicked(err.to_string()),
+            Refusal::Unauthorized(AuthenticationError::Rejected(why)) => {
+                DisconnectReason::Rejected(why.clone())
+            }
+            Refusal::Unauthorized(err) => DisconnectReason::Rejected(err.to_string()),
+        }
+    }
+
+    fn into_error(self, conn_id: ConnectionId) -> NetworkError {
+        match self {
+            Refusal::Handshake(err) => NetworkError::Handshake(conn_id, err),
+            Refusal::Unauthorized(err) => NetworkError::Unauthorized(conn_id, err),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_new_incoming_connections<NSP: NetworkServerProvider, RT: Runtime, C: Codec>(
+    server: ResMut<NetworkServer<NSP, C>>,
     runtime: Res<RT>,
     network_settings: Res<NSP::NetworkSettings>,
+    handshake: Option<Res<Handshake>>,
+    heartbeat: Option<Res<Heartbeat>>,
+    resumption: Option<Res<SessionResumption>>,
+    send_queue: Option<Res<SendQueue>>,
+    transfers: Option<Res<Transfers>>,
+    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))] compression: Option<
+        Res<Compression>,
+    >,
+    authentication: Option<Res<Authentication>>,
     mut network_events: EventWriter<ServerNetworkEvent>,
 ) {
+    let transfers = transfers.as_deref().cloned().unwrap_or_default();
+
     while let Ok(new_conn) = server.new_connections.receiver.try_recv() {
         let conn_id = ConnectionId {
             uuid: Uuid::new_v4(),
         };
 
+        let info = ConnectionInfo {
+            peer_addr: NSP::peer_addr(&new_conn),
+            local_addr: NSP::local_addr(&new_conn),
+            connected_at: SystemTime::now(),
+        };
         let (read_half, write_half) = NSP::split(new_conn);
         let recv_message_map = server.recv_message_map.clone();
         let read_network_settings = network_settings.clone();
         let write_network_settings = network_settings.clone();
         let disconnected_connections = server.disconnected_connections.sender.clone();
+        let admissions = server.admissions.sender.clone();
 
-        let (outgoing_tx, outgoing_rx) = unbounded();
+        let (outgoing, queued) = Outgoing::new(send_queue.as_deref());
         let (incoming_tx, incoming_rx) = unbounded();
+        let (recv_reason_tx, recv_reason_rx) = async_channel::bounded(1);
+        let map_outgoing_tx = outgoing.sender(Priority::Low);
+        let session = resumption.is_some().then(|| Session::new(queued.clone()));
+        let (transfer_tx, transfer_rx) = unbounded();
+        let (closed_tx, closed_rx) = async_channel::bounded(1);
+        let (chunk_tx, chunk_rx) = async_channel::bounded(1);
+        let (outgoing_rx, prioritized) = queued.relay(chunk_rx);
+        let (outgoing_rx, heartbeats) = heartbeat::keep_alive(outgoing_rx, heartbeat.as_deref());
+        let map_heartbeat = heartbeat.as_deref().cloned();
+        let transfer_progress = server.transfer_progress.sender.clone();
+        let chunks = transfer::chunk_loop(
+            transfer_rx,
+            chunk_tx,
+            transfers.chunk_size,
+            move |conn_id, progress| {
+                let _ = transfer_progress.try_send((conn_id, progress));
+            },
+        );
+        let map_transfer_progress = server.transfer_progress.sender.clone();
+        let mut reassembly = Reassembly::new(transfers.max_length);
+        #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+        let map_compression = compression.as_deref().cloned();
+        let map_authentication = authentication.as_deref().cloned();
+
+        let registry = MessageRegistry::new(
+            server
+                .message_options
+                .iter()
+                .map(|entry| (*entry.key(), entry.value().clone())),
+        );
+        let peer_registry = PeerRegistry::default();
+        let map_peer_registry = peer_registry.clone();
+
+        let mut pending_handshake = handshake.as_ref().map(|handshake| {
+            handshake.expect(server.peer_messages.iter().map(|name| *name).collect())
+        });
+        // Clients are let in once their registry arrived, after their handshake, their credentials
+        // and the session they resume were checked
+        let mut pending_admission = true;
+        let mut resume = None;
+        let mut credentials = None;
+        let admission_deadline = authentication
+            .as_ref()
+            .map(|authentication| Instant::now() + authentication.admission_timeout);
+
+        // Goes out before anything else, so the client can check us and switch to ids right away
+        if let Some(handshake) = handshake.as_ref() {
+            if outgoing.send_control(handshake.announcement()).is_err() {
+                error!("Could not send the handshake to {}", conn_id);
+            }
+        }
+        if outgoing.send_control(registry.announcement()).is_err() {
+            error!("Could not announce the message registry to {}", conn_id);
+        }
+
+        let connection = Connection {
+            receive_task: Box::new(runtime.spawn(async move {
+                trace!("Starting listen task for {}", conn_id);
+                let reason = NSP::recv_loop(read_half, incoming_tx, read_network_settings).await;
+                let _ = recv_reason_tx.send(reason).await;
+            })),
+            map_receive_task: Box::new(runtime.spawn(async move {
+                // Changes when the client resumes an earlier session
+                let mut conn_id = conn_id;
+
+                // Whether the peer still needs to be told why the connection ends
+                let (reason, tell_peer) = loop {
+                    let received = heartbeat::recv(&incoming_rx, map_heartbeat.as_ref());
+                    let received = match admission_deadline.filter(|_| pending_admission) {
+                        Some(deadline) => {
+                            future::or(async { Some(received.await) }, async {
+                                Timer::at(deadline).await;
+                                None
+                            })
+                            .await
+                        }
+                        None => Some(received.await),
+                    };
+                    let packet = match received {
+                        Some(Ok(Some(packet))) => packet,
+                        // Nothing is left behind if the connection was dropped on our side
+                        Some(Ok(None)) => break (recv_reason_rx.recv().await.ok(), true),
+                        Some(Err(reason)) => break (Some(reason), true),
+                        // The client took too long to present its credentials
+                        None => {
+                            let (assigned, _) = async_channel::bounded(1);
+                            let _ = admissions
+                                .send(Admission {
+                                    conn_id,
+                                    result: Err(AuthenticationError::TimedOut.into()),
+                                    identity: None,
+                                    resume: None,
+                                    assigned,
+                                })
+                                .await;
+                            return;
+                        }
+                    };
+
+                    // Nothing is buffered or decompressed for clients that weren't let in yet
+                    if pending_admission && (packet.kind == PacketKind::Chunk || packet.compressed)
+                    {
+                        warn!(
+                            "{} sent a transfer or compressed message before it was let in",
+                            conn_id
+                        );
+                        let reason = DisconnectReason::Rejected(
+                            "Sent too much before being let in".to_string(),
+                        );
+                        break (Some(reason), true);
+                    }
+
+                    // Transfers are handled like any other message once all their chunks arrived
+                    let packet = match packet.kind {
+                        PacketKind::Chunk => match reassembly.receive(packet.data) {
+                            Ok((progress, transferred)) => {
+                                let _ = map_transfer_progress.try_send((conn_id, progress));
+                                match transferred {
+                                    Some(packet) => packet,
+                                    None => continue,
+                                }
+                            }
+                            Err(reason) => break (Some(reason), true),
+                        },
+                        _ => packet,
+                    };
+                    #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+                    let packet = match packet.compressed {
+                        true => {
+                            match compression::decompress(packet.data, map_compression.as_ref()) {
+                                Ok(data) => NetworkPacket {
+                                    data,
+                                    compressed: false,
+                                    ..packet
+                                },
+                                Err(reason) => break (Some(reason), true),
+                            }
+                        }
+                        false => packet,
+                    };
+                    // Without an algorithm we never asked for anything to be compressed
+                    #[cfg(not(any(feature = "zstd", feature = "lz4", feature = "deflate")))]
+                    let packet = match packet.compressed {
+                        true => {
+                            error!(
+                                "{} sent a compressed message, but no algorithm is enabled",
+                                conn_id
+                            );
+                            break (Some(DisconnectReason::DecodeError), true);
+                        }
+                        false => packet,
+                    };
+
+                    match packet.kind {
+                        PacketKind::Handshake => {
+                            if let Some(Err(err)) = pending_handshake
+                                .as_mut()
+                                .map(|pending| pending.receive(&packet.data))
+                            {
+                                error!("Could not read the handshake of {}: {}", conn_id, err);
+                            }
+                            continue;
+                        }
+                        PacketKind::Resume => {
+                            resume = Some(packet.data);
+                            continue;
+                        }
+                        PacketKind::Auth => {
+                            if pending_admission {
+                                credentials = Some(Credentials::from(packet.data));
+                            }
+                            continue;
+                        }
+                        PacketKind::Registry => {
+                            if let Err(err) = map_peer_registry.update(&packet.data) {
+                                error!(
+                                    "Could not read the message registry of {}: {}",
+                                    conn_id, err
+                                );
+                            }
+
+                            if pending_admission {
+                                pending_admission = false;
+                                let mut result = pending_handshake
+                                    .take()
+                                    .map_or(Ok(()), |pending| pending.complete(&map_peer_registry))
+                                    .map_err(Refusal::from);
+                                let identity = match (result.is_ok(), map_authentication.as_ref()) {
+                                    (true, Some(authentication)) => match authentication
+                                        .validate(credentials.take(), info)
+                                        .await
+                                    {
+                                        Ok(identity) => Some(identity),
+                                        Err(err) => {
+                                            result = Err(err.into());
+                                            None
+                                        }
+                                    },
+                                    _ => None,
+                                };
+                                let accepted = result.is_ok();
 
-        server.established_connections.insert(
-                conn_id,
-                Connection {
-                    receive_task: Box::new(runtime.spawn(async move {
-                        trace!("Starting listen task for {}", conn_id);
-                        NSP::recv_loop(read_half, incoming_tx, read_network_settings).await;
-
-                        match disconnected_connections.send(conn_id).await {
-                            Ok(_) => (),
-                            Err(_) => {
-                                error!("Could not send disconnected event, because channel is disconnected");
+                                let (assigned_tx, assigned_rx) = async_channel::bounded(1);
+                                let admission = Admission {
+                                    conn_id,
+                                    result,
+                                    identity,
+                                    resume: resume.take(),
+                                    assigned: assigned_tx,
+                                };
+                                if admissions.send(admission).await.is_err() || !accepted {
+                                    return;
+                                }
+                                conn_id = match assigned_rx.recv().await {
+                                    Ok(conn_id) => conn_id,
+                                    Err(_) => return,
+                                };
                             }
+                            continue;
                         }
-                    })),
-                    map_receive_task: Box::new(runtime.spawn(async move{
-                        while let Ok(packet) = incoming_rx.recv().await{
-                            match recv_message_map.get_mut(&packet.kind[..]) {
-                                Some(mut packets) => packets.push((conn_id, packet.data)),
-                                None => {
-                                    error!("Could not find existing entries for message kinds: {:?}", packet);
+                        PacketKind::Heartbeat => continue,
+                        PacketKind::Disconnect => {
+                            match DisconnectReason::from_announcement(&packet.data) {
+                                Ok(reason) => break (Some(reason), false),
+                                Err(err) => {
+                                    error!("Could not read why {} disconnected: {}", conn_id, err);
+                                    break (Some(DisconnectReason::Eof), false);
                                 }
                             }
                         }
-                    })),
-                    send_task: Box::new(runtime.spawn(async move {
-                        trace!("Starting send task for {}", conn_id);
-                        NSP::send_loop(write_half, outgoing_rx, write_network_settings).await;
-                    })),
-                    send_message: outgoing_tx,
-                    //addr: new_conn.addr,
+                        _ => (),
+                    }
+
+                    // Clients only get to send messages once they are let in
+                    if pending_admission {
+                        warn!("Dropping a message {} sent before it was let in", conn_id);
+                        continue;
+                    }
+
+                    match registry
+                        .name_of(&packet.kind)
+                        .and_then(|name| recv_message_map.get_mut(name))
+                    {
+                        Some(mut packets) => packets.push((conn_id, packet.data)),
+                        None => {
+                            error!(
+                                "Could not find existing entries for message kinds: {:?}",
+                                packet
+                            );
+                        }
+                    }
+                };
+
+                let reason = match reason {
+                    Some(reason) => reason,
+                    None => return,
+                };
+                if tell_peer && reason != DisconnectReason::Eof {
+                    let _ = map_outgoing_tx.try_send(reason.announcement());
+                }
+
+                if pending_admission {
+                    let (assigned, _) = async_channel::bounded(1);
+                    let _ = admissions
+                        .send(Admission {
+                            conn_id,
+                            result: Err(HandshakeError::Closed.into()),
+                            identity: None,
+                            resume: None,
+                            assigned,
+                        })
+                        .await;
+                } else {
+                    info!("{} disconnected: {:?}", conn_id, reason);
+                    let _ = disconnected_connections.send((conn_id, reason)).await;
+                }
+            })),
+            send_task: Box::new(runtime.spawn(async move {
+                trace!("Starting send task for {}", conn_id);
+                let send = future::zip(
+                    NSP::send_loop(write_half, outgoing_rx, write_network_settings),
+                    future::zip(prioritized, future::zip(heartbeats, chunks)),
+                );
+                send_until_closed(send, closed_rx, CLOSE_TIMEOUT).await;
+            })),
+            closed: closed_tx,
+            outgoing,
+            transfers: transfer_tx,
+            peer_registry,
+            #[cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+            compression: compression.as_deref().cloned(),
+            info,
+        };
+
+        if let Some(session) = session {
+            server.sessions.insert(conn_id, session);
+        }
+        server.pending_connections.insert(conn_id, connection);
+    }
+
+    // Goes first, so that a connection that is about to be resumed is suspended beforehand
+    while let Ok((conn_id, reason)) = server.disconnected_connections.receiver.try_recv() {
+        if let Some((_, connection)) = server.pending_connections.remove(&conn_id) {
+            // Was never let in, so there is nothing to report
+            connection.close();
+            server.sessions.remove(&conn_id);
+            continue;
+        }
+
+        if resumption.is_some() && session::is_resumable(&reason) {
+            if let Some(mut session) = server.sessions.get_mut(&conn_id) {
+                if let Some(mut connection) = server.established_connections.get_mut(&conn_id) {
+                    info!("Keeping the session of {} around", conn_id);
+                    connection.suspend();
+                    session.suspended = Some((Instant::now(), reason));
+                }
+                continue;
+            }
+        }
+
+        // Connections dropped on our side are reported below instead
+        if let Some((_, connection)) = server.established_connections.remove(&conn_id) {
+            connection.close();
+            server.sessions.remove(&conn_id);
+            server.forget(conn_id);
+            network_events.send(ServerNetworkEvent::Disconnected(conn_id, reason));
+        }
+    }
+
+    while let Ok(admission) = server.admissions.receiver.try_recv() {
+        let Admission {
+            conn_id,
+            result,
+            identity,
+            resume,
+            assigned,
+        } = admission;
+
+        // The connection might have been dropped in the meantime
+        let connection = match server.pending_connections.remove(&conn_id) {
+            Some((_, connection)) => connection,
+            None => continue,
+        };
+
+        if let Err(err) = result {
+            server.sessions.remove(&conn_id);
+            // Without a handshake, a connection that closed early is not worth an error
+            if handshake.is_none() && matches!(err, Refusal::Handshake(HandshakeError::Closed)) {
+                connection.close();
+                continue;
+            }
+
+            connection.close_with(&err.reason());
+            network_events.send(ServerNetworkEvent::Error(err.into_error(conn_id)));
+            continue;
+        }
+
+        // Only sessions of clients that were let in and lost their connection can be resumed,
+        // so a token can't be used to take over a connection that is still alive
+        let resumed = resume.and_then(|token| {
+            server
+                .sessions
+                .iter()
+                .find(|session| session.suspended.is_some() && session.is_resumed_by(&token))
+                .map(|session| *session.key())
+                .filter(|resumed_id| server.established_connections.contains_key(resumed_id))
+        });
+
+        match resumed {
+            Some(resumed_id) => {
+                info!("{} resumed the session of {}", conn_id, resumed_id);
+                let mut session = match server.sessions.remove(&conn_id) {
+                    Some((_, session)) => session,
+                    None => continue,
+                };
+                if let Some((_, previous)) = server.sessions.remove(&resumed_id) {
+                    // Whatever was queued while the client was gone
+                    while let Some((priority, packet)) = previous.outgoing.try_recv() {
+                        let _ = connection.outgoing.send(priority, packet);
+                    }
+                }
+                session.suspended = None;
+                send_session_token(&connection, &session, resumed_id);
+                admit(&connection, resumed_id);
+                server.sessions.insert(resumed_id, session);
+                if let Some(identity) = identity {
+                    server.identities.insert(resumed_id, identity);
+                }
+                if let Some(previous) = server
+                    .established_connections
+                    .insert(resumed_id, connection)
+                {
+                    previous.close();
+                }
+                let _ = assigned.try_send(resumed_id);
+            }
+            None => {
+                if let Some(session) = server.sessions.get(&conn_id) {
+                    send_session_token(&connection, &session, conn_id);
+                }
+                admit(&connection, conn_id);
+                if let Some(identity) = identity {
+                    server.identities.insert(conn_id, identity);
+                }
+                server.established_connections.insert(conn_id, connection);
+                let _ = assigned.try_send(conn_id);
+                network_events.send(ServerNetworkEvent::Connected(conn_id));
+            }
+        }
+    }
+
+    let grace_period = resumption
+        .as_ref()
+        .map_or(Duration::ZERO, |resumption| resumption.grace_period);
+    let expired: Vec<_> = server
+        .sessions
+        .iter()
+        .filter(|session| session.is_expired(grace_period))
+        .map(|session| *session.key())
+        .collect();
+    for conn_id in expired {
+        let reason = match server.sessions.remove(&conn_id) {
+            Some((
+                _,
+                Session {
+                    suspended: Some((_, reason)),
+                    ..
                 },
-            );
+            )) => reason,
+            _ => continue,
+        };
+        if let Some((_, connection)) = server.established_connections.remove(&conn_id) {
+            connection.close();
+        }
+        server.forget(conn_id);
+        network_events.send(ServerNetworkEvent::Disconnected(conn_id, reason));
+    }
 
-        network_events.send(ServerNetworkEvent::Connected(conn_id));
+    while let Ok((conn_id, reason)) = server.dropped_connections.receiver.try_recv() {
+        network_events.send(ServerNetworkEvent::Disconnected(conn_id, reason));
     }
 
-    while let Ok(disconnected_connection) = server.disconnected_connections.receiver.try_recv() {
-        server
-            .established_connections
-            .remove(&disconnected_connection);
-        network_events.send(ServerNetworkEvent::Disconnected(disconnected_connection));
+    while let Ok((conn_id, progress)) = server.transfer_progress.receiver.try_recv() {
+        network_events.send(ServerNetworkEvent::Transfer(conn_id, progress));
+    }
+
+    while let Ok(event) = server.room_events.receiver.try_recv() {
+        network_events.send(event);
     }
 }
 
@@ -265,30 +1154,141 @@ pub trait AppNetworkServerMessage {
     /// - Internal bookkeeping
     fn listen_for_server_message<T: ServerMessage, NSP: NetworkServerProvider>(
         &mut self,
+    ) -> &mut Self {
+        self.listen_for_server_message_with_codec::<T, NSP, JsonCodec>()
+    }
+
+    /// Register a server message type for a server that uses the [`Codec`] `C`
+    ///
+    /// ## Details
+    /// See [`AppNetworkServerMessage::listen_for_server_message`]
+    fn listen_for_server_message_with_codec<
+        T: ServerMessage,
+        NSP: NetworkServerProvider,
+        C: Codec,
+    >(
+        &mut self,
+    ) -> &mut Self {
+        self.listen_for_server_message_with_options::<T, NSP, C>(MessageOptions::default())
+    }
+
+    /// Register a server message type, and choose how clients send it
+    ///
+    /// ## Details
+    /// See [`AppNetworkServerMessage::listen_for_server_message`] and [`MessageOptions`]
+    fn listen_for_server_message_with_options<
+        T: ServerMessage,
+        NSP: NetworkServerProvider,
+        C: Codec,
+    >(
+        &mut self,
+        options: MessageOptions,
+    ) -> &mut Self;
+
+    /// Register a request type, to answer it
+    ///
+    /// ## Details
+    /// This will:
+    /// - Add a new event type of [`NetworkRequest<T>`]
+    /// - Register the type for transformation over the wire
+    /// - Internal bookkeeping
+    fn listen_for_server_request<T: RequestMessage, NSP: NetworkServerProvider>(
+        &mut self,
+    ) -> &mut Self {
+        self.listen_for_server_request_with_codec::<T, NSP, JsonCodec>()
+    }
+
+    /// Register a request type for a server that uses the [`Codec`] `C`
+    ///
+    /// ## Details
+    /// See [`AppNetworkServerMessage::listen_for_server_request`]
+    fn listen_for_server_request_with_codec<
+        T: RequestMessage,
+        NSP: NetworkServerProvider,
+        C: Codec,
+    >(
+        &mut self,
+    ) -> &mut Self {
+        self.listen_for_server_request_with_options::<T, NSP, C>(MessageOptions::default())
+    }
+
+    /// Register a request type, and choose how clients send it
+    ///
+    /// ## Details
+    /// See [`AppNetworkServerMessage::listen_for_server_request`] and [`MessageOptions`]
+    fn listen_for_server_request_with_options<
+        T: RequestMessage,
+        NSP: NetworkServerProvider,
+        C: Codec,
+    >(
+        &mut self,
+        options: MessageOptions,
     ) -> &mut Self;
 }
 
 impl AppNetworkServerMessage for App {
-    fn listen_for_server_message<T: ServerMessage, NSP: NetworkServerProvider>(
+    fn listen_for_server_message_with_options<
+        T: ServerMessage,
+        NSP: NetworkServerProvider,
+        C: Codec,
+    >(
         &mut self,
+        options: MessageOptions,
     ) -> &mut Self {
-        let server = self.world.get_resource::<NetworkServer<NSP>>().expect("Could not find `NetworkServer`. Be sure to include the `ServerPlugin` before listening for server messages.");
+        let server = self.world.get_resource::<NetworkServer<NSP, C>>().expect("Could not find `NetworkServer`. Be sure to include the `ServerPlugin` before listening for server messages.");
 
         debug!("Registered a new ServerMessage: {}", T::NAME);
+        register_name(server, T::NAME, options);
 
-        assert!(
-            !server.recv_message_map.contains_key(T::NAME),
-            "Duplicate registration of ServerMessage: {}",
-            T::NAME
-        );
-        server.recv_message_map.insert(T::NAME, Vec::new());
         self.add_event::<NetworkData<T>>();
-        self.add_system_to_stage(CoreStage::PreUpdate, register_server_message::<T, NSP>)
+        self.add_system_to_stage(CoreStage::PreUpdate, register_server_message::<T, NSP, C>)
     }
+
+    fn listen_for_server_request_with_options<
+        T: RequestMessage,
+        NSP: NetworkServerProvider,
+        C: Codec,
+    >(
+        &mut self,
+        options: MessageOptions,
+    ) -> &mut Self {
+        let server = self.world.get_resource::<NetworkServer<NSP, C>>().expect("Could not find `NetworkServer`. Be sure to include the `ServerPlugin` before listening for server requests.");
+
+        debug!("Registered a new RequestMessage: {}", T::NAME);
+        register_name(server, T::NAME, options);
+        server.peer_messages.insert(T::Response::NAME);
+
+        self.add_event::<NetworkRequest<T>>();
+        self.add_system_to_stage(CoreStage::PreUpdate, register_server_request::<T, NSP, C>)
+    }
+}
+
+fn register_name<NSP: NetworkServerProvider, C: Codec>(
+    server: &NetworkServer<NSP, C>,
+    name: &'static str,
+    options: MessageOptions,
+) {
+    assert!(
+        NetworkPacket::is_valid_name(name),
+        "ServerMessage name is too long: {}",
+        name
+    );
+    assert!(
+        NetworkPacket::is_valid_name(options.channel.name()),
+        "Channel name is too long: {}",
+        options.channel.name()
+    );
+    assert!(
+        !server.recv_message_map.contains_key(name),
+        "Duplicate registration of ServerMessage: {}",
+        name
+    );
+    server.recv_message_map.insert(name, Vec::new());
+    server.message_options.insert(name, options);
 }
 
-fn register_server_message<T, NSP: NetworkServerProvider>(
-    net_res: ResMut<NetworkServer<NSP>>,
+fn register_server_message<T, NSP: NetworkServerProvider, C: Codec>(
+    net_res: ResMut<NetworkServer<NSP, C>>,
     mut events: EventWriter<NetworkData<T>>,
 ) where
     T: ServerMessage,
@@ -298,9 +1298,37 @@ fn register_server_message<T, NSP: NetworkServerProvider>(
         None => return,
     };
 
-    events.send_batch(messages.drain(..).filter_map(|(source, msg)| {
-        serde_json::from_str(&msg)
-            .ok()
-            .map(|inner| NetworkData { source, inner })
+    events.send_batch(
+        messages
+            .drain(..)
+            .filter_map(|(source, msg)| match C::decode(&msg) {
+                Ok(inner) => Some(NetworkData { source, inner }),
+                Err(err) => {
+                    warn!("Could not decode {} from {}: {}", T::NAME, source, err);
+                    None
+                }
+            }),
+    );
+}
+
+fn register_server_request<T, NSP: NetworkServerProvider, C: Codec>(
+    net_res: ResMut<NetworkServer<NSP, C>>,
+    mut events: EventWriter<NetworkRequest<T>>,
+) where
+    T: RequestMessage,
+{
+    let mut requests = match net_res.recv_message_map.get_mut(T::NAME) {
+        Some(requests) => requests,
+        None => return,
+    };
+
+    events.send_batch(requests.drain(..).filter_map(|(source, request)| {
+        match request::untag(request).and_then(|(id, request)| Ok((id, C::decode(&request)?))) {
+            Ok((id, inner)) => Some(NetworkRequest::new(source, id, inner)),
+            Err(err) => {
+                warn!("Could not decode {} from {}: {}", T::NAME, source, err);
+                None
+            }
+        }
     }));
 }
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use bevy::utils::Uuid;
+use bytes::Bytes;
+
+use crate::{network_packet::PacketKind, queue::Queued, Channel, DisconnectReason, NetworkPacket};
+
+/// Insert this as a resource on the server to let clients pick up where they left off.
+///
+/// Every client is handed a session token once it's connected. A client that lost its
+/// connection presents that token when it connects again, and if that happens within the
+/// [`SessionResumption::grace_period`] it gets its old [`ConnectionId`](crate::ConnectionId)
+/// back. Messages sent to it in the meantime are queued up and delivered once it's back.
+///
+/// Only connections that broke are kept around, see [`DisconnectReason::Eof`] and
+/// [`DisconnectReason::TimedOut`]. Until the grace period is over, the server doesn't
+/// emit `Disconnected` for them.
+///
+/// ## Note
+/// Clients present their token on their own, they need no extra setup
+#[derive(Debug, Clone)]
+pub struct SessionResumption {
+    /// How long a broken connection is kept around for its client to come back
+    ///
+    /// ## Default
+    /// The default is set to 30 seconds
+    pub grace_period: Duration,
+}
+
+impl Default for SessionResumption {
+    fn default() -> Self {
+        Self {
+            grace_period: Duration::from_secs(30),
+        }
+    }
+}
+
+/// What the server remembers about a connection, so its client can resume it
+#[derive(Debug)]
+pub(crate) struct Session {
+    token: Uuid,
+    /// The other end of the connection's outgoing queue, kept so it stays open while suspended
+    pub(crate) outgoing: Queued,
+    /// Since when, and why, the connection has been broken
+    pub(crate) suspended: Option<(Instant, DisconnectReason)>,
+}
+
+impl Session {
+    pub(crate) fn new(outgoing: Queued) -> Self {
+        Self {
+            token: Uuid::new_v4(),
+            outgoing,
+            suspended: None,
+        }
+    }
+
+    /// The packet handing the token to the client
+    pub(crate) fn announcement(&self) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Session,
+            data: Bytes::copy_from_slice(self.token.as_bytes()),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Whether the client presented this session's token
+    ///
+    /// Looks at every byte, so how long it takes doesn't give away how much of a guess was right.
+    pub(crate) fn is_resumed_by(&self, token: &[u8]) -> bool {
+        let own = self.token.as_bytes();
+        own.len() == token.len()
+            && own
+                .iter()
+                .zip(token)
+                .fold(0, |difference, (own, other)| difference | (own ^ other))
+                == 0
+    }
+
+    /// Whether the grace period for a suspended session is over
+    pub(crate) fn is_expired(&self, grace_period: Duration) -> bool {
+        matches!(&self.suspended, Some((since, _)) if since.elapsed() >= grace_period)
+    }
+}
+
+/// The packet a client opens a new connection with, to resume its last session
+pub(crate) fn resume_request(token: Bytes) -> NetworkPacket {
+    NetworkPacket {
+        kind: PacketKind::Resume,
+        data: token,
+        channel: Channel::DEFAULT,
+        compressed: false,
+    }
+}
+
+/// Whether a connection that ended for `reason` may be resumed
+pub(crate) fn is_resumable(reason: &DisconnectReason) -> bool {
+    matches!(reason, DisconnectReason::Eof | DisconnectReason::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::Outgoing;
+
+    fn session() -> Session {
+        let (_, queued) = Outgoing::new(None);
+        Session::new(queued)
+    }
+
+    #[test]
+    fn only_its_own_token_resumes_a_session() {
+        let session = session();
+        let token = session.announcement().data;
+        assert!(session.is_resumed_by(&token));
+
+        let mut guess = token.to_vec();
+        guess[15] ^= 1;
+        assert!(!session.is_resumed_by(&guess));
+        assert!(!session.is_resumed_by(&token[..15]));
+        assert!(!session.is_resumed_by(&[]));
+        assert!(!session.is_resumed_by(&self::session().announcement().data));
+    }
+
+    #[test]
+    fn sessions_expire_once_suspended_for_the_grace_period() {
+        let mut session = session();
+        assert!(!session.is_expired(Duration::ZERO));
+
+        session.suspended = Some((Instant::now(), DisconnectReason::Eof));
+        assert!(!session.is_expired(Duration::from_secs(30)));
+        assert!(session.is_expired(Duration::ZERO));
+    }
+
+    #[test]
+    fn only_broken_connections_are_resumable() {
+        assert!(is_resumable(&DisconnectReason::Eof));
+        assert!(is_resumable(&DisconnectReason::TimedOut));
+        assert!(!is_resumable(&DisconnectReason::Left));
+        assert!(!is_resumable(&DisconnectReason::Shutdown));
+    }
+}
@@ -5,12 +5,12 @@ use crate::{
     async_trait,
     client::NetworkClientProvider,
     error::NetworkError,
+    framing::{self, Batching},
     server::NetworkServerProvider,
-    ClientNetworkEvent, NetworkPacket,
+    ClientNetworkEvent, DisconnectReason, NetworkPacket,
 };
 use async_net::{TcpListener, TcpStream};
-use bevy::log::{debug, error, info, trace};
-use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use bevy::log::{debug, error, info};
 
 #[derive(Default, Debug)]
 /// Provides a tcp stream and listener for eventwork.
@@ -63,118 +63,32 @@ impl NetworkServerProvider for TcpServerProvider {
     }
 
     async fn recv_loop(
-        mut read_half: Self::ReadHalf,
+        read_half: Self::ReadHalf,
         messages: Sender<NetworkPacket>,
         settings: Self::NetworkSettings,
-    ) {
-        let mut buffer = vec![0; settings.max_packet_length];
-        loop {
-            trace!("Reading message length");
-            let length = match read_half.read(&mut buffer[..8]).await {
-                Ok(0) => {
-                    // EOF, meaning the TCP stream has closed.
-                    info!("Client disconnected");
-                    // TODO: probably want to do more than just quit the receive task.
-                    //       to let eventwork know that the peer disconnected.
-                    break;
-                }
-                Ok(8) => {
-                    let bytes = &buffer[..8];
-                    u64::from_le_bytes(bytes.try_into().unwrap()) as usize
-                }
-                Ok(n) => {
-                    error!(
-                        "Could not read enough bytes for header. Expected 8, got {}",
-                        n
-                    );
-                    break;
-                }
-                Err(err) => {
-                    error!("Encountered error while fetching length: {}", err);
-                    break;
-                }
-            };
-            debug!("Receiving new message of size: {}", length);
-
-            if length > settings.max_packet_length {
-                error!(
-                    "Received too large packet: {} > {}",
-                    length, settings.max_packet_length
-                );
-                break;
-            }
-
-            trace!("Reading message into buffer");
-            match read_half.read_exact(&mut buffer[..length]).await {
-                Ok(()) => (),
-                Err(err) => {
-                    error!(
-                        "Encountered error while fetching stream of length {}: {}",
-                        length, err
-                    );
-                    break;
-                }
-            }
-            trace!("Message read");
-
-            let packet: NetworkPacket = match serde_json::from_slice(&buffer[..length]) {
-                Ok(packet) => packet,
-                Err(err) => {
-                    error!("Failed to decode network packet from: {}", err);
-                    break;
-                }
-            };
-
-            if let Err(_) = messages.send(packet).await {
-                error!("Failed to send decoded message to eventwork");
-                break;
-            }
-            trace!("Message deserialized and sent to eventwork");
-        }
+    ) -> DisconnectReason {
+        framing::recv_loop(read_half, messages, settings.max_packet_length).await
     }
 
     async fn send_loop(
-        mut write_half: Self::WriteHalf,
+        write_half: Self::WriteHalf,
         messages: Receiver<NetworkPacket>,
-        _settings: Self::NetworkSettings,
+        settings: Self::NetworkSettings,
     ) {
-        while let Ok(message) = messages.recv().await {
-            let encoded = match serde_json::to_string(&message) {
-                Ok(encoded) => encoded,
-                Err(err) => {
-                    error!("Could not encode packet {:?}: {}", message, err);
-                    continue;
-                }
-            };
-
-            let len = encoded.len() as u64;
-            debug!("Sending a new message of size: {}", len);
-
-            match write_half.write(&len.to_le_bytes()).await {
-                Ok(_) => (),
-                Err(err) => {
-                    error!("Could not send packet length: {:?}: {}", len, err);
-                    break;
-                }
-            }
-
-            trace!("Sending the content of the message!");
-
-            match write_half.write_all(&encoded.as_bytes()).await {
-                Ok(_) => (),
-                Err(err) => {
-                    error!("Could not send packet: {:?}: {}", message, err);
-                    break;
-                }
-            }
-
-            trace!("Succesfully written all!");
-        }
+        framing::send_loop(write_half, messages, settings.batching.as_ref()).await
     }
 
     fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
         (combined.clone(), combined)
     }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.peer_addr().ok()
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.local_addr().ok()
+    }
 }
 
 #[derive(Default, Debug)]
@@ -231,119 +145,32 @@ impl NetworkClientProvider for TcpClientProvider {
     }
 
     async fn recv_loop(
-        mut read_half: Self::ReadHalf,
+        read_half: Self::ReadHalf,
         messages: Sender<NetworkPacket>,
         settings: Self::NetworkSettings,
-    ) {
-        let mut buffer = vec![0; settings.max_packet_length];
-        loop {
-            trace!("Reading message length");
-            let length = match read_half.read(&mut buffer[..8]).await {
-                Ok(0) => {
-                    // EOF, meaning the TCP stream has closed.
-                    info!("Client disconnected");
-                    // TODO: probably want to do more than just quit the receive task.
-                    //       to let eventwork know that the peer disconnected.
-                    break;
-                }
-                Ok(8) => {
-                    let bytes = &buffer[..8];
-                    u64::from_le_bytes(bytes.try_into().unwrap()) as usize
-                }
-                Ok(n) => {
-                    error!(
-                        "Could not read enough bytes for header. Expected 8, got {}",
-                        n
-                    );
-                    break;
-                }
-                Err(err) => {
-                    error!("Encountered error while fetching length: {}", err);
-                    break;
-                }
-            };
-            trace!("Info read");
-
-            if length > settings.max_packet_length {
-                error!(
-                    "Received too large packet: {} > {}",
-                    length, settings.max_packet_length
-                );
-                break;
-            }
-
-            trace!("Reading message into buffer");
-            match read_half.read_exact(&mut buffer[..length]).await {
-                Ok(_) => (),
-                Err(err) => {
-                    error!(
-                        "Encountered error while fetching stream of length {}: {}",
-                        length, err
-                    );
-                    break;
-                }
-            }
-            trace!("Message read");
-
-            let packet: NetworkPacket = match serde_json::from_slice(&buffer[..length]) {
-                Ok(packet) => packet,
-                Err(err) => {
-                    error!("Failed to decode network packet from: {}", err);
-                    break;
-                }
-            };
-
-            if let Err(_) = messages.send(packet).await {
-                error!("Failed to send decoded message to Spicy");
-                break;
-            }
-        }
+    ) -> DisconnectReason {
+        framing::recv_loop(read_half, messages, settings.max_packet_length).await
     }
 
     async fn send_loop(
-        mut write_half: Self::WriteHalf,
+        write_half: Self::WriteHalf,
         messages: Receiver<NetworkPacket>,
-        _settings: Self::NetworkSettings,
+        settings: Self::NetworkSettings,
     ) {
-        while let Ok(message) = messages.recv().await {
-            info!("Sending message!");
-
-            let encoded = match serde_json::to_string(&message) {
-                Ok(encoded) => encoded,
-                Err(err) => {
-                    error!("Could not encode packet {:?}: {}", message, err);
-                    continue;
-                }
-            };
-
-            let len = encoded.len() as u64;
-            debug!("Sending a new message of size: {}", len);
-
-            match write_half.write(&len.to_le_bytes()).await {
-                Ok(_) => (),
-                Err(err) => {
-                    error!("Could not send packet length: {:?}: {}", len, err);
-                    break;
-                }
-            }
-
-            trace!("Sending the content of the message!");
-
-            match write_half.write_all(&encoded.as_bytes()).await {
-                Ok(_) => (),
-                Err(err) => {
-                    error!("Could not send packet: {:?}: {}", message, err);
-                    break;
-                }
-            }
-
-            info!("Message sent!");
-        }
+        framing::send_loop(write_half, messages, settings.batching.as_ref()).await
     }
 
     fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
         (combined.clone(), combined)
     }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.peer_addr().ok()
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.local_addr().ok()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -352,20 +179,148 @@ impl NetworkClientProvider for TcpClientProvider {
 pub struct NetworkSettings {
     /// Maximum packet size in bytes. If a client ever exceeds this size, they will be disconnected
     ///
+    /// Larger messages can be sent in chunks instead, see [`Transfers`](crate::Transfers).
+    /// Every chunk is a packet of its own, so [`Transfers::chunk_size`](crate::Transfers::chunk_size)
+    /// has to stay a few bytes below this, to leave room for the headers. How large a whole
+    /// transfer may get is up to [`Transfers::max_length`](crate::Transfers::max_length) instead.
+    ///
     /// ## Default
-    /// The default is set to 10MiB
+    /// The default is set to 128KiB, twice the default chunk size
     pub max_packet_length: usize,
 
     /// Address to connect to or port to open
     pub addr: SocketAddr,
+
+    /// Collect packets and write them at once, instead of one by one
+    ///
+    /// ## Default
+    /// The default is `None`, every packet is written on its own
+    pub batching: Option<Batching>,
 }
 
 impl NetworkSettings {
     /// Create a new instance of [`NetworkSettings`]
     pub fn new(addr: impl Into<SocketAddr>) -> Self {
         Self {
-            max_packet_length: 10 * 1024 * 1024,
+            max_packet_length: 128 * 1024,
             addr: addr.into(),
+            batching: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use bevy::{
+        prelude::*,
+        tasks::{TaskPool, TaskPoolBuilder},
+    };
+
+    use super::*;
+    use crate::{
+        ClientPlugin, ConnectionId, NetworkClient, NetworkServer, ServerNetworkEvent, ServerPlugin,
+    };
+
+    /// A port nothing is listening on right now
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("a port is free")
+    }
+
+    #[test]
+    fn connections_know_their_addresses() {
+        let started = SystemTime::now();
+        let pool = TaskPoolBuilder::new().num_threads(2).build();
+        let settings = NetworkSettings::new(free_addr());
+
+        let mut server = App::new();
+        server
+            .add_plugins(MinimalPlugins)
+            .insert_resource(pool.clone())
+            .insert_resource(settings.clone())
+            .init_resource::<Vec<ConnectionId>>()
+            .add_plugin(ServerPlugin::<TcpServerProvider, TaskPool>::default())
+            .add_system(
+                |mut events: EventReader<ServerNetworkEvent>,
+                 mut clients: ResMut<Vec<ConnectionId>>| {
+                    for event in events.iter() {
+                        if let ServerNetworkEvent::Connected(conn_id) = event {
+                            clients.push(*conn_id);
+                        }
+                    }
+                },
+            );
+        server
+            .world
+            .resource_mut::<NetworkServer<TcpServerProvider>>()
+            .listen(&pool, &settings)
+            .expect("port is free");
+
+        let mut client = App::new();
+        client
+            .add_plugins(MinimalPlugins)
+            .insert_resource(pool.clone())
+            .insert_resource(settings.clone())
+            .add_plugin(ClientPlugin::<TcpClientProvider, TaskPool>::default())
+            // The server binds in the background, so the first attempts may be refused
+            .add_system(
+                |mut net: ResMut<NetworkClient<TcpClientProvider>>,
+                 mut events: EventReader<ClientNetworkEvent>,
+                 pool: Res<TaskPool>,
+                 settings: Res<NetworkSettings>| {
+                    for event in events.iter() {
+                        if let ClientNetworkEvent::Error(_) = event {
+                            net.connect(&*pool, &*settings);
+                        }
+                    }
+                },
+            );
+        client
+            .world
+            .resource_mut::<NetworkClient<TcpClientProvider>>()
+            .connect(&pool, &settings);
+
+        let mut infos = None;
+        for _ in 0..200 {
+            server.update();
+            client.update();
+            let conn_id = server
+                .world
+                .resource::<Vec<ConnectionId>>()
+                .first()
+                .copied();
+            let server_info = conn_id.and_then(|conn_id| {
+                server
+                    .world
+                    .resource::<NetworkServer<TcpServerProvider>>()
+                    .connection_info(conn_id)
+            });
+            let client_info = client
+                .world
+                .resource::<NetworkClient<TcpClientProvider>>()
+                .connection_info();
+            if let (Some(server_info), Some(client_info)) = (server_info, client_info) {
+                infos = Some((server_info, client_info));
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        let (server_info, client_info) = infos.expect("client connected");
+
+        assert_eq!(server_info.local_addr, Some(settings.addr));
+        assert_eq!(client_info.peer_addr, Some(settings.addr));
+        assert!(client_info.local_addr.is_some());
+        assert_eq!(server_info.peer_addr, client_info.local_addr);
+
+        let now = SystemTime::now();
+        for info in [server_info, client_info] {
+            assert!(started <= info.connected_at && info.connected_at <= now);
         }
     }
 }
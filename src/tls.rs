@@ -0,0 +1,506 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::{
+    async_channel::{Receiver, Sender},
+    async_trait,
+    client::NetworkClientProvider,
+    error::NetworkError,
+    framing::{self, Batching},
+    server::NetworkServerProvider,
+    ClientNetworkEvent, DisconnectReason, NetworkPacket,
+};
+use async_io::Timer;
+use async_net::{TcpListener, TcpStream};
+use bevy::log::{debug, error, info};
+use futures_lite::{
+    future,
+    io::{split, ReadHalf, WriteHalf},
+    StreamExt,
+};
+use futures_rustls::{
+    client, pki_types::ServerName, rustls, rustls::crypto::CryptoProvider, server, TlsAcceptor,
+    TlsConnector,
+};
+use futures_util::stream::FuturesUnordered;
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    server::WebPkiClientVerifier,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+
+#[derive(Default, Debug)]
+/// Provides a tcp listener for eventwork, with every connection wrapped in TLS.
+///
+/// Packets are framed exactly like in the `tcp` provider.
+pub struct TlsServerProvider;
+
+#[async_trait]
+impl NetworkServerProvider for TlsServerProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = server::TlsStream<TcpStream>;
+
+    type ReadHalf = ReadHalf<server::TlsStream<TcpStream>>;
+
+    type WriteHalf = WriteHalf<server::TlsStream<TcpStream>>;
+
+    async fn accept_loop(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<NetworkError>,
+    ) {
+        let acceptor = match network_settings.server_config() {
+            Ok(config) => TlsAcceptor::from(Arc::new(config)),
+            Err(err) => {
+                if let Err(err) = errors.send(NetworkError::Listen(err)).await {
+                    error!("Could not send listen error: {}", err);
+                }
+                return;
+            }
+        };
+
+        let listener = match TcpListener::bind(network_settings.addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                if let Err(err) = errors.send(NetworkError::Listen(err)).await {
+                    error!("Could not send listen error: {}", err);
+                }
+                return;
+            }
+        };
+
+        enum Accepted {
+            Stream(io::Result<TcpStream>),
+            Handshake(io::Result<Box<server::TlsStream<TcpStream>>>),
+        }
+
+        // Handshakes run concurrently, so a slow client can't hold up everyone else,
+        // and they time out, so one that never finishes doesn't stick around forever
+        let mut handshakes = FuturesUnordered::new();
+        loop {
+            let accepted = future::or(
+                async { Accepted::Stream(listener.accept().await.map(|(socket, _addr)| socket)) },
+                async {
+                    match handshakes.next().await {
+                        Some(handshake) => Accepted::Handshake(handshake),
+                        None => future::pending().await,
+                    }
+                },
+            )
+            .await;
+
+            let error = match accepted {
+                Accepted::Stream(Ok(socket)) => {
+                    let accept = acceptor.accept(socket);
+                    let timeout = network_settings.handshake_timeout;
+                    handshakes.push(future::or(
+                        async move { accept.await.map(Box::new) },
+                        async move {
+                            Timer::after(timeout).await;
+                            Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "The TLS handshake took too long",
+                            ))
+                        },
+                    ));
+                    continue;
+                }
+                Accepted::Handshake(Ok(stream)) => {
+                    if let Err(err) = new_connections.send(*stream).await {
+                        error!("Could not send listen error: {}", err);
+                        return;
+                    }
+                    info!("New Connection Made!");
+                    continue;
+                }
+                Accepted::Stream(Err(error)) | Accepted::Handshake(Err(error)) => error,
+            };
+
+            if let Err(err) = errors.send(NetworkError::Accept(error)).await {
+                error!("Could not send listen error: {}", err);
+                return;
+            };
+        }
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        framing::recv_loop(read_half, messages, settings.max_packet_length).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        framing::send_loop(write_half, messages, settings.batching.as_ref()).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        split(combined)
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().0.peer_addr().ok()
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().0.local_addr().ok()
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides a TLS wrapped tcp stream for eventwork.
+pub struct TlsClientProvider;
+
+#[async_trait]
+impl NetworkClientProvider for TlsClientProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = client::TlsStream<TcpStream>;
+
+    type ReadHalf = ReadHalf<client::TlsStream<TcpStream>>;
+
+    type WriteHalf = WriteHalf<client::TlsStream<TcpStream>>;
+
+    async fn connect_task(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<ClientNetworkEvent>,
+    ) {
+        info!("Beginning connection");
+        let stream = match connect(&network_settings).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                match errors
+                    .send(ClientNetworkEvent::Error(NetworkError::Connection(error)))
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(err) => {
+                        error!("Could not send error event: {}", err);
+                    }
+                }
+
+                return;
+            }
+        };
+
+        info!("Connected!");
+
+        match new_connections.send(stream).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not initiate connection: {}", err);
+            }
+        }
+
+        debug!(
+            "Connected to: {:?} ({})",
+            network_settings.addr, network_settings.server_name
+        );
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        framing::recv_loop(read_half, messages, settings.max_packet_length).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        framing::send_loop(write_half, messages, settings.batching.as_ref()).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        split(combined)
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().0.peer_addr().ok()
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().0.local_addr().ok()
+    }
+}
+
+async fn connect(settings: &NetworkSettings) -> io::Result<client::TlsStream<TcpStream>> {
+    let connector = TlsConnector::from(Arc::new(settings.client_config()?));
+    let server_name = ServerName::try_from(settings.server_name.clone())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let stream = TcpStream::connect(settings.addr).await?;
+    connector.connect(server_name, stream).await
+}
+
+fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+#[derive(Clone, Debug)]
+/// Settings to configure the network, both client and server
+///
+/// The certificate chain and private key are this side's own identity, the trusted
+/// certificates are the roots the other side's certificate has to chain up to.
+pub struct NetworkSettings {
+    /// Maximum packet size in bytes. If a client ever exceeds this size, they will be disconnected
+    ///
+    /// Larger messages can be sent in chunks instead, see [`Transfers`](crate::Transfers).
+    /// Every chunk is a packet of its own, so [`Transfers::chunk_size`](crate::Transfers::chunk_size)
+    /// has to stay a few bytes below this, to leave room for the headers. How large a whole
+    /// transfer may get is up to [`Transfers::max_length`](crate::Transfers::max_length) instead.
+    ///
+    /// ## Default
+    /// The default is set to 128KiB, twice the default chunk size
+    pub max_packet_length: usize,
+
+    /// Address to connect to or port to open
+    pub addr: SocketAddr,
+
+    /// The name a client sends as SNI and expects in the server's certificate
+    ///
+    /// ## Default
+    /// The default is set to `localhost`
+    pub server_name: String,
+
+    /// The certificates presented to the peer, starting with this side's own
+    ///
+    /// Required on the server, a client only needs it for client certificate authentication.
+    pub certificate_chain: Vec<CertificateDer<'static>>,
+
+    /// The private key belonging to the first certificate of [`NetworkSettings::certificate_chain`]
+    pub private_key: Option<Arc<PrivateKeyDer<'static>>>,
+
+    /// The root certificates used to verify the peer
+    ///
+    /// A client checks the server against these, a server checks client certificates
+    /// against them if [`NetworkSettings::require_client_certificate`] is set.
+    pub trusted_certificates: Vec<CertificateDer<'static>>,
+
+    /// Whether the server only accepts clients that present a trusted certificate
+    ///
+    /// ## Default
+    /// The default is set to `false`
+    pub require_client_certificate: bool,
+
+    /// How long a client may take to finish the TLS handshake before it is dropped
+    ///
+    /// ## Default
+    /// The default is set to 10 seconds
+    pub handshake_timeout: Duration,
+
+    /// Collect packets and write them at once, instead of one by one
+    ///
+    /// ## Default
+    /// The default is `None`, every packet is written on its own
+    pub batching: Option<Batching>,
+}
+
+impl NetworkSettings {
+    /// Create a new instance of [`NetworkSettings`]
+    ///
+    /// ## Note
+    /// No certificates are set up, see [`NetworkSettings::self_signed`] for local development.
+    pub fn new(addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            max_packet_length: 128 * 1024,
+            addr: addr.into(),
+            server_name: String::from("localhost"),
+            certificate_chain: Vec::new(),
+            private_key: None,
+            trusted_certificates: Vec::new(),
+            require_client_certificate: false,
+            handshake_timeout: Duration::from_secs(10),
+            batching: None,
+        }
+    }
+
+    /// Create a new instance of [`NetworkSettings`] with a freshly generated certificate for `localhost`
+    ///
+    /// The certificate is both presented and trusted, so the same settings work for a server and its clients.
+    pub fn self_signed(addr: impl Into<SocketAddr>) -> Result<Self, rcgen::Error> {
+        let certified = rcgen::generate_simple_self_signed(vec![String::from("localhost")])?;
+        let certificate = certified.cert.der().clone();
+        let private_key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der()).into();
+
+        Ok(Self {
+            certificate_chain: vec![certificate.clone()],
+            private_key: Some(Arc::new(private_key)),
+            trusted_certificates: vec![certificate],
+            ..Self::new(addr)
+        })
+    }
+
+    fn root_store(&self) -> io::Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for certificate in &self.trusted_certificates {
+            roots.add(certificate.clone()).map_err(io::Error::other)?;
+        }
+        Ok(roots)
+    }
+
+    fn server_config(&self) -> io::Result<ServerConfig> {
+        let private_key = self.private_key.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A private key is needed to accept TLS connections",
+            )
+        })?;
+
+        let builder = ServerConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?;
+
+        let builder = if self.require_client_certificate {
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(self.root_store()?),
+                crypto_provider(),
+            )
+            .build()
+            .map_err(io::Error::other)?;
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        builder
+            .with_single_cert(self.certificate_chain.clone(), private_key.clone_key())
+            .map_err(io::Error::other)
+    }
+
+    fn client_config(&self) -> io::Result<ClientConfig> {
+        let builder = ClientConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(self.root_store()?);
+
+        match &self.private_key {
+            Some(private_key) if !self.certificate_chain.is_empty() => builder
+                .with_client_auth_cert(self.certificate_chain.clone(), private_key.clone_key())
+                .map_err(io::Error::other),
+            _ => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_channel::unbounded, network_packet::PacketKind, Channel};
+
+    /// Settings for a server on a free port of the loopback interface
+    fn settings() -> NetworkSettings {
+        let addr = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|listener| listener.local_addr())
+            .expect("loopback is available");
+        NetworkSettings::self_signed(addr).expect("certificate can be generated")
+    }
+
+    fn packet(data: Vec<u8>) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Name("test".to_owned()),
+            data: data.into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Runs `test` next to a server, which binds before the test gets to connect
+    fn with_server(
+        settings: &NetworkSettings,
+        connections_tx: Sender<server::TlsStream<TcpStream>>,
+        test: impl future::Future<Output = ()>,
+    ) -> Receiver<NetworkError> {
+        let (errors_tx, errors_rx) = unbounded();
+        future::block_on(future::or(
+            async {
+                TlsServerProvider::accept_loop(settings.clone(), connections_tx, errors_tx).await;
+                panic!("server stopped");
+            },
+            test,
+        ));
+        errors_rx
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let settings = settings();
+        let (connections_tx, connections_rx) = unbounded();
+
+        with_server(&settings, connections_tx, async {
+            let client = connect(&settings).await.expect("server is listening");
+            let accepted = connections_rx
+                .recv()
+                .await
+                .expect("server accepted the client");
+            let (client_read, client_write) = TlsClientProvider::split(client);
+            let (server_read, server_write) = TlsServerProvider::split(accepted);
+
+            // Client to server and back again
+            let (outgoing_tx, outgoing_rx) = unbounded();
+            let (received_tx, received_rx) = unbounded();
+            let (echo_tx, echo_rx) = unbounded();
+            let (returned_tx, returned_rx) = unbounded();
+            outgoing_tx
+                .try_send(packet(vec![1, 2, 3]))
+                .expect("channel is open");
+
+            let run = future::zip(
+                future::zip(
+                    TlsClientProvider::send_loop(client_write, outgoing_rx, settings.clone()),
+                    TlsServerProvider::recv_loop(server_read, received_tx, settings.clone()),
+                ),
+                future::zip(
+                    TlsServerProvider::send_loop(server_write, echo_rx, settings.clone()),
+                    TlsClientProvider::recv_loop(client_read, returned_tx, settings.clone()),
+                ),
+            );
+            let check = async {
+                let received = received_rx.recv().await.expect("server got the packet");
+                assert_eq!(received.encode(), packet(vec![1, 2, 3]).encode());
+                echo_tx.try_send(received).expect("channel is open");
+
+                let returned = returned_rx.recv().await.expect("client got the echo");
+                assert_eq!(returned.encode(), packet(vec![1, 2, 3]).encode());
+
+                outgoing_tx.close();
+                echo_tx.close();
+            };
+            let (((), server_reason), ((), client_reason)) = future::zip(run, check).await.0;
+            assert_eq!(server_reason, DisconnectReason::Eof);
+            assert_eq!(client_reason, DisconnectReason::Eof);
+        });
+    }
+
+    #[test]
+    fn drops_clients_that_never_finish_the_handshake() {
+        let settings = NetworkSettings {
+            handshake_timeout: Duration::from_millis(100),
+            ..settings()
+        };
+        let (connections_tx, connections_rx) = unbounded();
+
+        let errors = with_server(&settings, connections_tx, async {
+            // A plain tcp connection never says hello
+            let _stream = TcpStream::connect(settings.addr)
+                .await
+                .expect("server is listening");
+            Timer::after(settings.handshake_timeout * 2).await;
+        });
+
+        assert!(connections_rx.is_empty());
+        match errors.try_recv().expect("server reports the timeout") {
+            NetworkError::Accept(err) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+use async_channel::{Receiver, Sender};
+use bevy::log::error;
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::{network_packet::PacketKind, Channel, ConnectionId, DisconnectReason, NetworkPacket};
+
+/// The transfer id, its total length and the offset of the chunk
+const HEADER_LEN: usize = 4 + 8 + 8;
+
+/// Insert this as a resource, on the server or the client, to tune how transfers are sent and received.
+///
+/// Transfers are large messages sent in chunks, see [`NetworkServer::send_transfer`](crate::NetworkServer::send_transfer)
+/// and [`NetworkClient::send_transfer`](crate::NetworkClient::send_transfer). Without this resource the defaults are used.
+#[derive(Debug, Clone)]
+pub struct Transfers {
+    /// How many bytes of a transfer are sent in a single packet
+    ///
+    /// Chunks are packets like any other, so this has to stay below the provider's
+    /// `max_packet_length`, with a few bytes to spare for the headers.
+    ///
+    /// ## Default
+    /// The default is set to 64KiB
+    pub chunk_size: usize,
+
+    /// How many bytes of incoming transfers a connection may buffer, before it is dropped
+    /// with [`DisconnectReason::Oversize`]
+    ///
+    /// ## Default
+    /// The default is set to 256MiB
+    pub max_length: usize,
+}
+
+impl Default for Transfers {
+    fn default() -> Self {
+        Self {
+            chunk_size: 64 * 1024,
+            max_length: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Identifies a transfer, unique per connection and direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransferId(u32);
+
+/// Whether a transfer is sent or received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    /// We are sending it, the id is the one `send_transfer` returned
+    Sending,
+    /// The peer is sending it, the id is the one the peer assigned
+    Receiving,
+}
+
+/// How far along a transfer is, sent as an event for every chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// Which transfer this is about
+    pub id: TransferId,
+    /// Whether it is sent or received
+    pub direction: TransferDirection,
+    /// How many bytes have been sent, or received, so far
+    pub transferred: usize,
+    /// How many bytes there are in total
+    pub total: usize,
+}
+
+impl TransferProgress {
+    /// Whether every byte has been sent, or received
+    ///
+    /// A received transfer is handed over as a regular message in the same frame.
+    pub fn is_complete(&self) -> bool {
+        self.transferred >= self.total
+    }
+
+    /// How far along the transfer is, from 0 to 1
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.transferred as f32 / self.total as f32
+        }
+    }
+}
+
+/// A message waiting to be sent in chunks
+#[derive(Debug)]
+pub(crate) struct Transfer {
+    pub(crate) id: TransferId,
+    /// Whom the progress is reported for
+    pub(crate) to: ConnectionId,
+    /// The message, encoded as a whole packet
+    pub(crate) packet: Bytes,
+}
+
+impl Transfer {
+    pub(crate) fn new(id: u32, to: ConnectionId, packet: NetworkPacket) -> Self {
+        Self {
+            id: TransferId(id),
+            to,
+            packet: packet.encode().into(),
+        }
+    }
+}
+
+/// Split the transfers into chunks and hand them over one by one, reporting the progress of each.
+///
+/// `chunks` should only hold a single packet, so that a chunk counts as sent once the connection took it.
+/// Stops sending once `transfers` is closed, dropping whatever is left.
+pub(crate) async fn chunk_loop(
+    transfers: Receiver<Transfer>,
+    chunks: Sender<NetworkPacket>,
+    chunk_size: usize,
+    report: impl Fn(ConnectionId, TransferProgress),
+) {
+    let chunk_size = chunk_size.max(1);
+
+    while let Ok(transfer) = transfers.recv().await {
+        let total = transfer.packet.len();
+        let mut offset = 0;
+
+        while offset < total {
+            if transfers.is_closed() {
+                return;
+            }
+
+            let end = total.min(offset + chunk_size);
+            let mut data = Vec::with_capacity(HEADER_LEN + end - offset);
+            data.put_u32_le(transfer.id.0);
+            data.put_u64_le(total as u64);
+            data.put_u64_le(offset as u64);
+            data.put_slice(&transfer.packet[offset..end]);
+
+            let chunk = NetworkPacket {
+                kind: PacketKind::Chunk,
+                data: data.into(),
+                channel: Channel::DEFAULT,
+                compressed: false,
+            };
+            if chunks.send(chunk).await.is_err() {
+                return;
+            }
+
+            offset = end;
+            report(
+                transfer.to,
+                TransferProgress {
+                    id: transfer.id,
+                    direction: TransferDirection::Sending,
+                    transferred: offset,
+                    total,
+                },
+            );
+        }
+    }
+}
+
+/// Puts the chunks of incoming transfers back together
+#[derive(Debug)]
+pub(crate) struct Reassembly {
+    max_length: usize,
+    partial: HashMap<u32, Vec<u8>>,
+    /// How many bytes all partial transfers hold together
+    buffered: usize,
+}
+
+impl Reassembly {
+    pub(crate) fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+            partial: HashMap::new(),
+            buffered: 0,
+        }
+    }
+
+    /// Add a chunk to its transfer
+    ///
+    /// Returns the transfer's progress, and the packet it carried once it is complete.
+    pub(crate) fn receive(
+        &mut self,
+        mut chunk: Bytes,
+    ) -> Result<(TransferProgress, Option<NetworkPacket>), DisconnectReason> {
+        if chunk.remaining() < HEADER_LEN {
+            error!("Received a transfer chunk whose header is cut off");
+            return Err(DisconnectReason::DecodeError);
+        }
+        let id = chunk.get_u32_le();
+        let total = chunk.get_u64_le() as usize;
+        let offset = chunk.get_u64_le() as usize;
+
+        if total > self.max_length || self.buffered + chunk.len() > self.max_length {
+            error!(
+                "Received too large transfer: {} > {}",
+                total.max(self.buffered + chunk.len()),
+                self.max_length
+            );
+            return Err(DisconnectReason::Oversize);
+        }
+
+        let partial = self.partial.entry(id).or_default();
+        // Chunks are sent in order, on a reliable channel
+        if offset != partial.len() || offset + chunk.len() > total {
+            error!("Received a transfer chunk out of place");
+            return Err(DisconnectReason::DecodeError);
+        }
+        partial.extend_from_slice(&chunk);
+        self.buffered += chunk.len();
+
+        let progress = TransferProgress {
+            id: TransferId(id),
+            direction: TransferDirection::Receiving,
+            transferred: partial.len(),
+            total,
+        };
+        if !progress.is_complete() {
+            return Ok((progress, None));
+        }
+
+        let packet = self.partial.remove(&id).unwrap_or_default();
+        self.buffered -= packet.len();
+        match NetworkPacket::decode(packet) {
+            Ok(packet) => Ok((progress, Some(packet))),
+            Err(err) => {
+                error!("Failed to decode transferred packet: {}", err);
+                Err(DisconnectReason::DecodeError)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_channel::unbounded;
+    use futures_lite::future;
+
+    use super::*;
+
+    fn packet(length: usize) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Id(3),
+            data: (0..length)
+                .map(|byte| byte as u8)
+                .collect::<Vec<_>>()
+                .into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Send `packet` as a transfer in chunks of `chunk_size`, and put it back together
+    fn transfer(
+        packet: NetworkPacket,
+        chunk_size: usize,
+    ) -> (Vec<TransferProgress>, NetworkPacket) {
+        let (transfers_tx, transfers_rx) = unbounded();
+        let (chunks_tx, chunks_rx) = unbounded();
+        let sent = Mutex::new(Vec::new());
+        transfers_tx
+            .try_send(Transfer::new(1, ConnectionId::server(), packet))
+            .expect("transfer should be queued");
+
+        let sending = async {
+            chunk_loop(transfers_rx, chunks_tx, chunk_size, |_, progress| {
+                sent.lock().expect("not poisoned").push(progress)
+            })
+            .await;
+            unreachable!("the transfer is never closed");
+        };
+        let receiving = async {
+            let mut reassembly = Reassembly::new(usize::MAX);
+            loop {
+                let chunk = chunks_rx.recv().await.expect("chunks should arrive");
+                let (progress, packet) = reassembly.receive(chunk.data).expect("chunk should fit");
+                assert_eq!(progress.direction, TransferDirection::Receiving);
+                if let Some(packet) = packet {
+                    assert!(progress.is_complete());
+                    return packet;
+                }
+            }
+        };
+
+        let received = future::block_on(future::or(sending, receiving));
+        (sent.into_inner().expect("not poisoned"), received)
+    }
+
+    #[test]
+    fn transfers_arrive_whole() {
+        let original = packet(10_000);
+        let (progress, received) = transfer(packet(10_000), 1000);
+
+        assert_eq!(received.kind, original.kind);
+        assert_eq!(received.data, original.data);
+
+        let total = original.encode().len();
+        assert_eq!(progress.len(), total.div_ceil(1000));
+        assert!(progress.iter().all(|progress| progress.total == total));
+        assert!(progress.last().is_some_and(TransferProgress::is_complete));
+    }
+
+    #[test]
+    fn transfers_smaller_than_a_chunk_arrive_whole() {
+        let (progress, received) = transfer(packet(10), 1000);
+
+        assert_eq!(received.data, packet(10).data);
+        assert_eq!(progress.len(), 1);
+    }
+
+    fn chunk(id: u32, total: u64, offset: u64, data: &[u8]) -> Bytes {
+        let mut chunk = Vec::new();
+        chunk.put_u32_le(id);
+        chunk.put_u64_le(total);
+        chunk.put_u64_le(offset);
+        chunk.put_slice(data);
+        chunk.into()
+    }
+
+    #[test]
+    fn drops_transfers_that_are_too_large() {
+        let mut reassembly = Reassembly::new(100);
+        assert!(matches!(
+            reassembly.receive(chunk(1, 101, 0, &[0; 10])),
+            Err(DisconnectReason::Oversize)
+        ));
+
+        // Every transfer fits on its own, but not both at once
+        let mut reassembly = Reassembly::new(100);
+        assert!(reassembly.receive(chunk(1, 100, 0, &[0; 60])).is_ok());
+        assert!(matches!(
+            reassembly.receive(chunk(2, 100, 0, &[0; 60])),
+            Err(DisconnectReason::Oversize)
+        ));
+    }
+
+    #[test]
+    fn drops_chunks_out_of_place() {
+        let mut reassembly = Reassembly::new(100);
+        assert!(matches!(
+            reassembly.receive(chunk(1, 50, 10, &[0; 10])),
+            Err(DisconnectReason::DecodeError)
+        ));
+
+        let mut reassembly = Reassembly::new(100);
+        assert!(matches!(
+            reassembly.receive(chunk(1, 5, 0, &[0; 10])),
+            Err(DisconnectReason::DecodeError)
+        ));
+
+        let mut reassembly = Reassembly::new(100);
+        assert!(matches!(
+            reassembly.receive(Bytes::from_static(&[0; HEADER_LEN - 1])),
+            Err(DisconnectReason::DecodeError)
+        ));
+    }
+}
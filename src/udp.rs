@@ -0,0 +1,1145 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    async_channel::{bounded, Receiver, Sender, TrySendError},
+    async_trait,
+    channel::Sequences,
+    client::NetworkClientProvider,
+    error::NetworkError,
+    server::NetworkServerProvider,
+    ClientNetworkEvent, Delivery, DisconnectReason, NetworkPacket,
+};
+use async_io::{Async, Timer};
+use bevy::log::{debug, error, info, trace, warn};
+use futures_lite::future;
+
+/// Asks the server to open a virtual connection for the sending address.
+const CONNECT: u8 = 0;
+/// Confirms a [`CONNECT`], sent every time one is received.
+const CONNECT_ACK: u8 = 1;
+/// A reliable fragment of an encoded [`NetworkPacket`].
+const DATA: u8 = 2;
+/// Acknowledges a single [`DATA`] datagram by its sequence number.
+const ACK: u8 = 3;
+/// Tells the peer that this side has gone away.
+const DISCONNECT: u8 = 4;
+/// An encoded [`NetworkPacket`] that is sent once, without being acknowledged.
+const UNRELIABLE: u8 = 5;
+/// Like [`UNRELIABLE`], but numbered per channel so stale packets can be dropped.
+const SEQUENCED: u8 = 6;
+
+/// Set on the final fragment of an encoded [`NetworkPacket`].
+const LAST_FRAGMENT: u8 = 1;
+/// Set on [`DATA`] that is handed over as soon as it arrives, instead of in order.
+const UNORDERED: u8 = 2;
+
+/// Kind, sequence number and flags in front of every [`DATA`] payload.
+const DATA_HEADER_LEN: usize = 6;
+/// Kind, channel id and sequence number in front of every [`SEQUENCED`] payload.
+const SEQUENCED_HEADER_LEN: usize = 9;
+
+/// Large enough to hold any datagram the OS will hand us.
+const MAX_DATAGRAM_LEN: usize = 65_536;
+
+#[derive(Default, Debug)]
+/// Provides a udp socket and a reliability layer for eventwork.
+///
+/// A single socket is shared by all clients, datagrams are handed
+/// to the matching [`UdpConnection`] by their source address.
+///
+/// Every [`Delivery`] is honoured, but packets larger than
+/// [`NetworkSettings::mtu`] are always sent reliably and in order.
+pub struct UdpServerProvider;
+
+#[async_trait]
+impl NetworkServerProvider for UdpServerProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = UdpConnection;
+
+    type ReadHalf = UdpReadHalf;
+
+    type WriteHalf = UdpWriteHalf;
+
+    async fn accept_loop(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<NetworkError>,
+    ) {
+        let socket = match Async::<UdpSocket>::bind(network_settings.addr) {
+            Ok(socket) => Arc::new(socket),
+            Err(err) => {
+                if let Err(err) = errors.send(NetworkError::Listen(err)).await {
+                    error!("Could not send listen error: {}", err);
+                }
+                return;
+            }
+        };
+
+        let mut peers: HashMap<SocketAddr, Sender<Vec<u8>>> = HashMap::new();
+        let mut buffer = vec![0; MAX_DATAGRAM_LEN];
+        loop {
+            let (length, addr) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(error) => {
+                    if let Err(err) = errors.send(NetworkError::Accept(error)).await {
+                        error!("Could not send listen error: {}", err);
+                        return;
+                    };
+                    continue;
+                }
+            };
+            let datagram = &buffer[..length];
+
+            match datagram.first() {
+                Some(&CONNECT) => {
+                    // The client keeps asking until it sees our acknowledgement
+                    let known = peers.get(&addr).is_some_and(|peer| !peer.is_closed());
+                    if !known {
+                        peers.retain(|_, peer| !peer.is_closed());
+                        if peers.len() >= network_settings.max_connections {
+                            // Without an acknowledgement the client gives up on its own
+                            debug!("Refusing connection from {}, the server is full", addr);
+                            continue;
+                        }
+                    }
+
+                    if let Err(err) = socket.send_to(&[CONNECT_ACK], addr).await {
+                        warn!("Could not acknowledge connection from {}: {}", addr, err);
+                    }
+                    if known {
+                        continue;
+                    }
+
+                    // Holds a window worth of datagrams, reliable ones that don't fit are sent again
+                    let (incoming_tx, incoming_rx) = bounded(network_settings.window_size.max(1));
+                    peers.insert(addr, incoming_tx);
+
+                    let connection = UdpConnection {
+                        socket: socket.clone(),
+                        peer: addr,
+                        incoming: Incoming::Demultiplexed(incoming_rx),
+                    };
+
+                    if let Err(err) = new_connections.send(connection).await {
+                        error!("Could not send listen error: {}", err);
+                        return;
+                    }
+                    info!("New Connection Made!");
+                }
+                Some(&kind) => {
+                    let closed = match peers.get(&addr) {
+                        Some(peer) => match peer.try_send(datagram.to_vec()) {
+                            // Lost like any other datagram
+                            Ok(()) | Err(TrySendError::Full(_)) => false,
+                            Err(TrySendError::Closed(_)) => true,
+                        },
+                        None => {
+                            trace!("Dropping datagram from unknown peer {}", addr);
+                            continue;
+                        }
+                    };
+
+                    if closed || kind == DISCONNECT {
+                        peers.remove(&addr);
+                    }
+                }
+                None => (),
+            }
+        }
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        combined.split()
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        Some(socket.peer)
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.socket.get_ref().local_addr().ok()
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides a udp socket and a reliability layer for eventwork.
+pub struct UdpClientProvider;
+
+#[async_trait]
+impl NetworkClientProvider for UdpClientProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = UdpConnection;
+
+    type ReadHalf = UdpReadHalf;
+
+    type WriteHalf = UdpWriteHalf;
+
+    async fn connect_task(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<ClientNetworkEvent>,
+    ) {
+        info!("Beginning connection");
+        let connection = match connect(&network_settings).await {
+            Ok(connection) => connection,
+            Err(error) => {
+                match errors
+                    .send(ClientNetworkEvent::Error(NetworkError::Connection(error)))
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(err) => {
+                        error!("Could not send error event: {}", err);
+                    }
+                }
+
+                return;
+            }
+        };
+
+        info!("Connected!");
+
+        match new_connections.send(connection).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not initiate connection: {}", err);
+            }
+        }
+
+        debug!("Connected to: {:?}", network_settings.addr);
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        combined.split()
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        Some(socket.peer)
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.socket.get_ref().local_addr().ok()
+    }
+}
+
+/// Keeps sending [`CONNECT`] until the server acknowledges it or we run out of time.
+async fn connect(settings: &NetworkSettings) -> io::Result<UdpConnection> {
+    let local_addr: SocketAddr = if settings.addr.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = Arc::new(Async::<UdpSocket>::bind(local_addr)?);
+
+    let started = Instant::now();
+    let mut buffer = vec![0; MAX_DATAGRAM_LEN];
+    while started.elapsed() < settings.connect_timeout {
+        socket.send_to(&[CONNECT], settings.addr).await?;
+
+        let acknowledged = future::or(
+            async {
+                loop {
+                    match socket.recv_from(&mut buffer).await {
+                        Ok((length, addr)) if addr == settings.addr => {
+                            if buffer[..length].first() == Some(&CONNECT_ACK) {
+                                return Ok(true);
+                            }
+                        }
+                        Ok(_) => (),
+                        Err(err) => return Err(err),
+                    }
+                }
+            },
+            async {
+                Timer::after(settings.resend_interval).await;
+                Ok(false)
+            },
+        )
+        .await?;
+
+        if acknowledged {
+            return Ok(UdpConnection {
+                socket,
+                peer: settings.addr,
+                incoming: Incoming::Socket,
+            });
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "The server did not answer the connection request",
+    ))
+}
+
+async fn recv_loop(
+    read_half: UdpReadHalf,
+    messages: Sender<NetworkPacket>,
+    settings: NetworkSettings,
+) -> DisconnectReason {
+    let mut buffer = vec![0; MAX_DATAGRAM_LEN];
+    let mut frame = Vec::new();
+    let mut sequences = Sequences::default();
+    loop {
+        let datagram = match read_half.next_datagram(&mut buffer).await {
+            Some(datagram) => datagram,
+            // The send loop gives up on peers that stop acknowledging
+            None if read_half.shared.lock().timed_out => return DisconnectReason::TimedOut,
+            None => return DisconnectReason::Eof,
+        };
+
+        match datagram.first() {
+            Some(&DATA) if datagram.len() >= DATA_HEADER_LEN => {
+                let seq = read_seq(&datagram);
+                let flags = datagram[5];
+
+                let (acknowledge, ready) = read_half.shared.lock().receive(
+                    seq,
+                    flags,
+                    &datagram[DATA_HEADER_LEN..],
+                    settings.window_size,
+                );
+
+                if acknowledge {
+                    let mut ack = [ACK, 0, 0, 0, 0];
+                    ack[1..].copy_from_slice(&seq.to_le_bytes());
+                    if let Err(err) = read_half.socket.send_to(&ack, read_half.peer).await {
+                        warn!("Could not acknowledge datagram {}: {}", seq, err);
+                    }
+                }
+
+                for (flags, payload) in ready {
+                    // Unordered packets always fit into a single datagram, they're never part of a frame
+                    if flags & UNORDERED != 0 {
+                        if let Err(reason) = forward(payload, &messages).await {
+                            return reason;
+                        }
+                        continue;
+                    }
+
+                    frame.extend_from_slice(&payload);
+
+                    if frame.len() > settings.max_packet_length {
+                        error!(
+                            "Received too large packet: {} > {}",
+                            frame.len(),
+                            settings.max_packet_length
+                        );
+                        return DisconnectReason::Oversize;
+                    }
+
+                    if flags & LAST_FRAGMENT == 0 {
+                        continue;
+                    }
+
+                    if let Err(reason) = forward(std::mem::take(&mut frame), &messages).await {
+                        return reason;
+                    }
+                }
+            }
+            Some(&UNRELIABLE) => {
+                if let Err(reason) = forward(datagram[1..].to_vec(), &messages).await {
+                    return reason;
+                }
+            }
+            Some(&SEQUENCED) if datagram.len() >= SEQUENCED_HEADER_LEN => {
+                let channel = read_seq(&datagram);
+                let seq = read_seq(&datagram[4..]);
+
+                if !sequences.accept(channel, seq) {
+                    trace!("Dropping stale datagram {} on channel {}", seq, channel);
+                    continue;
+                }
+
+                let payload = datagram[SEQUENCED_HEADER_LEN..].to_vec();
+                if let Err(reason) = forward(payload, &messages).await {
+                    return reason;
+                }
+            }
+            Some(&ACK) if datagram.len() >= 5 => {
+                read_half.shared.lock().unacked.remove(&read_seq(&datagram));
+                // Wakes up the send loop if it waits for room in the window
+                let _ = read_half.acked.try_send(());
+            }
+            Some(&DISCONNECT) => {
+                info!("Peer disconnected");
+                return DisconnectReason::Eof;
+            }
+            // Late copies of the connection handshake
+            Some(&CONNECT) | Some(&CONNECT_ACK) => (),
+            _ => {
+                warn!("Dropping malformed datagram from {}", read_half.peer);
+            }
+        }
+    }
+}
+
+async fn send_loop(
+    write_half: UdpWriteHalf,
+    messages: Receiver<NetworkPacket>,
+    settings: NetworkSettings,
+) {
+    let mut sequences = Sequences::default();
+    loop {
+        // Nothing new is taken while reliable datagrams wait for room in the window
+        let window_full = write_half.shared.lock().is_backed_up();
+
+        let next = if window_full {
+            future::or(
+                async {
+                    // Without the receiving half there are no acknowledgements to wait for
+                    if write_half.acked.recv().await.is_err() {
+                        future::pending::<()>().await;
+                    }
+                },
+                async {
+                    Timer::after(settings.resend_interval).await;
+                },
+            )
+            .await;
+            None
+        } else {
+            future::or(async { Some(messages.recv().await) }, async {
+                Timer::after(settings.resend_interval).await;
+                None
+            })
+            .await
+        };
+
+        match next {
+            Some(Ok(message)) => {
+                let encoded = message.encode();
+                debug!("Sending a new message of size: {}", encoded.len());
+
+                let mtu = settings.mtu.max(1);
+                let delivery = match message.channel().delivery() {
+                    // Only reliable, ordered packets can be put back together from fragments
+                    _ if encoded.len() > mtu => Delivery::ReliableOrdered,
+                    delivery => delivery,
+                };
+
+                let datagrams = match delivery {
+                    Delivery::ReliableOrdered => {
+                        write_half.shared.lock().queue_fragments(&encoded, mtu);
+                        Vec::new()
+                    }
+                    Delivery::ReliableUnordered => {
+                        write_half
+                            .shared
+                            .lock()
+                            .queue(LAST_FRAGMENT | UNORDERED, &encoded);
+                        Vec::new()
+                    }
+                    Delivery::Unreliable => {
+                        let mut datagram = Vec::with_capacity(1 + encoded.len());
+                        datagram.push(UNRELIABLE);
+                        datagram.extend_from_slice(&encoded);
+                        vec![datagram]
+                    }
+                    Delivery::UnreliableSequenced => {
+                        let channel = message.channel().id();
+                        let seq = sequences.next(channel);
+
+                        let mut datagram = Vec::with_capacity(SEQUENCED_HEADER_LEN + encoded.len());
+                        datagram.push(SEQUENCED);
+                        datagram.extend_from_slice(&channel.to_le_bytes());
+                        datagram.extend_from_slice(&seq.to_le_bytes());
+                        datagram.extend_from_slice(&encoded);
+                        vec![datagram]
+                    }
+                };
+
+                for datagram in datagrams {
+                    if let Err(err) = write_half.socket.send_to(&datagram, write_half.peer).await {
+                        error!("Could not send packet: {:?}: {}", message, err);
+                    }
+                }
+            }
+            // The connection was dropped on our side
+            Some(Err(_)) => break,
+            None => (),
+        }
+
+        let released = write_half
+            .shared
+            .lock()
+            .release(settings.window_size.max(1));
+        for datagram in released {
+            if let Err(err) = write_half.socket.send_to(&datagram, write_half.peer).await {
+                error!("Could not send datagram {}: {}", read_seq(&datagram), err);
+            }
+        }
+
+        let resends = match write_half
+            .shared
+            .lock()
+            .expired(settings.resend_interval, settings.max_resends)
+        {
+            Some(resends) => resends,
+            None => {
+                error!(
+                    "Peer {} did not acknowledge a datagram after {} resends",
+                    write_half.peer, settings.max_resends
+                );
+                break;
+            }
+        };
+
+        for datagram in resends {
+            trace!("Resending datagram {}", read_seq(&datagram));
+            if let Err(err) = write_half.socket.send_to(&datagram, write_half.peer).await {
+                error!("Could not resend datagram: {}", err);
+            }
+        }
+    }
+}
+
+/// Decodes a complete packet and hands it over to eventwork.
+async fn forward(
+    encoded: Vec<u8>,
+    messages: &Sender<NetworkPacket>,
+) -> Result<(), DisconnectReason> {
+    let packet = match NetworkPacket::decode(encoded) {
+        Ok(packet) => packet,
+        Err(err) => {
+            error!("Failed to decode network packet from: {}", err);
+            return Err(DisconnectReason::DecodeError);
+        }
+    };
+
+    if messages.send(packet).await.is_err() {
+        error!("Failed to send decoded message to eventwork");
+        return Err(DisconnectReason::Eof);
+    }
+    trace!("Message deserialized and sent to eventwork");
+    Ok(())
+}
+
+fn read_seq(datagram: &[u8]) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&datagram[1..5]);
+    u32::from_le_bytes(bytes)
+}
+
+#[derive(Debug)]
+enum Incoming {
+    /// Datagrams are handed over by the server's accept loop.
+    Demultiplexed(Receiver<Vec<u8>>),
+    /// The socket only talks to a single peer and is read directly.
+    Socket,
+}
+
+#[derive(Debug)]
+/// A virtual connection with a single peer on top of a udp socket.
+pub struct UdpConnection {
+    socket: Arc<Async<UdpSocket>>,
+    peer: SocketAddr,
+    incoming: Incoming,
+}
+
+impl UdpConnection {
+    /// The address of the remote end of this connection
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn split(self) -> (UdpReadHalf, UdpWriteHalf) {
+        let shared = Arc::new(Shared::default());
+        let (close, closed) = bounded(1);
+        let (acked_tx, acked_rx) = bounded(1);
+
+        (
+            UdpReadHalf {
+                socket: self.socket.clone(),
+                peer: self.peer,
+                incoming: self.incoming,
+                shared: shared.clone(),
+                closed,
+                acked: acked_tx,
+            },
+            UdpWriteHalf {
+                socket: self.socket,
+                peer: self.peer,
+                shared,
+                acked: acked_rx,
+                _close: close,
+            },
+        )
+    }
+}
+
+#[derive(Debug)]
+/// The receiving half of a [`UdpConnection`].
+pub struct UdpReadHalf {
+    socket: Arc<Async<UdpSocket>>,
+    peer: SocketAddr,
+    incoming: Incoming,
+    shared: Arc<Shared>,
+    /// Closed once the [`UdpWriteHalf`] is gone.
+    closed: Receiver<()>,
+    /// Tells the [`UdpWriteHalf`] that an acknowledgement arrived.
+    acked: Sender<()>,
+}
+
+impl UdpReadHalf {
+    /// Returns `None` once the connection can no longer be read from.
+    async fn next_datagram(&self, buffer: &mut [u8]) -> Option<Vec<u8>> {
+        let incoming = async {
+            match &self.incoming {
+                Incoming::Demultiplexed(datagrams) => datagrams.recv().await.ok(),
+                Incoming::Socket => loop {
+                    match self.socket.recv_from(buffer).await {
+                        Ok((length, addr)) if addr == self.peer => {
+                            return Some(buffer[..length].to_vec())
+                        }
+                        Ok(_) => (),
+                        Err(err) => {
+                            error!("Encountered error while receiving datagram: {}", err);
+                            return None;
+                        }
+                    }
+                },
+            }
+        };
+        let closed = async {
+            let _ = self.closed.recv().await;
+            None
+        };
+
+        future::or(incoming, closed).await
+    }
+}
+
+#[derive(Debug)]
+/// The sending half of a [`UdpConnection`].
+pub struct UdpWriteHalf {
+    socket: Arc<Async<UdpSocket>>,
+    peer: SocketAddr,
+    shared: Arc<Shared>,
+    /// Woken up by the [`UdpReadHalf`] for every acknowledgement.
+    acked: Receiver<()>,
+    /// Dropping this wakes up the [`UdpReadHalf`].
+    _close: Sender<()>,
+}
+
+impl Drop for UdpWriteHalf {
+    fn drop(&mut self) {
+        // Best effort, the peer will otherwise notice once its resends go unanswered.
+        let _ = self.socket.get_ref().send_to(&[DISCONNECT], self.peer);
+    }
+}
+
+#[derive(Default, Debug)]
+struct Shared {
+    reliability: Mutex<Reliability>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Reliability> {
+        self.reliability
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Debug)]
+struct Unacked {
+    datagram: Vec<u8>,
+    last_sent: Instant,
+    resends: u32,
+}
+
+/// Sequencing state of a single connection, shared between both halves.
+#[derive(Default, Debug)]
+struct Reliability {
+    next_send_seq: u32,
+    unacked: HashMap<u32, Unacked>,
+    /// Flags and payloads of [`DATA`] waiting for room in the window, see [`Reliability::release`]
+    queued: VecDeque<(u8, Vec<u8>)>,
+    /// Set once the peer stopped acknowledging, see [`NetworkSettings::max_resends`]
+    timed_out: bool,
+    next_recv_seq: u32,
+    /// Fragments waiting for the ones before them, `None` for those handed over already
+    out_of_order: HashMap<u32, Option<(u8, Vec<u8>)>>,
+}
+
+impl Reliability {
+    /// Queues a [`DATA`] payload, to be sent once it fits into the window.
+    fn queue(&mut self, flags: u8, payload: &[u8]) {
+        self.queued.push_back((flags, payload.to_vec()));
+    }
+
+    /// Splits an encoded packet into [`DATA`] payloads of at most `mtu` bytes and queues them.
+    fn queue_fragments(&mut self, encoded: &[u8], mtu: usize) {
+        let fragments = encoded.chunks(mtu).count();
+        for (index, fragment) in encoded.chunks(mtu).enumerate() {
+            let flags = if index + 1 == fragments {
+                LAST_FRAGMENT
+            } else {
+                0
+            };
+            self.queue(flags, fragment);
+        }
+    }
+
+    /// Whether queued datagrams are still waiting for room in the window.
+    fn is_backed_up(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    /// Builds the datagrams for as many queued payloads as fit into the window.
+    ///
+    /// The window spans from the oldest unacknowledged datagram, which is as far
+    /// as the peer buffers datagrams ahead of the one it waits for.
+    fn release(&mut self, window_size: usize) -> Vec<Vec<u8>> {
+        let mut in_flight = self
+            .unacked
+            .keys()
+            .map(|seq| self.next_send_seq.wrapping_sub(*seq) as usize)
+            .max()
+            .unwrap_or(0);
+
+        let mut released = Vec::new();
+        while in_flight < window_size {
+            let (flags, payload) = match self.queued.pop_front() {
+                Some(queued) => queued,
+                None => break,
+            };
+            released.push(self.track(flags, &payload));
+            in_flight += 1;
+        }
+        released
+    }
+
+    /// Builds the next [`DATA`] datagram and remembers it until it is acknowledged.
+    fn track(&mut self, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let seq = self.next_send_seq;
+        self.next_send_seq = seq.wrapping_add(1);
+
+        let mut datagram = Vec::with_capacity(DATA_HEADER_LEN + payload.len());
+        datagram.push(DATA);
+        datagram.extend_from_slice(&seq.to_le_bytes());
+        datagram.push(flags);
+        datagram.extend_from_slice(payload);
+
+        self.unacked.insert(
+            seq,
+            Unacked {
+                datagram: datagram.clone(),
+                last_sent: Instant::now(),
+                resends: 0,
+            },
+        );
+
+        datagram
+    }
+
+    /// Collects every datagram that has waited too long for its acknowledgement.
+    ///
+    /// Returns `None` if any of them ran out of resends, the connection has timed out then.
+    fn expired(&mut self, resend_interval: Duration, max_resends: u32) -> Option<Vec<Vec<u8>>> {
+        let now = Instant::now();
+        let mut resends = Vec::new();
+        for unacked in self.unacked.values_mut() {
+            if now.duration_since(unacked.last_sent) < resend_interval {
+                continue;
+            }
+            if unacked.resends >= max_resends {
+                self.timed_out = true;
+                return None;
+            }
+            unacked.resends += 1;
+            unacked.last_sent = now;
+            resends.push(unacked.datagram.clone());
+        }
+        Some(resends)
+    }
+
+    /// Stores a received fragment and returns every fragment that is now in order.
+    ///
+    /// [`UNORDERED`] fragments are returned right away instead.
+    ///
+    /// The returned flag tells whether the datagram should be acknowledged.
+    fn receive(
+        &mut self,
+        seq: u32,
+        flags: u8,
+        payload: &[u8],
+        window_size: usize,
+    ) -> (bool, Vec<(u8, Vec<u8>)>) {
+        let ahead = seq.wrapping_sub(self.next_recv_seq);
+
+        // Already delivered, our acknowledgement must have been lost
+        if (ahead as i32) < 0 {
+            return (true, Vec::new());
+        }
+        // Too far ahead to buffer, the peer will send it again
+        if ahead as usize >= window_size {
+            return (false, Vec::new());
+        }
+
+        // Already received, it's waiting for the ones before it
+        if self.out_of_order.contains_key(&seq) {
+            return (true, Vec::new());
+        }
+
+        let mut ready = Vec::new();
+        if flags & UNORDERED != 0 {
+            ready.push((flags, payload.to_vec()));
+            self.out_of_order.insert(seq, None);
+        } else {
+            self.out_of_order
+                .insert(seq, Some((flags, payload.to_vec())));
+        }
+
+        while let Some(fragment) = self.out_of_order.remove(&self.next_recv_seq) {
+            ready.extend(fragment);
+            self.next_recv_seq = self.next_recv_seq.wrapping_add(1);
+        }
+        (true, ready)
+    }
+}
+
+#[derive(Clone, Debug)]
+#[allow(missing_copy_implementations)]
+/// Settings to configure the network, both client and server
+pub struct NetworkSettings {
+    /// Maximum packet size in bytes. If a client ever exceeds this size, they will be disconnected
+    ///
+    /// Larger messages can be sent in chunks instead, see [`Transfers`](crate::Transfers).
+    /// Every chunk is a packet of its own, so [`Transfers::chunk_size`](crate::Transfers::chunk_size)
+    /// has to stay a few bytes below this, to leave room for the headers. How large a whole
+    /// transfer may get is up to [`Transfers::max_length`](crate::Transfers::max_length) instead.
+    ///
+    /// ## Default
+    /// The default is set to 128KiB, twice the default chunk size
+    pub max_packet_length: usize,
+
+    /// Address to connect to or port to open
+    pub addr: SocketAddr,
+
+    /// Maximum payload of a single datagram, larger packets are split into fragments
+    ///
+    /// Fragmented packets are always delivered reliably and in order, whatever their channel asks for.
+    ///
+    /// ## Default
+    /// The default is set to 1200 bytes, which fits through most networks without IP fragmentation
+    pub mtu: usize,
+
+    /// How long to wait for an acknowledgement before sending a datagram again
+    ///
+    /// ## Default
+    /// The default is set to 100ms
+    pub resend_interval: Duration,
+
+    /// How often a datagram is sent again before the connection is dropped
+    /// with [`DisconnectReason::TimedOut`]
+    ///
+    /// ## Default
+    /// The default is set to 50
+    pub max_resends: u32,
+
+    /// Maximum number of reliable datagrams in flight, counted from the oldest one that
+    /// wasn't acknowledged yet. Further ones wait until there is room again.
+    ///
+    /// Both sides should use the same window, the receiving side drops datagrams too far ahead.
+    ///
+    /// ## Default
+    /// The default is set to 1024
+    pub window_size: usize,
+
+    /// How long a client keeps asking the server to accept its connection
+    ///
+    /// ## Default
+    /// The default is set to 5 seconds
+    pub connect_timeout: Duration,
+
+    /// How many clients the server keeps connections for at once, further ones are refused
+    ///
+    /// A single datagram, even from a forged address, is enough to ask for a connection,
+    /// so this caps what such requests can take up on the server.
+    ///
+    /// ## Default
+    /// The default is set to 1024
+    pub max_connections: usize,
+}
+
+impl NetworkSettings {
+    /// Create a new instance of [`NetworkSettings`]
+    pub fn new(addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            max_packet_length: 128 * 1024,
+            addr: addr.into(),
+            mtu: 1200,
+            resend_interval: Duration::from_millis(100),
+            max_resends: 50,
+            window_size: 1024,
+            connect_timeout: Duration::from_secs(5),
+            max_connections: 1024,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_channel::unbounded, network_packet::PacketKind, Channel};
+
+    fn settings(addr: SocketAddr) -> NetworkSettings {
+        NetworkSettings {
+            resend_interval: Duration::from_millis(10),
+            max_resends: 3,
+            connect_timeout: Duration::from_secs(1),
+            ..NetworkSettings::new(addr)
+        }
+    }
+
+    fn packet(data: Vec<u8>) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Name("test".to_owned()),
+            data: data.into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Two ends of a connection over the loopback interface
+    fn pair() -> (UdpConnection, UdpConnection) {
+        let bind = || {
+            Arc::new(Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).expect("loopback is available"))
+        };
+        let (a, b) = (bind(), bind());
+        let addr = |socket: &Arc<Async<UdpSocket>>| {
+            socket.get_ref().local_addr().expect("socket is bound")
+        };
+
+        (
+            UdpConnection {
+                peer: addr(&b),
+                socket: a.clone(),
+                incoming: Incoming::Socket,
+            },
+            UdpConnection {
+                peer: addr(&a),
+                socket: b,
+                incoming: Incoming::Socket,
+            },
+        )
+    }
+
+    #[test]
+    fn large_packets_arrive_in_fragments_through_a_small_window() {
+        let (sender, receiver) = pair();
+        let settings = NetworkSettings {
+            mtu: 100,
+            window_size: 8,
+            ..settings(sender.peer)
+        };
+        let data: Vec<u8> = (0..50_000).map(|byte| byte as u8).collect();
+
+        let (messages_tx, messages_rx) = unbounded();
+        messages_tx
+            .try_send(packet(data.clone()))
+            .expect("channel is open");
+        let (received_tx, received_rx) = unbounded();
+
+        let (sender_read, sender_write) = sender.split();
+        let (receiver_read, _receiver_write) = receiver.split();
+        let connection = async {
+            future::zip(
+                future::zip(
+                    // Only there to take the acknowledgements
+                    recv_loop(sender_read, unbounded().0, settings.clone()),
+                    send_loop(sender_write, messages_rx, settings.clone()),
+                ),
+                recv_loop(receiver_read, received_tx, settings.clone()),
+            )
+            .await;
+            None
+        };
+        let received = future::block_on(future::or(connection, async {
+            received_rx.recv().await.ok()
+        }));
+
+        assert_eq!(received.map(|packet| packet.data), Some(data.into()));
+    }
+
+    #[test]
+    fn windows_start_at_the_oldest_unacknowledged_datagram() {
+        let mut reliability = Reliability::default();
+        for _ in 0..10 {
+            reliability.queue(LAST_FRAGMENT, &[0; 10]);
+        }
+
+        assert_eq!(reliability.release(4).len(), 4);
+        assert!(reliability.is_backed_up());
+
+        // The peer still waits for the first one, so nothing fits in yet
+        reliability.unacked.remove(&3);
+        assert!(reliability.release(4).is_empty());
+
+        reliability.unacked.remove(&0);
+        assert_eq!(reliability.release(4).len(), 1);
+
+        reliability.unacked.clear();
+        assert_eq!(reliability.release(4).len(), 4);
+        assert!(reliability.release(4).is_empty());
+
+        reliability.unacked.clear();
+        assert_eq!(reliability.release(4).len(), 1);
+        assert!(!reliability.is_backed_up());
+    }
+
+    #[test]
+    fn fragments_are_handed_over_in_order() {
+        let mut reliability = Reliability::default();
+
+        assert_eq!(
+            reliability.receive(2, LAST_FRAGMENT, b"c", 8),
+            (true, vec![])
+        );
+        assert_eq!(
+            reliability.receive(0, 0, b"a", 8),
+            (true, vec![(0, b"a".to_vec())])
+        );
+        // Copies are acknowledged again, but only handed over once
+        assert_eq!(reliability.receive(0, 0, b"a", 8), (true, vec![]));
+        assert_eq!(
+            reliability.receive(2, LAST_FRAGMENT, b"c", 8),
+            (true, vec![])
+        );
+        assert_eq!(
+            reliability.receive(1, 0, b"b", 8),
+            (
+                true,
+                vec![(0, b"b".to_vec()), (LAST_FRAGMENT, b"c".to_vec())]
+            )
+        );
+
+        // Unordered datagrams don't wait, but still keep their place
+        let unordered = LAST_FRAGMENT | UNORDERED;
+        assert_eq!(
+            reliability.receive(4, unordered, b"e", 8),
+            (true, vec![(unordered, b"e".to_vec())])
+        );
+        assert_eq!(
+            reliability.receive(3, LAST_FRAGMENT, b"d", 8),
+            (true, vec![(LAST_FRAGMENT, b"d".to_vec())])
+        );
+
+        // Too far ahead to be buffered, the peer has to send it again
+        assert_eq!(
+            reliability.receive(13, LAST_FRAGMENT, b"x", 8),
+            (false, vec![])
+        );
+    }
+
+    #[test]
+    fn gives_up_on_a_peer_that_stops_acknowledging() {
+        future::block_on(async {
+            // Lets the client in, then ignores everything it sends
+            let peer =
+                Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).expect("loopback is available");
+            let settings = settings(peer.get_ref().local_addr().expect("socket is bound"));
+            let accept = async {
+                let mut buffer = [0; 16];
+                let (_, addr) = peer.recv_from(&mut buffer).await.expect("client connects");
+                peer.send_to(&[CONNECT_ACK], addr)
+                    .await
+                    .expect("peer is reachable");
+            };
+            let (connection, ()) = future::zip(connect(&settings), accept).await;
+            let (read_half, write_half) = connection.expect("server lets us in").split();
+
+            let (messages_tx, messages_rx) = unbounded();
+            let (received_tx, _received_rx) = unbounded();
+            messages_tx
+                .try_send(packet(vec![1; 10]))
+                .expect("channel is open");
+
+            let (reason, ()) = future::zip(
+                recv_loop(read_half, received_tx, settings.clone()),
+                send_loop(write_half, messages_rx, settings.clone()),
+            )
+            .await;
+            assert_eq!(reason, DisconnectReason::TimedOut);
+        });
+    }
+
+    #[test]
+    fn refuses_connections_once_full() {
+        // A free port for the server to bind
+        let addr = UdpSocket::bind(("127.0.0.1", 0))
+            .and_then(|socket| socket.local_addr())
+            .expect("loopback is available");
+        let settings = NetworkSettings {
+            max_connections: 1,
+            connect_timeout: Duration::from_millis(300),
+            ..settings(addr)
+        };
+        let (connections_tx, connections_rx) = unbounded();
+        let (errors_tx, _errors_rx) = unbounded();
+
+        let clients = async {
+            let first = connect(&settings).await.expect("there is room");
+            let accepted: UdpConnection = connections_rx.recv().await.expect("server is listening");
+            let local_addr = first
+                .socket
+                .get_ref()
+                .local_addr()
+                .expect("socket is bound");
+            assert_eq!(accepted.peer_addr().port(), local_addr.port());
+
+            let second = connect(&settings).await;
+            assert_eq!(
+                second.map(|_| ()).map_err(|err| err.kind()),
+                Err(io::ErrorKind::TimedOut)
+            );
+            assert!(connections_rx.is_empty());
+        };
+        future::block_on(future::or(clients, async {
+            UdpServerProvider::accept_loop(settings.clone(), connections_tx, errors_tx).await;
+            panic!("server stopped");
+        }));
+    }
+}
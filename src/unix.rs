@@ -0,0 +1,334 @@
+use std::{
+    io,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    async_channel::{Receiver, Sender},
+    async_trait,
+    client::NetworkClientProvider,
+    error::NetworkError,
+    framing::{self, Batching},
+    server::NetworkServerProvider,
+    ClientNetworkEvent, DisconnectReason, NetworkPacket,
+};
+use async_net::unix::{UnixListener, UnixStream};
+use bevy::log::{debug, error, info};
+
+/// Removes the socket file once the listener is stopped, so it can be bound again.
+struct BoundPath(PathBuf);
+
+impl Drop for BoundPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Removes a socket file left behind by a server that didn't shut down cleanly.
+///
+/// Nobody answers on such a file, a path a live server still listens on is left alone,
+/// and so is anything that isn't a socket.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => (),
+        _ => return Ok(()),
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+            debug!("Removing stale socket file {:?}", path);
+            std::fs::remove_file(path)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides a unix domain socket listener for eventwork.
+///
+/// Packets are framed exactly like in the `tcp` provider.
+pub struct UnixServerProvider;
+
+#[async_trait]
+impl NetworkServerProvider for UnixServerProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = UnixStream;
+
+    type ReadHalf = UnixStream;
+
+    type WriteHalf = UnixStream;
+
+    async fn accept_loop(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<NetworkError>,
+    ) {
+        let listener = match remove_stale_socket(&network_settings.path)
+            .and_then(|()| UnixListener::bind(&network_settings.path))
+        {
+            Ok(listener) => listener,
+            Err(err) => {
+                if let Err(err) = errors.send(NetworkError::Listen(err)).await {
+                    error!("Could not send listen error: {}", err);
+                }
+                return;
+            }
+        };
+        let _bound_path = BoundPath(network_settings.path);
+
+        loop {
+            let resp = match listener.accept().await {
+                Ok((socket, _addr)) => socket,
+                Err(error) => {
+                    if let Err(err) = errors.send(NetworkError::Accept(error)).await {
+                        error!("Could not send listen error: {}", err);
+                        return;
+                    };
+                    continue;
+                }
+            };
+
+            if let Err(err) = new_connections.send(resp).await {
+                error!("Could not send listen error: {}", err);
+                return;
+            }
+            info!("New Connection Made!");
+        }
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        framing::recv_loop(read_half, messages, settings.max_packet_length).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        framing::send_loop(write_half, messages, settings.batching.as_ref()).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        (combined.clone(), combined)
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides a unix domain socket stream for eventwork.
+pub struct UnixClientProvider;
+
+#[async_trait]
+impl NetworkClientProvider for UnixClientProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = UnixStream;
+
+    type ReadHalf = UnixStream;
+
+    type WriteHalf = UnixStream;
+
+    async fn connect_task(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<ClientNetworkEvent>,
+    ) {
+        info!("Beginning connection");
+        let stream = match UnixStream::connect(&network_settings.path).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                match errors
+                    .send(ClientNetworkEvent::Error(NetworkError::Connection(error)))
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(err) => {
+                        error!("Could not send error event: {}", err);
+                    }
+                }
+
+                return;
+            }
+        };
+
+        info!("Connected!");
+
+        match new_connections.send(stream).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not initiate connection: {}", err);
+            }
+        }
+
+        debug!("Connected to: {:?}", network_settings.path);
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        framing::recv_loop(read_half, messages, settings.max_packet_length).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        framing::send_loop(write_half, messages, settings.batching.as_ref()).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        (combined.clone(), combined)
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Settings to configure the network, both client and server
+pub struct NetworkSettings {
+    /// Maximum packet size in bytes. If a client ever exceeds this size, they will be disconnected
+    ///
+    /// Larger messages can be sent in chunks instead, see [`Transfers`](crate::Transfers).
+    /// Every chunk is a packet of its own, so [`Transfers::chunk_size`](crate::Transfers::chunk_size)
+    /// has to stay a few bytes below this, to leave room for the headers. How large a whole
+    /// transfer may get is up to [`Transfers::max_length`](crate::Transfers::max_length) instead.
+    ///
+    /// ## Default
+    /// The default is set to 128KiB, twice the default chunk size
+    pub max_packet_length: usize,
+
+    /// Path of the socket file to connect to or create
+    ///
+    /// ## Note
+    /// The server removes the file again when it stops listening.
+    /// One left behind by a server that crashed is replaced, as long as nobody listens on it anymore.
+    pub path: PathBuf,
+
+    /// Collect packets and write them at once, instead of one by one
+    ///
+    /// ## Default
+    /// The default is `None`, every packet is written on its own
+    pub batching: Option<Batching>,
+}
+
+impl NetworkSettings {
+    /// Create a new instance of [`NetworkSettings`]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            max_packet_length: 128 * 1024,
+            path: path.into(),
+            batching: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_channel::unbounded, network_packet::PacketKind, Channel};
+    use futures_lite::future;
+
+    /// A socket path no other test uses
+    fn path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("eventwork-{}-{}.sock", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn packet(data: Vec<u8>) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Name("test".to_owned()),
+            data: data.into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    /// Connects to a server listening on `settings.path` and sends a packet to it
+    fn round_trip(settings: &NetworkSettings) {
+        let (connections_tx, connections_rx) = unbounded();
+        let (errors_tx, errors_rx) = unbounded();
+
+        let test = async {
+            let client = UnixStream::connect(&settings.path)
+                .await
+                .expect("server is listening");
+            let accepted = connections_rx
+                .recv()
+                .await
+                .expect("server accepted the client");
+
+            let (messages_tx, messages_rx) = unbounded();
+            let (received_tx, received_rx) = unbounded();
+            messages_tx
+                .try_send(packet(vec![1, 2, 3]))
+                .expect("channel is open");
+            messages_tx.close();
+
+            let ((), reason) = future::zip(
+                UnixClientProvider::send_loop(client, messages_rx, settings.clone()),
+                UnixServerProvider::recv_loop(accepted, received_tx, settings.clone()),
+            )
+            .await;
+
+            let received = received_rx.try_recv().expect("server got the packet");
+            assert_eq!(received.encode(), packet(vec![1, 2, 3]).encode());
+            assert_eq!(reason, DisconnectReason::Eof);
+        };
+        // The server binds on its first poll, before the client tries to connect
+        future::block_on(future::or(
+            async {
+                UnixServerProvider::accept_loop(settings.clone(), connections_tx, errors_tx).await;
+                panic!("server stopped: {:?}", errors_rx.try_recv());
+            },
+            test,
+        ));
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let settings = NetworkSettings::new(path("round-trip"));
+        round_trip(&settings);
+
+        assert!(!settings.path.exists());
+    }
+
+    #[test]
+    fn reclaims_stale_socket_files() {
+        let settings = NetworkSettings::new(path("stale"));
+        // Dropping the listener leaves its file behind, like a crashed server would
+        drop(std::os::unix::net::UnixListener::bind(&settings.path).expect("path is free"));
+        assert!(settings.path.exists());
+
+        round_trip(&settings);
+    }
+
+    #[test]
+    fn leaves_live_sockets_alone() {
+        let settings = NetworkSettings::new(path("live"));
+        let _listener =
+            std::os::unix::net::UnixListener::bind(&settings.path).expect("path is free");
+        let (connections_tx, _connections_rx) = unbounded();
+        let (errors_tx, errors_rx) = unbounded();
+
+        future::block_on(UnixServerProvider::accept_loop(
+            settings.clone(),
+            connections_tx,
+            errors_tx,
+        ));
+
+        match errors_rx.try_recv().expect("server reports the error") {
+            NetworkError::Listen(err) => assert_eq!(err.kind(), io::ErrorKind::AddrInUse),
+            err => panic!("unexpected error: {}", err),
+        }
+        assert!(settings.path.exists());
+        let _ = std::fs::remove_file(&settings.path);
+    }
+}
@@ -0,0 +1,465 @@
+//! The client connects with [`async_tungstenite`] over a plain tcp stream, so it only runs natively.
+//! Browsers can still talk to [`WsServerProvider`](crate::websocket::WsServerProvider) with
+//! their own `WebSocket`, sending every packet as a binary message in the format described on
+//! [`NetworkPacket`].
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use crate::{
+    async_channel::{Receiver, Sender},
+    async_trait,
+    client::NetworkClientProvider,
+    error::NetworkError,
+    server::NetworkServerProvider,
+    ClientNetworkEvent, DisconnectReason, NetworkPacket,
+};
+use async_io::Timer;
+use async_net::{TcpListener, TcpStream};
+use async_tungstenite::{
+    tungstenite::{protocol::WebSocketConfig, Error as WsError, Message},
+    WebSocketReceiver, WebSocketSender, WebSocketStream,
+};
+use bevy::log::{debug, error, info, trace};
+use futures_lite::{future, StreamExt};
+use futures_util::stream::FuturesUnordered;
+
+#[derive(Default, Debug)]
+/// Provides a websocket listener for eventwork.
+///
+/// Every [`NetworkPacket`] is sent as a single binary websocket message.
+pub struct WsServerProvider;
+
+#[async_trait]
+impl NetworkServerProvider for WsServerProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = WebSocketStream<TcpStream>;
+
+    type ReadHalf = WebSocketReceiver<TcpStream>;
+
+    type WriteHalf = WebSocketSender<TcpStream>;
+
+    async fn accept_loop(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<NetworkError>,
+    ) {
+        let listener = match TcpListener::bind(network_settings.addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                if let Err(err) = errors.send(NetworkError::Listen(err)).await {
+                    error!("Could not send listen error: {}", err);
+                }
+                return;
+            }
+        };
+
+        enum Accepted {
+            Stream(io::Result<TcpStream>),
+            Upgraded(io::Result<Box<WebSocketStream<TcpStream>>>),
+        }
+
+        // Upgrades run concurrently, so a slow client can't hold up everyone else,
+        // and they time out, so one that never finishes doesn't stick around forever
+        let mut upgrades = FuturesUnordered::new();
+        loop {
+            let accepted = future::or(
+                async { Accepted::Stream(listener.accept().await.map(|(socket, _addr)| socket)) },
+                async {
+                    match upgrades.next().await {
+                        Some(upgraded) => Accepted::Upgraded(upgraded),
+                        None => future::pending().await,
+                    }
+                },
+            )
+            .await;
+
+            let error = match accepted {
+                Accepted::Stream(Ok(socket)) => {
+                    let config = network_settings.websocket_config();
+                    let timeout = network_settings.handshake_timeout;
+                    upgrades.push(future::or(
+                        async move {
+                            async_tungstenite::accept_async_with_config(socket, Some(config))
+                                .await
+                                .map(Box::new)
+                                .map_err(io::Error::other)
+                        },
+                        async move {
+                            Timer::after(timeout).await;
+                            Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "The websocket upgrade took too long",
+                            ))
+                        },
+                    ));
+                    continue;
+                }
+                Accepted::Upgraded(Ok(websocket)) => {
+                    if let Err(err) = new_connections.send(*websocket).await {
+                        error!("Could not send listen error: {}", err);
+                        return;
+                    }
+                    info!("New Connection Made!");
+                    continue;
+                }
+                Accepted::Stream(Err(error)) | Accepted::Upgraded(Err(error)) => error,
+            };
+
+            if let Err(err) = errors.send(NetworkError::Accept(error)).await {
+                error!("Could not send listen error: {}", err);
+                return;
+            };
+        }
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        let (write_half, read_half) = combined.split();
+        (read_half, write_half)
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().peer_addr().ok()
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().local_addr().ok()
+    }
+}
+
+#[derive(Default, Debug)]
+/// Provides a websocket stream for eventwork.
+pub struct WsClientProvider;
+
+#[async_trait]
+impl NetworkClientProvider for WsClientProvider {
+    type NetworkSettings = NetworkSettings;
+
+    type Socket = WebSocketStream<TcpStream>;
+
+    type ReadHalf = WebSocketReceiver<TcpStream>;
+
+    type WriteHalf = WebSocketSender<TcpStream>;
+
+    async fn connect_task(
+        network_settings: Self::NetworkSettings,
+        new_connections: Sender<Self::Socket>,
+        errors: Sender<ClientNetworkEvent>,
+    ) {
+        info!("Beginning connection");
+        let websocket = match connect(&network_settings).await {
+            Ok(websocket) => websocket,
+            Err(error) => {
+                match errors
+                    .send(ClientNetworkEvent::Error(NetworkError::Connection(error)))
+                    .await
+                {
+                    Ok(_) => (),
+                    Err(err) => {
+                        error!("Could not send error event: {}", err);
+                    }
+                }
+
+                return;
+            }
+        };
+
+        info!("Connected!");
+
+        match new_connections.send(websocket).await {
+            Ok(_) => (),
+            Err(err) => {
+                error!("Could not initiate connection: {}", err);
+            }
+        }
+
+        debug!("Connected to: {}", network_settings.url());
+    }
+
+    async fn recv_loop(
+        read_half: Self::ReadHalf,
+        messages: Sender<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) -> DisconnectReason {
+        recv_loop(read_half, messages, settings).await
+    }
+
+    async fn send_loop(
+        write_half: Self::WriteHalf,
+        messages: Receiver<NetworkPacket>,
+        settings: Self::NetworkSettings,
+    ) {
+        send_loop(write_half, messages, settings).await
+    }
+
+    fn split(combined: Self::Socket) -> (Self::ReadHalf, Self::WriteHalf) {
+        let (write_half, read_half) = combined.split();
+        (read_half, write_half)
+    }
+
+    fn peer_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().peer_addr().ok()
+    }
+
+    fn local_addr(socket: &Self::Socket) -> Option<SocketAddr> {
+        socket.get_ref().local_addr().ok()
+    }
+}
+
+async fn connect(settings: &NetworkSettings) -> io::Result<WebSocketStream<TcpStream>> {
+    let stream = TcpStream::connect(settings.addr).await?;
+
+    let (websocket, _response) = async_tungstenite::client_async_with_config(
+        settings.url(),
+        stream,
+        Some(settings.websocket_config()),
+    )
+    .await
+    .map_err(io::Error::other)?;
+
+    Ok(websocket)
+}
+
+async fn recv_loop(
+    mut read_half: WebSocketReceiver<TcpStream>,
+    messages: Sender<NetworkPacket>,
+    _settings: NetworkSettings,
+) -> DisconnectReason {
+    while let Some(message) = read_half.next().await {
+        let data = match message {
+            Ok(Message::Binary(data)) => data,
+            Ok(Message::Close(_)) => {
+                info!("Peer disconnected");
+                return DisconnectReason::Eof;
+            }
+            // Pings are answered by tungstenite itself
+            Ok(_) => continue,
+            Err(WsError::Capacity(err)) => {
+                error!("Received too large packet: {}", err);
+                return DisconnectReason::Oversize;
+            }
+            Err(err) => {
+                error!("Encountered error while reading websocket message: {}", err);
+                return DisconnectReason::Eof;
+            }
+        };
+        debug!("Received new message of size: {}", data.len());
+
+        let packet = match NetworkPacket::decode(data) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to decode network packet from: {}", err);
+                return DisconnectReason::DecodeError;
+            }
+        };
+
+        if messages.send(packet).await.is_err() {
+            error!("Failed to send decoded message to eventwork");
+            return DisconnectReason::Eof;
+        }
+        trace!("Message deserialized and sent to eventwork");
+    }
+
+    info!("Peer disconnected");
+    DisconnectReason::Eof
+}
+
+async fn send_loop(
+    mut write_half: WebSocketSender<TcpStream>,
+    messages: Receiver<NetworkPacket>,
+    _settings: NetworkSettings,
+) {
+    while let Ok(message) = messages.recv().await {
+        let encoded = message.encode();
+        debug!("Sending a new message of size: {}", encoded.len());
+
+        if let Err(err) = write_half.send(Message::binary(encoded)).await {
+            error!("Could not send packet: {:?}: {}", message, err);
+            break;
+        }
+
+        trace!("Succesfully written all!");
+    }
+
+    let _ = write_half.close(None).await;
+}
+
+#[derive(Clone, Debug)]
+/// Settings to configure the network, both client and server
+pub struct NetworkSettings {
+    /// Maximum packet size in bytes. If a client ever exceeds this size, they will be disconnected
+    ///
+    /// Larger messages can be sent in chunks instead, see [`Transfers`](crate::Transfers).
+    /// Every chunk is a packet of its own, so [`Transfers::chunk_size`](crate::Transfers::chunk_size)
+    /// has to stay a few bytes below this, to leave room for the headers. How large a whole
+    /// transfer may get is up to [`Transfers::max_length`](crate::Transfers::max_length) instead.
+    ///
+    /// ## Default
+    /// The default is set to 128KiB, twice the default chunk size
+    pub max_packet_length: usize,
+
+    /// Address to connect to or port to open
+    pub addr: SocketAddr,
+
+    /// The path requested by the client during the http upgrade
+    ///
+    /// ## Default
+    /// The default is set to `/`
+    pub path: String,
+
+    /// How long a client may take to finish the http upgrade before it is dropped
+    ///
+    /// ## Default
+    /// The default is set to 10 seconds
+    pub handshake_timeout: Duration,
+}
+
+impl NetworkSettings {
+    /// Create a new instance of [`NetworkSettings`]
+    pub fn new(addr: impl Into<SocketAddr>) -> Self {
+        Self {
+            max_packet_length: 128 * 1024,
+            addr: addr.into(),
+            path: String::from("/"),
+            handshake_timeout: Duration::from_secs(10),
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("ws://{}{}", self.addr, self.path)
+    }
+
+    fn websocket_config(&self) -> WebSocketConfig {
+        WebSocketConfig::default()
+            .max_message_size(Some(self.max_packet_length))
+            .max_frame_size(Some(self.max_packet_length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{async_channel::unbounded, network_packet::PacketKind, Channel};
+
+    /// Settings for a server on a free port of the loopback interface
+    fn settings() -> NetworkSettings {
+        let addr = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|listener| listener.local_addr())
+            .expect("loopback is available");
+        NetworkSettings::new(addr)
+    }
+
+    /// Retries until the server started in the same future is listening
+    async fn retry<T, F: future::Future<Output = io::Result<T>>>(attempt: impl Fn() -> F) -> T {
+        for _ in 0..50 {
+            if let Ok(connected) = attempt().await {
+                return connected;
+            }
+            Timer::after(Duration::from_millis(10)).await;
+        }
+        panic!("server never started listening");
+    }
+
+    fn packet(data: Vec<u8>) -> NetworkPacket {
+        NetworkPacket {
+            kind: PacketKind::Name("test".to_owned()),
+            data: data.into(),
+            channel: Channel::DEFAULT,
+            compressed: false,
+        }
+    }
+
+    #[test]
+    fn packets_round_trip_over_localhost() {
+        let settings = settings();
+        let (connections_tx, connections_rx) = unbounded();
+        let (errors_tx, _errors_rx) = unbounded();
+
+        let test = async {
+            let client = retry(|| connect(&settings)).await;
+            let accepted = connections_rx
+                .recv()
+                .await
+                .expect("server upgraded the connection");
+            let (server_read, server_write) = WsServerProvider::split(accepted);
+            let (client_read, client_write) = WsClientProvider::split(client);
+
+            // Server to client and back again
+            let (outgoing_tx, outgoing_rx) = unbounded();
+            let (received_tx, received_rx) = unbounded();
+            let (echo_tx, echo_rx) = unbounded();
+            let (returned_tx, returned_rx) = unbounded();
+            outgoing_tx
+                .try_send(packet(vec![1, 2, 3]))
+                .expect("channel is open");
+
+            let run = future::zip(
+                future::zip(
+                    send_loop(server_write, outgoing_rx, settings.clone()),
+                    recv_loop(client_read, received_tx, settings.clone()),
+                ),
+                future::zip(
+                    send_loop(client_write, echo_rx, settings.clone()),
+                    recv_loop(server_read, returned_tx, settings.clone()),
+                ),
+            );
+            let check = async {
+                let received = received_rx.recv().await.expect("client got the packet");
+                assert_eq!(received.encode(), packet(vec![1, 2, 3]).encode());
+                echo_tx.try_send(received).expect("channel is open");
+
+                let returned = returned_rx.recv().await.expect("server got the echo");
+                assert_eq!(returned.encode(), packet(vec![1, 2, 3]).encode());
+
+                // Closing the client's side is seen by the server
+                echo_tx.close();
+                outgoing_tx.close();
+            };
+            let (((), client_reason), ((), server_reason)) = future::zip(run, check).await.0;
+            assert_eq!(client_reason, DisconnectReason::Eof);
+            assert_eq!(server_reason, DisconnectReason::Eof);
+        };
+        future::block_on(future::or(test, async {
+            WsServerProvider::accept_loop(settings.clone(), connections_tx, errors_tx).await;
+            panic!("server stopped");
+        }));
+    }
+
+    #[test]
+    fn drops_clients_that_never_upgrade() {
+        let settings = NetworkSettings {
+            handshake_timeout: Duration::from_millis(100),
+            ..settings()
+        };
+        let (connections_tx, _connections_rx) = unbounded();
+        let (errors_tx, errors_rx) = unbounded();
+
+        let test = async {
+            let _stream = retry(|| TcpStream::connect(settings.addr)).await;
+            match errors_rx.recv().await.expect("server reports the timeout") {
+                NetworkError::Accept(err) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+                err => panic!("unexpected error: {}", err),
+            }
+        };
+        future::block_on(future::or(test, async {
+            WsServerProvider::accept_loop(settings.clone(), connections_tx, errors_tx).await;
+            panic!("server stopped");
+        }));
+    }
+}